@@ -5,11 +5,15 @@ use mwf::decorator;
 
 /// This is what our page should be formatted like.
 /// The `{{middle}}` part is a delimiter to tell `decorator::Surround` where it
-/// needs to split the string to insert the view's content.
+/// needs to split the string to insert the view's content, and
+/// `{{meta.title}}` is replaced by the title the markdown decorator found.
 const PAGE_FORMAT: &'static str =
 r#"
 <!DOCTYPE html>
 <html>
+<head>
+  <title>{{meta.title}}</title>
+</head>
 <body>
   <div>I'm a header!</div>
   <article> {{middle}} </article>
@@ -26,7 +30,7 @@ impl Decorator for Screaming
     {
         View {
             content: view.content.to_uppercase(),
-            mime: view.mime,
+            ..view
         }
     }
 }
@@ -53,7 +57,7 @@ impl DecoratorExample
     {
         DecoratorExample {
             screaming: Screaming,
-            markdown: decorator::Markdown,
+            markdown: decorator::Markdown::new(),
             page: decorator::Surround::from(PAGE_FORMAT),
         }
     }
@@ -0,0 +1,55 @@
+extern crate mwf;
+extern crate hyper;
+
+use mwf::{ServerBuilder, DirectoryListing};
+use mwf::{Resolver, RouteMap, ResolveParams};
+use mwf::decorator;
+
+/// A minimal theme for the listing pages.
+const PAGE_FORMAT: &str =
+r#"<!DOCTYPE html>
+<html>
+<head>
+  <meta charset="utf-8">
+  <style>
+    body { font-family: sans-serif; }
+    td { padding: 0 1em; }
+  </style>
+</head>
+<body>
+{{middle}}
+</body>
+</html>
+"#;
+
+/// Accepts every GET request, so that the listing can decide what to do with
+/// the path on its own.
+struct AnyPath;
+impl Resolver for AnyPath
+{
+    fn resolve(&self, params: &ResolveParams) -> Option<RouteMap>
+    {
+        use hyper::Method;
+
+        if params.method != Method::Get {
+            return None;
+        }
+
+        Some(RouteMap::new())
+    }
+}
+
+fn main()
+{
+    // Serves the current working directory, the same as the file_browser
+    // example, except that the listing pages are generated for us.
+    // Try adding `?sort=size&order=desc` to the URL, or requesting a page
+    // with `Accept: application/json`.
+    let listing = DirectoryListing::new(".")
+        .decorator(decorator::Surround::from(PAGE_FORMAT));
+
+    ServerBuilder::new()
+        .resolver(|_, _| Box::new(AnyPath))
+        .bind("*", listing)
+        .start();
+}
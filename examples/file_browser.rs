@@ -4,10 +4,21 @@ extern crate hyper;
 use mwf::{ServerBuilder};
 use mwf::{View, Result, RequestHandler};
 use mwf::{Resolver, RouteMap, ResolveParams};
+use mwf::decorator::{Template, Context, Highlight};
 
-/// Displays the resolved file. If the file is a directory, then its contents
-/// will be displayed in a list of links.
-struct Browser;
+/// The page a directory's contents are listed on.
+const LISTING: &str = "<html><body>\
+    {{#each entries}}<a href='{{link}}'> {{name}} </a><br/>{{/each}}\
+    </body></html>";
+
+/// Displays the resolved file, highlighting it if it's source code. If the
+/// file is a directory, then its contents will be displayed in a list of
+/// links.
+struct Browser
+{
+    listing: Template,
+    highlight: Highlight,
+}
 
 /// Resolves any path so long as a file with the same name exists in the
 /// current working directory.
@@ -20,7 +31,10 @@ fn main()
 {
     ServerBuilder::new()
         .resolver(|_, _| Box::new(FileResolver))
-        .bind("*", Browser)
+        .bind("*", Browser {
+            listing: Template::new(LISTING).unwrap(),
+            highlight: Highlight::new().inline(true),
+        })
         .start();
 }
 
@@ -42,14 +56,14 @@ impl RequestHandler for Browser
         };
 
         // if it's a file, then it's really easy:
-        // just look at its contents
+        // just look at its contents (in colour, if it's code)
         if file.is_file() {
-            return View::file(file);
+            return Ok(View::file(file)?.apply(&self.highlight));
         }
 
         // otherwise, it's a directory. So we have to list all of the contents
         // of the directory (with links!)
-        let mut contents = Vec::new();
+        let mut entries = Vec::new();
         for entry in file.read_dir().unwrap() {
 
             // Make sure that the entry was alright
@@ -77,19 +91,13 @@ impl RequestHandler for Browser
                 link = format!("/{}/{}", path, entry);
             }
 
-            // create the actual link for the entry
-            let link = format!("<a href='{}'> {} </a>", link, entry);
-            contents.push(link);
+            // the template takes care of escaping both of these
+            entries.push(Context::new().set("link", link).set("name", entry));
         }
 
-        // try to make this part into a decorator!
-
-        // build a valid HTML page from the list of links
-        let contents = contents.join("<br/>");
-        let contents = format!("<html><body>{}</body></html>", contents);
-
-        // manually create a view that shows the directory listing as html
-        let mut view = View::raw(contents);
+        // render the listing page around the links
+        let context = Context::new().set("entries", entries);
+        let mut view = View::raw(self.listing.render(&context));
         view.mime = "text/html".parse().unwrap();
 
         Ok(view)
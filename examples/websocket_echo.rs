@@ -0,0 +1,68 @@
+extern crate mwf;
+
+use mwf::{ServerBuilder, RequestHandler, RouteMap, View};
+use mwf::{WebSocketHandler, WebSocket, Message};
+
+/// A tiny page which connects to the echo socket and shows everything it
+/// sends back.
+const PAGE: &str =
+r#"<!DOCTYPE html>
+<html>
+<body>
+  <input id="text" autofocus> <button id="send">Send</button>
+  <pre id="log"></pre>
+  <script>
+    var socket = new WebSocket("ws://" + location.host + "/echo");
+    var log = document.getElementById("log");
+    var text = document.getElementById("text");
+    socket.onmessage = function(e) { log.textContent += e.data + "\n"; };
+    document.getElementById("send").onclick = function() {
+      socket.send(text.value);
+      text.value = "";
+    };
+  </script>
+</body>
+</html>
+"#;
+
+/// Serves the page with the WebSocket client on it.
+struct Page;
+impl RequestHandler for Page
+{
+    fn handle(&self, _route_map: RouteMap) -> mwf::Result<View>
+    {
+        let mut view = View::raw(PAGE);
+        view.mime = "text/html".parse().unwrap();
+        Ok(view)
+    }
+}
+
+/// Sends every text or binary message right back to the client.
+struct Echo;
+impl WebSocketHandler for Echo
+{
+    fn connect(&self, mut socket: WebSocket, _route_map: RouteMap)
+    {
+        // recv blocks until the next message, and returns None once the
+        // connection has been closed
+        while let Some(message) = socket.recv() {
+            let reply = match message {
+                Message::Text(text) => Message::Text(text),
+                Message::Binary(data) => Message::Binary(data),
+                _ => continue,
+            };
+
+            if socket.send(reply).is_err() {
+                break;
+            }
+        }
+    }
+}
+
+fn main()
+{
+    ServerBuilder::new()
+        .bind("/", Page)
+        .websocket("/echo", Echo)
+        .start();
+}
@@ -0,0 +1,711 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use hyper::header::ContentLength;
+use hyper::server::{Request, Response};
+
+use escape::escape_json;
+
+/// The format each request is written to the access log in.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LogFormat
+{
+    /// The Common Log Format:
+    /// `host - - [time] "request line" status size`
+    Common,
+
+    /// The Common Log Format followed by the quoted `Referer` and
+    /// `User-Agent` headers.
+    Combined,
+
+    /// One JSON object per line, which also includes the request's ID (as
+    /// sent in the `X-Request-Id` header) and how long the response took to
+    /// produce in milliseconds.
+    Json,
+}
+
+/// Where the lines of an access log are written.
+///
+/// Any closure taking the formatted line is a sink too, which makes it easy
+/// to hand lines off to some other logging system.
+pub trait LogSink
+    where Self: Send + Sync
+{
+    /// Writes a single `line` to the log. The line doesn't end in a newline.
+    fn write(&self, line: &str);
+}
+
+/// Writes the access log of a server, one line per request.
+///
+/// Requests are logged once their response is ready to be sent. A streamed
+/// body (a [BodyStream], or a file served from disk) is sent after that, so
+/// its size is logged as unknown, and the time it took to send isn't part of
+/// the duration.
+///
+/// ```rust,no_run
+/// use mwf::{ServerBuilder, AccessLog, LogFormat, FileSink, Rotation};
+///
+/// let file = FileSink::new("logs/access.log")
+///     .unwrap()
+///     .rotate(Rotation::Daily)
+///     .keep(7);
+///
+/// ServerBuilder::new()
+///     .access_log(AccessLog::new(LogFormat::Combined).sink(file))
+///     .start();
+/// ```
+pub struct AccessLog
+{
+    format: LogFormat,
+    sink: Box<LogSink>,
+}
+
+/// Writes access logs to standard output.
+pub struct StdoutSink;
+
+/// Writes access logs through the `log` crate, at the info level with the
+/// `mwf::access` target, so they end up wherever the rest of the
+/// application's logs go.
+pub struct FacadeSink;
+
+/// Writes access logs to a file, which may be rotated.
+pub struct FileSink
+{
+    path: PathBuf,
+    rotation: Rotation,
+    keep: usize,
+    state: Mutex<FileState>,
+}
+
+/// When a [FileSink] starts writing to a new file.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Rotation
+{
+    /// The same file is written to forever.
+    Never,
+
+    /// Once the file reaches the given size in bytes, it's renamed to
+    /// `<path>.1`, moving any older files along to `<path>.2` and so on.
+    Size(u64),
+
+    /// At the first request of each (UTC) day, the file is renamed to
+    /// `<path>.<yyyy-mm-dd>` after the day it was written, deleting the
+    /// oldest of those files beyond as many as are kept.
+    Daily,
+}
+
+/// The file a [FileSink] is currently writing to.
+struct FileState
+{
+    file: File,
+    size: u64,
+    day: u64,
+}
+
+/// The details of a single request which are logged.
+pub(crate) struct Entry
+{
+    time: SystemTime,
+    remote: Option<SocketAddr>,
+    request_id: String,
+    request_line: String,
+    method: String,
+    path: String,
+    version: String,
+    referer: Option<String>,
+    user_agent: Option<String>,
+    status: u16,
+    size: Option<u64>,
+    duration: Duration,
+}
+
+//
+// Implementation
+//
+
+impl AccessLog
+{
+    /// Creates a log in the given `format`, which is written to standard
+    /// output until another sink is given.
+    pub fn new(format: LogFormat) -> Self
+    {
+        AccessLog {
+            format,
+            sink: Box::new(StdoutSink),
+        }
+    }
+
+    /// Changes where the log is written to.
+    pub fn sink<S: 'static + LogSink>(mut self, sink: S) -> Self
+    {
+        self.sink = Box::new(sink);
+        self
+    }
+
+    /// Writes the `entry` to the log.
+    pub(crate) fn record(&self, entry: &Entry)
+    {
+        self.sink.write(&entry.format(self.format));
+    }
+}
+
+impl<F> LogSink for F
+    where F: Fn(&str) + Send + Sync
+{
+    fn write(&self, line: &str)
+    {
+        self(line)
+    }
+}
+
+impl LogSink for StdoutSink
+{
+    fn write(&self, line: &str)
+    {
+        println!("{}", line);
+    }
+}
+
+impl LogSink for FacadeSink
+{
+    fn write(&self, line: &str)
+    {
+        info!(target: "mwf::access", "{}", line);
+    }
+}
+
+impl FileSink
+{
+    /// Opens the file at `path` to append the log to, creating it if it
+    /// doesn't exist yet. By default it's never rotated.
+    pub fn new<P: Into<PathBuf>>(path: P) -> io::Result<Self>
+    {
+        let path = path.into();
+        let state = FileState::open(&path)?;
+
+        Ok(FileSink {
+            path,
+            rotation: Rotation::Never,
+            keep: 5,
+            state: Mutex::new(state),
+        })
+    }
+
+    /// Changes when the file is rotated.
+    pub fn rotate(mut self, rotation: Rotation) -> Self
+    {
+        self.rotation = rotation;
+        self
+    }
+
+    /// Changes how many rotated files are kept, which is 5 by default.
+    /// Older files are deleted.
+    pub fn keep(mut self, count: usize) -> Self
+    {
+        self.keep = count;
+        self
+    }
+
+    /// Rotates the file if it's due to be, given that a `line` of `length`
+    /// bytes is about to be written `today`.
+    fn rotate_if_needed(&self, state: &mut FileState, length: u64, today: u64)
+        -> io::Result<()>
+    {
+        match self.rotation {
+            Rotation::Never => return Ok(()),
+
+            Rotation::Size(max) => {
+                if state.size == 0 || state.size + length <= max {
+                    return Ok(());
+                }
+
+                for index in (1..self.keep).rev() {
+                    let from = numbered(&self.path, index);
+                    if from.exists() {
+                        fs::rename(from, numbered(&self.path, index + 1))?;
+                    }
+                }
+
+                if self.keep == 0 {
+                    fs::remove_file(&self.path)?;
+                } else {
+                    fs::rename(&self.path, numbered(&self.path, 1))?;
+                }
+            },
+
+            Rotation::Daily => {
+                if state.day == today || state.size == 0 {
+                    state.day = today;
+                    return Ok(());
+                }
+
+                let (year, month, day) = civil_date(state.day);
+                let mut name = self.path.clone().into_os_string();
+                name.push(format!(".{:04}-{:02}-{:02}", year, month, day));
+                fs::rename(&self.path, name)?;
+                self.prune_daily()?;
+            },
+        }
+
+        *state = FileState::open(&self.path)?;
+        state.day = today;
+        Ok(())
+    }
+
+    /// Deletes the oldest files rotated by day, leaving only as many as are
+    /// kept.
+    fn prune_daily(&self) -> io::Result<()>
+    {
+        let name = match self.path.file_name().and_then(|it| it.to_str()) {
+            Some(name) => format!("{}.", name),
+            None => return Ok(()),
+        };
+        let dir = match self.path.parent() {
+            Some(dir) if dir != Path::new("") => dir,
+            _ => Path::new("."),
+        };
+
+        let mut rotated = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            let dated = path.file_name()
+                .and_then(|it| it.to_str())
+                .and_then(|it| it.strip_prefix(name.as_str()))
+                .is_some_and(is_date);
+
+            if dated {
+                rotated.push(path);
+            }
+        }
+
+        // the dates sort the same way as the days they're for
+        rotated.sort();
+        let old = rotated.len().saturating_sub(self.keep);
+        for path in &rotated[..old] {
+            fs::remove_file(path)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl LogSink for FileSink
+{
+    fn write(&self, line: &str)
+    {
+        let line = format!("{}\n", line);
+        let today = days_since_epoch(SystemTime::now());
+        let mut state = self.state.lock().unwrap();
+
+        // logging must never take the server down, so if the log can't be
+        // written there's nothing left to do but complain
+        let result = self.rotate_if_needed(&mut state, line.len() as u64, today)
+            .and_then(|_| state.file.write_all(line.as_bytes()));
+
+        match result {
+            Ok(()) => state.size += line.len() as u64,
+            Err(error) => {
+                error!("Couldn't write to {}: {}", self.path.display(), error)
+            },
+        }
+    }
+}
+
+impl FileState
+{
+    fn open(path: &Path) -> io::Result<Self>
+    {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let metadata = file.metadata()?;
+
+        let modified = metadata.modified().unwrap_or_else(|_| SystemTime::now());
+
+        Ok(FileState {
+            file,
+            size: metadata.len(),
+            day: days_since_epoch(modified),
+        })
+    }
+}
+
+/// Checks if the `text` is a date in the `yyyy-mm-dd` format.
+fn is_date(text: &str) -> bool
+{
+    text.len() == 10 && text.bytes().enumerate().all(|(i, byte)| {
+        match i {
+            4 | 7 => byte == b'-',
+            _ => byte.is_ascii_digit(),
+        }
+    })
+}
+
+/// The path of the rotated log file with the given `index`.
+fn numbered(path: &Path, index: usize) -> PathBuf
+{
+    let mut name = path.to_path_buf().into_os_string();
+    name.push(format!(".{}", index));
+    name.into()
+}
+
+impl Entry
+{
+    /// Starts an entry for the `request` with the given ID, from the client
+    /// at `remote`.
+    pub fn new(
+        request: &Request,
+        request_id: &str,
+        remote: Option<SocketAddr>
+    ) -> Self
+    {
+        let header = |name: &str| {
+            request.headers().get_raw(name)
+                .and_then(|it| it.one())
+                .map(|it| String::from_utf8_lossy(it).into_owned())
+        };
+
+        let path = request.uri().to_string();
+        let method = request.method().to_string();
+        let version = request.version().to_string();
+
+        Entry {
+            time: SystemTime::now(),
+            remote,
+            request_id: request_id.to_string(),
+            request_line: format!("{} {} {}", method, path, version),
+            method,
+            path,
+            version,
+            referer: header("Referer"),
+            user_agent: header("User-Agent"),
+            status: 0,
+            size: None,
+            duration: Duration::from_secs(0),
+        }
+    }
+
+    /// Fills in the details of the `response`, which took `duration` to
+    /// produce. The size is only known if the response has a length, which
+    /// streamed responses don't.
+    pub fn finish(mut self, response: &Response, duration: Duration) -> Self
+    {
+        self.status = response.status().as_u16();
+        self.size = response.headers().get::<ContentLength>().map(|it| it.0);
+        self.duration = duration;
+        self
+    }
+
+    /// Formats this entry as a single line in the given `format`.
+    fn format(&self, format: LogFormat) -> String
+    {
+        if format == LogFormat::Json {
+            return self.to_json();
+        }
+
+        let host = self.remote
+            .map(|it| it.ip().to_string())
+            .unwrap_or_else(|| "-".into());
+        let size = self.size
+            .map(|it| it.to_string())
+            .unwrap_or_else(|| "-".into());
+
+        let mut line = format!(
+            "{} - - [{}] \"{}\" {} {}",
+            host,
+            clf_time(self.time),
+            escape_quoted(&self.request_line),
+            self.status,
+            size
+        );
+
+        if format == LogFormat::Combined {
+            let quoted = |it: &Option<String>| match *it {
+                None => "\"-\"".to_string(),
+                Some(ref it) => format!("\"{}\"", escape_quoted(it)),
+            };
+
+            line.push_str(&format!(
+                " {} {}",
+                quoted(&self.referer),
+                quoted(&self.user_agent)
+            ));
+        }
+
+        line
+    }
+
+    fn to_json(&self) -> String
+    {
+        let string = |it: &Option<String>| match *it {
+            None => "null".to_string(),
+            Some(ref it) => format!("\"{}\"", escape_json(it)),
+        };
+
+        let millis = self.duration.as_secs() as f64 * 1000.0
+            + self.duration.subsec_nanos() as f64 / 1_000_000.0;
+
+        format!(
+            "{{\"time\":\"{}\",\"request_id\":\"{}\",\"remote\":{},\
+             \"method\":\"{}\",\"path\":\"{}\",\"version\":\"{}\",\
+             \"status\":{},\"size\":{},\"duration_ms\":{:.3},\
+             \"referer\":{},\"user_agent\":{}}}",
+            iso_time(self.time),
+            escape_json(&self.request_id),
+            string(&self.remote.map(|it| it.ip().to_string())),
+            escape_json(&self.method),
+            escape_json(&self.path),
+            escape_json(&self.version),
+            self.status,
+            self.size.map(|it| it.to_string()).unwrap_or_else(|| "null".into()),
+            millis,
+            string(&self.referer),
+            string(&self.user_agent)
+        )
+    }
+}
+
+/// Escapes quotes and control characters in a quoted field of a log line,
+/// so that the line can't be forged by a client.
+fn escape_quoted(text: &str) -> String
+{
+    let mut output = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '"' => output.push_str("\\\""),
+            '\\' => output.push_str("\\\\"),
+            c if c.is_control() => {
+                output.push_str(&format!("\\x{:02x}", c as u32))
+            },
+            c => output.push(c),
+        }
+    }
+    output
+}
+
+/// The number of whole days between the epoch and the `time`.
+fn days_since_epoch(time: SystemTime) -> u64
+{
+    seconds_since_epoch(time) / 86400
+}
+
+fn seconds_since_epoch(time: SystemTime) -> u64
+{
+    time.duration_since(UNIX_EPOCH).map(|it| it.as_secs()).unwrap_or(0)
+}
+
+/// Converts a number of days since the epoch into a (year, month, day) date.
+fn civil_date(days: u64) -> (u64, u64, u64)
+{
+    // see http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719468;
+    let era = z / 146097;
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year, month, day)
+}
+
+/// Splits the `time` into its date and its hours, minutes and seconds.
+fn split_time(time: SystemTime) -> ((u64, u64, u64), u64, u64, u64)
+{
+    let seconds = seconds_since_epoch(time);
+    let of_day = seconds % 86400;
+
+    (
+        civil_date(seconds / 86400),
+        of_day / 3600,
+        of_day / 60 % 60,
+        of_day % 60,
+    )
+}
+
+/// Formats the `time` the way the Common Log Format does, in UTC.
+fn clf_time(time: SystemTime) -> String
+{
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun",
+        "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+
+    let ((year, month, day), hours, minutes, seconds) = split_time(time);
+    format!(
+        "{:02}/{}/{:04}:{:02}:{:02}:{:02} +0000",
+        day,
+        MONTHS[month as usize - 1],
+        year,
+        hours,
+        minutes,
+        seconds
+    )
+}
+
+/// Formats the `time` as an ISO 8601 timestamp in UTC.
+fn iso_time(time: SystemTime) -> String
+{
+    let ((year, month, day), hours, minutes, seconds) = split_time(time);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        hours,
+        minutes,
+        seconds
+    )
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+    use testing::TempDir;
+    use hyper::{Method, StatusCode};
+
+    /// Creates an entry for a typical request, made at a fixed time.
+    fn entry() -> Entry
+    {
+        let mut request = Request::new(
+            Method::Get,
+            "/docs/index.html?lang=en".parse().unwrap()
+        );
+        request.headers_mut().set_raw("User-Agent", "curl/7.0 \"quoted\"");
+
+        let mut response = Response::new().with_status(StatusCode::NotFound);
+        response.headers_mut().set(ContentLength(1234));
+
+        let remote = Some("10.0.0.1:5000".parse().unwrap());
+        let mut entry = Entry::new(&request, "c0ffee", remote)
+            .finish(&response, Duration::new(0, 1_500_000));
+
+        // Sun, 18 Oct 2026 14:45:31 GMT
+        entry.time = UNIX_EPOCH + Duration::from_secs(1792334731);
+        entry
+    }
+
+    /// Tests that dates are converted correctly, including leap days.
+    #[test]
+    fn dates()
+    {
+        assert_eq!((1970, 1, 1), civil_date(0));
+        assert_eq!((2000, 2, 29), civil_date(11016));
+        assert_eq!((2026, 10, 18), civil_date(20744));
+    }
+
+    /// Tests each of the formats.
+    #[test]
+    fn formats()
+    {
+        let entry = entry();
+
+        assert_eq!(
+            "10.0.0.1 - - [18/Oct/2026:14:45:31 +0000] \
+             \"GET /docs/index.html?lang=en HTTP/1.1\" 404 1234",
+            entry.format(LogFormat::Common)
+        );
+
+        assert_eq!(
+            "10.0.0.1 - - [18/Oct/2026:14:45:31 +0000] \
+             \"GET /docs/index.html?lang=en HTTP/1.1\" 404 1234 \
+             \"-\" \"curl/7.0 \\\"quoted\\\"\"",
+            entry.format(LogFormat::Combined)
+        );
+
+        assert_eq!(
+            "{\"time\":\"2026-10-18T14:45:31Z\",\"request_id\":\"c0ffee\",\
+             \"remote\":\"10.0.0.1\",\
+             \"method\":\"GET\",\"path\":\"/docs/index.html?lang=en\",\
+             \"version\":\"HTTP/1.1\",\"status\":404,\"size\":1234,\
+             \"duration_ms\":1.500,\"referer\":null,\
+             \"user_agent\":\"curl/7.0 \\\"quoted\\\"\"}",
+            entry.format(LogFormat::Json)
+        );
+    }
+
+    /// Tests that unknown clients and sizes are logged as dashes.
+    #[test]
+    fn unknown_fields()
+    {
+        let mut entry = entry();
+        entry.remote = None;
+        entry.size = None;
+
+        assert!(entry.format(LogFormat::Common).starts_with("- - - ["));
+        assert!(entry.format(LogFormat::Common).ends_with(" 404 -"));
+    }
+
+    /// Tests that files are rotated once they'd grow too big, keeping only
+    /// as many as asked.
+    #[test]
+    fn size_rotation()
+    {
+        let dir = TempDir::new("access-log");
+        let path = dir.join("access.log");
+
+        let sink = FileSink::new(&path).unwrap()
+            .rotate(Rotation::Size(10))
+            .keep(2);
+
+        for line in &["one", "two", "three", "four", "five", "six"] {
+            sink.write(line);
+        }
+
+        let read = |name: &str| fs::read_to_string(dir.join(name)).unwrap();
+        assert_eq!("six\n", read("access.log"));
+        assert_eq!("four\nfive\n", read("access.log.1"));
+        assert_eq!("three\n", read("access.log.2"));
+        assert!(!dir.join("access.log.3").exists());
+    }
+
+    /// Tests that files rotated by day are named after the day they were
+    /// written, and that only as many as asked are kept.
+    #[test]
+    fn daily_rotation()
+    {
+        let dir = TempDir::new("access-daily");
+        let path = dir.join("access.log");
+
+        for day in &["2019-12-30", "2019-12-31", "2020-01-01"] {
+            fs::write(dir.join(format!("access.log.{}", day)), "").unwrap();
+        }
+        fs::write(dir.join("access.log.1"), "").unwrap();
+        fs::write(dir.join("access.log.2020-01-01.gz"), "").unwrap();
+
+        let sink = FileSink::new(&path).unwrap()
+            .rotate(Rotation::Daily)
+            .keep(2);
+
+        // written on 2020-01-02, then rotated the day after
+        sink.write("one");
+        let mut state = sink.state.lock().unwrap();
+        state.day = 18263;
+        sink.rotate_if_needed(&mut state, 4, 18264).unwrap();
+        drop(state);
+        sink.write("two");
+
+        let mut names: Vec<String> = fs::read_dir(dir.path()).unwrap()
+            .map(|it| it.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        assert_eq!(
+            vec![
+                "access.log",
+                "access.log.1",
+                "access.log.2020-01-01",
+                "access.log.2020-01-01.gz",
+                "access.log.2020-01-02",
+            ],
+            names
+        );
+        let rotated = dir.join("access.log.2020-01-02");
+        assert_eq!("one\n", fs::read_to_string(rotated).unwrap());
+        assert_eq!("two\n", fs::read_to_string(&path).unwrap());
+    }
+}
@@ -1,18 +1,45 @@
+use std::io;
+use std::rc::Rc;
 use std::sync::Arc;
+use std::sync::mpsc;
 use std::net::SocketAddr;
+use std::thread;
+use std::time::Duration;
 
+use futures::{future, Future, Stream};
+use futures::sync::oneshot;
 use hyper::server::Http;
+use hyper::header::StrictTransportSecurity;
 use hyper::Method;
+use openssl::ssl::SslAcceptor;
+use tokio_core::net::TcpListener;
+use tokio_core::reactor::{Core, Handle, Timeout};
+use tokio_io::{AsyncRead, AsyncWrite};
 
 use routing::*;
 use server::*;
 use request_handler::RequestHandler;
 use resolution::Resolver;
+use access_log::AccessLog;
+use health::HealthChecks;
+use metrics::Metrics;
+use reporting::{ErrorReporter, LogReporter};
+use error::Result;
+use shutdown::{self, Drain, ServerHandle};
+use stream;
+use testing::TestClient;
+use tls::{self, TlsConfig};
+use websocket::WebSocketHandler;
+#[cfg(unix)]
+use unix::UnixSocket;
 
 /// The protocol to use for the server.
 pub enum Protocol
 {
     Http,
+
+    /// HTTP over TLS, using the certificates in the given configuration.
+    Https(TlsConfig),
 }
 
 /// The server building interface. This streamlines the entire process of
@@ -21,21 +48,90 @@ pub struct ServerBuilder
 {
     router: RouterBuilder,
     proto: Protocol,
-    addr: SocketAddr,
+    addrs: Vec<SocketAddr>,
+    #[cfg(unix)]
+    sockets: Vec<UnixSocket>,
+    redirect: Option<SocketAddr>,
+    hsts: Option<StrictTransportSecurity>,
+    access_log: Option<Arc<AccessLog>>,
+    reporter: Arc<ErrorReporter>,
+    abort_on_panic: bool,
+    metrics: Option<Arc<Metrics>>,
+    health: Option<Arc<HealthChecks>>,
+    shutdown_grace: Duration,
+    shutdown_timeout: Duration,
+    blocking_threads: Option<usize>,
+}
+
+/// The configuration of a server which is ready to be moved onto its own
+/// thread and started.
+struct Launch
+{
+    router: Arc<Router>,
+    acceptor: Option<SslAcceptor>,
+    hsts: Option<StrictTransportSecurity>,
+    addrs: Vec<SocketAddr>,
+    #[cfg(unix)]
+    sockets: Vec<UnixSocket>,
+    redirect: Option<SocketAddr>,
+    access_log: Option<Arc<AccessLog>>,
+    reporter: Arc<ErrorReporter>,
+    abort_on_panic: bool,
+    metrics: Option<Arc<Metrics>>,
+    health: Option<Arc<HealthChecks>>,
+    shutdown_grace: Duration,
+    shutdown_timeout: Duration,
+}
+
+/// Everything the listeners need to serve the connections they accept.
+struct Connections
+{
+    router: Arc<Router>,
+    acceptor: Option<SslAcceptor>,
+    hsts: Option<StrictTransportSecurity>,
+    access_log: Option<Arc<AccessLog>>,
+    reporter: Arc<ErrorReporter>,
+    abort_on_panic: bool,
+    metrics: Option<Arc<Metrics>>,
+    health: Option<Arc<HealthChecks>>,
+    http: Http,
+    handle: Handle,
+    drain: Rc<Drain>,
 }
 
+/// A listener accepting connections until it's dropped.
+type Listener = Box<Future<Item=(), Error=io::Error>>;
+
+/// How long a listener waits before accepting again after it fails to accept
+/// a connection, such as when the process has run out of file descriptors.
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
 impl ServerBuilder
 {
     /// Creates a new server build with the following defaults:
     /// * No routes set up
     /// * Served over HTTP
-    /// * bound to `127.0.0.1:8080`
+    /// * bound to `127.0.0.1:8080`, unless other addresses are given
+    /// * closing its listeners as soon as it's told to shut down
+    /// * waiting up to 30 seconds for requests to finish when shutting down
     pub fn new() -> Self
     {
         ServerBuilder {
             router: RouterBuilder::new(),
             proto: Protocol::Http,
-            addr: "127.0.0.1:8080".parse().unwrap(),
+            addrs: Vec::new(),
+            #[cfg(unix)]
+            sockets: Vec::new(),
+            redirect: None,
+            hsts: None,
+            access_log: None,
+            reporter: Arc::new(LogReporter),
+            abort_on_panic: false,
+            metrics: None,
+            health: None,
+            shutdown_grace: Duration::from_secs(0),
+            shutdown_timeout: Duration::from_secs(30),
+            blocking_threads: None,
         }
     }
 
@@ -75,10 +171,35 @@ impl ServerBuilder
         self
     }
 
-    /// Binds the server to listen to a new `address`.
+    /// Binds a new WebSocket `handler` to a given `route`. GET requests to the
+    /// route which ask to be upgraded to a WebSocket will be handed off to
+    /// the handler once the handshake is complete.
+    pub fn websocket<T: Into<String>, H: 'static>(
+        mut self,
+        route: T,
+        handler: H
+    ) -> Self
+        where H: WebSocketHandler
+    {
+        self.router.websocket(route, handler);
+        self
+    }
+
+    /// Binds the server to listen to a new `address`. This may be called
+    /// several times to listen on several addresses, such as both IPv4 and
+    /// IPv6, or more than one port.
     pub fn addr(mut self, address: SocketAddr) -> Self
     {
-        self.addr = address;
+        self.addrs.push(address);
+        self
+    }
+
+    /// Binds the server to listen on a Unix domain `socket` as well as, or
+    /// instead of, its addresses.
+    #[cfg(unix)]
+    pub fn unix<S: Into<UnixSocket>>(mut self, socket: S) -> Self
+    {
+        self.sockets.push(socket.into());
         self
     }
 
@@ -89,17 +210,779 @@ impl ServerBuilder
         self
     }
 
-    /// Starts the server with the current configuration.
+    /// Also listens for plain HTTP on the given `address`, and permanently
+    /// redirects every request made there to the same path and query on the
+    /// HTTPS server, on the port of its first TCP listener.
+    ///
+    /// The server has to be served over [Protocol::Https] on at least one
+    /// TCP address, or it fails to start.
+    pub fn redirect_http(mut self, address: SocketAddr) -> Self
+    {
+        self.redirect = Some(address);
+        self
+    }
+
+    /// Adds the given `Strict-Transport-Security` header to every response
+    /// served over HTTPS, which tells browsers to only ever use HTTPS for
+    /// this site. It's never sent over plain HTTP, where it would be ignored.
+    ///
+    /// ```rust,no_run
+    /// use mwf::{ServerBuilder, Protocol, TlsConfig};
+    /// use mwf::hyper::header::StrictTransportSecurity;
+    ///
+    /// let one_year = 365 * 24 * 60 * 60;
+    ///
+    /// ServerBuilder::new()
+    ///     .addr("0.0.0.0:443".parse().unwrap())
+    ///     .proto(Protocol::Https(TlsConfig::new("cert.pem", "key.pem")))
+    ///     .redirect_http("0.0.0.0:80".parse().unwrap())
+    ///     .hsts(StrictTransportSecurity::including_subdomains(one_year))
+    ///     .start();
+    /// ```
+    pub fn hsts(mut self, header: StrictTransportSecurity) -> Self
+    {
+        self.hsts = Some(header);
+        self
+    }
+
+    /// Writes a line to the `access_log` for every request the server
+    /// responds to.
+    pub fn access_log(mut self, access_log: AccessLog) -> Self
+    {
+        self.access_log = Some(Arc::new(access_log));
+        self
+    }
+
+    /// Sends the errors returned by handlers to the `reporter`, instead of
+    /// logging them with the `log` crate.
+    pub fn error_reporter<R: 'static>(mut self, reporter: R) -> Self
+        where R: ErrorReporter
+    {
+        self.reporter = Arc::new(reporter);
+        self
+    }
+
+    /// Aborts the whole process when a handler or resolver panics, instead
+    /// of reporting the panic as an error and responding with a `500`. This
+    /// can be useful during development, so that bugs can't go unnoticed.
+    pub fn abort_on_panic(mut self, abort: bool) -> Self
+    {
+        self.abort_on_panic = abort;
+        self
+    }
+
+    /// Counts and times every request in the `metrics`, and serves them on
+    /// the metrics' path for Prometheus to scrape.
+    pub fn metrics(mut self, metrics: Metrics) -> Self
+    {
+        self.metrics = Some(Arc::new(metrics));
+        self
+    }
+
+    /// Serves the liveness and readiness endpoints of the `health` checks.
+    /// Readiness starts failing as soon as the server starts to shut down,
+    /// so a [shutdown_grace] period should be given for load balancers to
+    /// notice before the server stops accepting connections.
+    pub fn health(mut self, health: HealthChecks) -> Self
+    {
+        self.health = Some(Arc::new(health));
+        self
+    }
+
+    /// Changes how long the server keeps accepting new connections after
+    /// it's been told to shut down, which is no time at all by default.
+    /// Readiness fails throughout, so this should be a little longer than
+    /// the interval load balancers check it at.
+    pub fn shutdown_grace(mut self, grace: Duration) -> Self
+    {
+        self.shutdown_grace = grace;
+        self
+    }
+
+    /// Changes how long the server waits for the requests in flight to
+    /// finish once it's been told to shut down, before closing their
+    /// connections anyway.
+    pub fn shutdown_timeout(mut self, timeout: Duration) -> Self
+    {
+        self.shutdown_timeout = timeout;
+        self
+    }
+
+    /// Changes how many threads read the streamed bodies which may block,
+    /// which are files, readers and iterators. Each of them holds a thread
+    /// until it's been sent, so once slow clients or endless sources have
+    /// taken every thread, the other streamed responses wait. There are 8
+    /// threads by default.
+    ///
+    /// The threads are shared by every server in the process, so only the
+    /// first server started can change how many there are.
+    pub fn blocking_threads(mut self, threads: usize) -> Self
+    {
+        self.blocking_threads = Some(threads);
+        self
+    }
+
+    /// Starts the server with the current configuration, and blocks until
+    /// it stops.
     /// This *will* panic if the server couldn't be started for some reason.
     pub fn start(self)
     {
-        let router: Arc<Router> = Arc::new(self.router.into());
+        self.spawn().unwrap().wait().unwrap();
+    }
+
+    /// Starts the server with the current configuration on its own thread,
+    /// returning once every listener is bound.
+    ///
+    /// The returned handle is used to find the addresses the server is
+    /// listening on, and to shut it down.
+    pub fn spawn(self) -> Result<ServerHandle>
+    {
+        let launch = self.launch()?;
+
+        let (ready, listening) = mpsc::channel();
+        let (trigger, shutdown) = oneshot::channel();
+        let thread = thread::spawn(move || launch.run(ready, shutdown));
+
+        match listening.recv() {
+            Ok(Ok(addrs)) => Ok(ServerHandle::new(addrs, trigger, thread)),
+            Ok(Err(error)) => Err(error),
+
+            // the thread died before it could tell us how it went
+            Err(_) => match thread.join() {
+                Ok(Err(error)) => Err(error),
+                _ => Err(shutdown::other("The server thread panicked")),
+            },
+        }
+    }
+
+    /// Creates a client which sends requests straight to a server with the
+    /// current configuration, without binding any listeners. See the
+    /// [testing] module.
+    pub fn test_client(self) -> TestClient
+    {
+        let hsts = match self.proto {
+            Protocol::Http => None,
+            Protocol::Https(_) => self.hsts.clone(),
+        };
+
+        let server = Server::new(Arc::new(self.router.into()))
+            .hsts(hsts)
+            .access_log(self.access_log)
+            .error_reporter(self.reporter)
+            .abort_on_panic(self.abort_on_panic)
+            .metrics(self.metrics)
+            .health(self.health);
+        TestClient::from_server(server)
+    }
+
+    /// Prepares everything the server thread needs, loading the TLS
+    /// certificates if they're needed.
+    fn launch(self) -> Result<Launch>
+    {
+        let mut addrs = self.addrs.clone();
+        if addrs.is_empty() && !self.has_sockets() {
+            addrs.push("127.0.0.1:8080".parse().unwrap());
+        }
+
+        // the redirects have to lead to a TLS listener, rather than back to
+        // plain HTTP
+        if self.redirect.is_some() {
+            if let Protocol::Http = self.proto {
+                return Err(shutdown::other(
+                    "redirect_http needs the server to use Protocol::Https"
+                ));
+            }
+            if addrs.is_empty() {
+                return Err(shutdown::other(
+                    "redirect_http needs a TCP address to serve HTTPS on"
+                ));
+            }
+        }
+
+        if let Some(threads) = self.blocking_threads {
+            stream::blocking_threads(threads);
+        }
+
+        let (acceptor, hsts) = match self.proto {
+            Protocol::Http => (None, None),
+            Protocol::Https(ref config) => {
+                (Some(config.acceptor()?), self.hsts.clone())
+            },
+        };
+
+        Ok(Launch {
+            router: Arc::new(self.router.into()),
+            acceptor,
+            hsts,
+            addrs,
+            #[cfg(unix)]
+            sockets: self.sockets,
+            redirect: self.redirect,
+            access_log: self.access_log,
+            reporter: self.reporter,
+            abort_on_panic: self.abort_on_panic,
+            metrics: self.metrics,
+            health: self.health,
+            shutdown_grace: self.shutdown_grace,
+            shutdown_timeout: self.shutdown_timeout,
+        })
+    }
+
+    /// If the server has been given any Unix domain sockets to listen on.
+    #[cfg(unix)]
+    fn has_sockets(&self) -> bool
+    {
+        !self.sockets.is_empty()
+    }
+
+    #[cfg(not(unix))]
+    fn has_sockets(&self) -> bool
+    {
+        false
+    }
+}
+
+impl Launch
+{
+    /// Runs the server until it's told to `shutdown`, sending whether every
+    /// listener could be bound to `ready` once they have been.
+    fn run(
+        self,
+        ready: mpsc::Sender<Result<Vec<SocketAddr>>>,
+        shutdown: oneshot::Receiver<()>
+    ) -> Result<()>
+    {
+        let drain = Drain::new();
+
+        let listening = Core::new().and_then(|core| {
+            let listeners = self.listen(&core.handle(), &drain)?;
+            Ok((core, listeners))
+        });
+
+        let (mut core, (listeners, addrs)) = match listening {
+            Ok(x) => x,
+            Err(error) => {
+                let _ = ready.send(Err(error.into()));
+                return Ok(());
+            },
+        };
+        let _ = ready.send(Ok(addrs));
+
+        let serving = future::join_all(listeners).map(|_| ());
+        let listeners = match core.run(
+            serving.select(shutdown::signal(shutdown))
+        ) {
+            Err((error, _)) => return Err(error.into()),
+            Ok(((), listeners)) => listeners,
+        };
+
+        // readiness fails first, and new connections are still accepted
+        // until load balancers have had the chance to notice
+        if let Some(ref health) = self.health {
+            health.shutting_down();
+        }
+
+        let grace = Timeout::new(self.shutdown_grace, &core.handle())?;
+        if let Err((error, _)) = core.run(listeners.select(grace)) {
+            return Err(error.into());
+        }
+
+        // the listeners were dropped along with the grace period, which
+        // stops any new connections
+        #[cfg(unix)]
+        for socket in &self.sockets {
+            let _ = ::std::fs::remove_file(socket.path());
+        }
+
+        drain.start();
+        let timeout = Timeout::new(self.shutdown_timeout, &core.handle())?;
+        let _ = core.run(drain.finished().select(timeout.map_err(|_| ())));
+
+        Ok(())
+    }
+
+    /// Binds every listener, returning them along with the addresses of the
+    /// TCP listeners.
+    fn listen(&self, handle: &Handle, drain: &Rc<Drain>)
+        -> io::Result<(Vec<Listener>, Vec<SocketAddr>)>
+    {
+        let http = Http::new();
+
+        let connections = Rc::new(Connections {
+            router: self.router.clone(),
+            acceptor: self.acceptor.clone(),
+            hsts: self.hsts.clone(),
+            access_log: self.access_log.clone(),
+            reporter: self.reporter.clone(),
+            abort_on_panic: self.abort_on_panic,
+            metrics: self.metrics.clone(),
+            health: self.health.clone(),
+            http: http.clone(),
+            handle: handle.clone(),
+            drain: drain.clone(),
+        });
+
+        let mut listeners: Vec<Listener> = Vec::new();
+        let mut addrs = Vec::new();
+
+        for addr in &self.addrs {
+            let listener = TcpListener::bind(addr, handle)?;
+            addrs.push(listener.local_addr()?);
+            let connections = connections.clone();
+
+            listeners.push(accept(
+                listener.incoming(),
+                handle,
+                move |(socket, remote)| {
+                    connections.serve(socket, Some(remote));
+                }
+            ));
+        }
+
+        #[cfg(unix)]
+        for socket in &self.sockets {
+            let listener = socket.bind(handle)?;
+            let connections = connections.clone();
+
+            listeners.push(accept(
+                listener.incoming(),
+                handle,
+                move |(socket, _)| connections.serve(socket, None)
+            ));
+        }
+
+        if let Some(ref addr) = self.redirect {
+            let redirects = TcpListener::bind(addr, handle)?;
+            // every TCP listener serves HTTPS, and there's at least one
+            let port = addrs[0].port();
+            let spawner = handle.clone();
+
+            listeners.push(accept(
+                redirects.incoming(),
+                handle,
+                move |(socket, _)| {
+                    let redirect = Redirect::new(port);
+                    let connection = http.serve_connection(socket, redirect);
+                    spawner.spawn(connection.map(|_| ()).map_err(|_| ()));
+                }
+            ));
+        }
+
+        Ok((listeners, addrs))
+    }
+}
+
+/// Passes each connection from `incoming` to `serve`. A connection which
+/// can't be accepted is logged and skipped after a short pause, so that one
+/// failure doesn't stop the listener.
+fn accept<S: 'static, F: 'static>(incoming: S, handle: &Handle, mut serve: F)
+    -> Listener
+    where S: Stream<Error=io::Error>,
+          F: FnMut(S::Item)
+{
+    let handle = handle.clone();
+
+    Box::new(incoming
+        .then(move |accepted| -> Box<Future<Item=(), Error=io::Error>> {
+            match accepted {
+                Ok(connection) => {
+                    serve(connection);
+                    Box::new(future::ok(()))
+                },
+
+                Err(error) => {
+                    error!("Could not accept a connection: {}", error);
+                    match Timeout::new(ACCEPT_BACKOFF, &handle) {
+                        Ok(pause) => Box::new(pause),
+                        Err(error) => Box::new(future::err(error)),
+                    }
+                },
+            }
+        })
+        .for_each(Ok))
+}
+
+impl Connections
+{
+    /// Serves HTTP (or HTTPS) over the newly accepted connection `io` from
+    /// the client at the `remote` address.
+    fn serve<I: 'static>(&self, io: I, remote: Option<SocketAddr>)
+        where I: AsyncRead + AsyncWrite
+    {
+        let service = Server::new(self.router.clone())
+            .hsts(self.hsts.clone())
+            .remote(remote)
+            .access_log(self.access_log.clone())
+            .error_reporter(self.reporter.clone())
+            .abort_on_panic(self.abort_on_panic)
+            .metrics(self.metrics.clone())
+            .health(self.health.clone());
+        let drain = self.drain.clone();
+
+        let connection: Box<Future<Item=(), Error=()>> = match self.acceptor {
+            None => Box::new(
+                serve_connection(
+                    &self.http,
+                    io,
+                    service,
+                    self.handle.clone(),
+                    drain
+                ).map_err(|_| ())
+            ),
+
+            Some(ref acceptor) => {
+                let http = self.http.clone();
+                let handle = self.handle.clone();
+
+                Box::new(
+                    tls::accept(acceptor, io)
+                        .map_err(|_| ())
+                        .and_then(move |io| {
+                            serve_connection(&http, io, service, handle, drain)
+                                .map_err(|_| ())
+                        })
+                )
+            },
+        };
+
+        self.handle.spawn(self.drain.track(connection));
+    }
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+    use std::io::{Read, Write};
+    use std::iter;
+    use std::net::TcpStream;
+    use std::time::Instant;
+
+    use error::Error;
+    use resolution::RouteMap;
+    use stream::BodyStream;
+    use view::View;
+
+    struct Hello;
+
+    impl RequestHandler for Hello
+    {
+        fn handle(&self, _route_map: RouteMap) -> Result<View>
+        {
+            Ok(View::raw("Hello, world!"))
+        }
+    }
+
+    /// Slowly streams the numbers up to 3.
+    struct Slow;
+
+    impl RequestHandler for Slow
+    {
+        fn handle(&self, _route_map: RouteMap) -> Result<View>
+        {
+            let numbers = (0..3).map(|it| {
+                thread::sleep(Duration::from_millis(100));
+                it.to_string()
+            });
+
+            Ok(View::stream(BodyStream::chunks(numbers)))
+        }
+    }
+
+    /// Streams dots forever.
+    struct Endless;
+
+    impl RequestHandler for Endless
+    {
+        fn handle(&self, _route_map: RouteMap) -> Result<View>
+        {
+            let dots = iter::repeat(".").inspect(|_| {
+                thread::sleep(Duration::from_millis(10));
+            });
+
+            Ok(View::stream(BodyStream::chunks(dots)))
+        }
+    }
+
+    /// Sends a request for `path` over the `stream`.
+    fn request<S: Write>(stream: &mut S, path: &str)
+    {
+        let request = format!("GET {} HTTP/1.1\r\nHost: test\r\n\r\n", path);
+        stream.write_all(request.as_bytes()).unwrap();
+    }
+
+    /// Reads everything sent over the `stream` until it's closed.
+    fn read<S: Read>(stream: &mut S) -> String
+    {
+        let mut response = Vec::new();
+        let _ = stream.read_to_end(&mut response);
+        String::from_utf8_lossy(&response).into_owned()
+    }
+
+    /// Sends a request for `/` over the `stream` and reads the response.
+    fn get<S: Read + Write>(mut stream: S) -> String
+    {
+        stream.write_all(b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        read(&mut stream)
+    }
+
+    /// Tests that listeners keep accepting connections after failing to
+    /// accept one.
+    #[test]
+    fn accept_errors()
+    {
+        use futures::stream;
+
+        let connections = vec![
+            Ok(1),
+            Err(io::Error::other("too many open files")),
+            Ok(2),
+        ];
+
+        let mut core = Core::new().unwrap();
+        let served = Rc::new(::std::cell::RefCell::new(Vec::new()));
+        let serving = served.clone();
+        core.run(accept(
+            stream::iter_result(connections),
+            &core.handle(),
+            move |it| serving.borrow_mut().push(it)
+        )).unwrap();
+
+        assert_eq!(vec![1, 2], *served.borrow());
+    }
+
+    /// Tests that the actual addresses are reported when binding to port 0,
+    /// and that the server stops listening once it's shut down.
+    #[test]
+    fn spawn_and_shutdown()
+    {
+        let any = "127.0.0.1:0".parse().unwrap();
+        let server = ServerBuilder::new()
+            .bind("/", Hello)
+            .addr(any)
+            .addr(any)
+            .spawn()
+            .unwrap();
+
+        assert_eq!(2, server.addrs().len());
+        assert_eq!(server.addr(), server.addrs()[0]);
+        assert!(server.addrs()[0].port() != 0);
+        assert!(server.addrs()[0] != server.addrs()[1]);
+
+        for addr in server.addrs() {
+            let response = get(TcpStream::connect(addr).unwrap());
+            assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+            assert!(response.contains("Hello, world!"));
+        }
+
+        let addr = server.addr();
+        server.shutdown();
+        server.wait().unwrap();
+        assert!(TcpStream::connect(addr).is_err());
+    }
+
+    /// Tests that plain HTTP can only be redirected to HTTPS.
+    #[test]
+    fn redirect_needs_https()
+    {
+        let any: SocketAddr = "127.0.0.1:0".parse().unwrap();
+        let error = ServerBuilder::new()
+            .bind("/", Hello)
+            .addr(any)
+            .redirect_http(any)
+            .spawn()
+            .err()
+            .expect("Redirected plain HTTP to itself");
+
+        assert!(error.to_string().contains("Protocol::Https"));
+    }
+
+    /// Tests that requests are written to the access log along with the
+    /// client's address.
+    #[test]
+    fn access_log()
+    {
+        use access_log::LogFormat;
+        use std::sync::Mutex;
+
+        let lines = Arc::new(Mutex::new(Vec::new()));
+        let sink = lines.clone();
+        let log = AccessLog::new(LogFormat::Combined)
+            .sink(move |line: &str| sink.lock().unwrap().push(line.to_string()));
+
+        let server = ServerBuilder::new()
+            .bind("/", Hello)
+            .addr("127.0.0.1:0".parse().unwrap())
+            .access_log(log)
+            .spawn()
+            .unwrap();
+
+        let mut stream = TcpStream::connect(server.addr()).unwrap();
+        stream.write_all(
+            b"GET /?a=b HTTP/1.1\r\nUser-Agent: tester\r\nConnection: close\r\n\r\n"
+        ).unwrap();
+        read(&mut stream);
+
+        server.shutdown();
+        server.wait().unwrap();
+
+        let lines = lines.lock().unwrap();
+        assert_eq!(1, lines.len());
+        assert!(lines[0].starts_with("127.0.0.1 - - ["));
+        assert!(lines[0].ends_with("] \"GET /?a=b HTTP/1.1\" 200 13 \"-\" \"tester\""));
+    }
+
+    /// Tests that failing to bind is reported instead of panicking.
+    #[test]
+    fn bind_failure()
+    {
+        let taken = ::std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+
+        let result = ServerBuilder::new()
+            .addr("127.0.0.1:0".parse().unwrap())
+            .addr(taken.local_addr().unwrap())
+            .spawn();
+
+        match result {
+            Err(Error::Io(_)) => {},
+            _ => panic!("Binding to a taken address should fail"),
+        }
+    }
+
+    /// Tests that requests in flight are finished before shutting down, but
+    /// idle connections are closed.
+    #[test]
+    fn graceful_shutdown()
+    {
+        let server = ServerBuilder::new()
+            .bind("/", Hello)
+            .bind("/slow", Slow)
+            .addr("127.0.0.1:0".parse().unwrap())
+            .spawn()
+            .unwrap();
+
+        // an idle keep-alive connection, which shouldn't hold anything up
+        let mut idle = TcpStream::connect(server.addr()).unwrap();
+        request(&mut idle, "/");
+        let mut buffer = [0; 1024];
+        assert!(idle.read(&mut buffer).unwrap() > 0);
+
+        let mut busy = TcpStream::connect(server.addr()).unwrap();
+        request(&mut busy, "/slow");
+        thread::sleep(Duration::from_millis(50));
+
+        let start = Instant::now();
+        server.shutdown();
+
+        let response = read(&mut busy);
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("\r\n1\r\n0\r\n1\r\n1\r\n1\r\n2\r\n0\r\n\r\n"));
+
+        server.wait().unwrap();
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(0, idle.read(&mut buffer).unwrap_or(0));
+    }
+
+    /// Tests that readiness fails as soon as the server is told to shut
+    /// down, while connections are still accepted during the grace period.
+    #[test]
+    fn shutdown_grace()
+    {
+        let server = ServerBuilder::new()
+            .bind("/", Hello)
+            .addr("127.0.0.1:0".parse().unwrap())
+            .health(HealthChecks::new())
+            .shutdown_grace(Duration::from_millis(500))
+            .spawn()
+            .unwrap();
+        let addr = server.addr();
+
+        let start = Instant::now();
+        server.shutdown();
+
+        let ready = |path: &str| {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            let request = format!(
+                "GET {} HTTP/1.1\r\nConnection: close\r\n\r\n",
+                path
+            );
+            stream.write_all(request.as_bytes()).unwrap();
+            read(&mut stream)
+        };
+        // shutting down starts on the server's own thread
+        while ready("/readyz").starts_with("HTTP/1.1 200 OK\r\n") {
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        assert!(ready("/readyz").starts_with("HTTP/1.1 503 "));
+        assert!(ready("/").ends_with("Hello, world!"));
+        assert!(start.elapsed() < Duration::from_millis(500));
+
+        server.wait().unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(500));
+        assert!(TcpStream::connect(addr).is_err());
+    }
+
+    /// Tests that requests which take too long are cut off once the shutdown
+    /// timeout has passed.
+    #[test]
+    fn shutdown_timeout()
+    {
+        let server = ServerBuilder::new()
+            .bind("/", Endless)
+            .addr("127.0.0.1:0".parse().unwrap())
+            .shutdown_timeout(Duration::from_millis(100))
+            .spawn()
+            .unwrap();
+
+        let mut stream = TcpStream::connect(server.addr()).unwrap();
+        request(&mut stream, "/");
+        thread::sleep(Duration::from_millis(50));
+
+        let start = Instant::now();
+        server.shutdown();
+        server.wait().unwrap();
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        let response = read(&mut stream);
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(!response.ends_with("0\r\n\r\n"));
+    }
+
+    /// Tests that every address and socket the server is bound to serves
+    /// the same routes, and that the socket is removed afterwards.
+    #[cfg(unix)]
+    #[test]
+    fn serves_every_listener()
+    {
+        use std::os::unix::net::UnixStream;
+
+        use testing::TempDir;
+
+        let dir = TempDir::new("builder-listeners");
+        let socket = dir.join("site.sock");
+
+        let server = ServerBuilder::new()
+            .bind("/", Hello)
+            .addr("127.0.0.1:0".parse().unwrap())
+            .addr("127.0.0.1:0".parse().unwrap())
+            .unix(socket.clone())
+            .spawn()
+            .unwrap();
+
+        for addr in server.addrs() {
+            let response = get(TcpStream::connect(addr).unwrap());
+            assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+            assert!(response.contains("Hello, world!"));
+        }
 
-        let server = Http::new().bind(&self.addr, move || {
-            let router = router.clone();
-            Ok(Server::new(router))
-        }).unwrap();
+        let response = get(UnixStream::connect(&socket).unwrap());
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("Hello, world!"));
 
-        server.run().unwrap();
+        server.shutdown();
+        server.wait().unwrap();
+        assert!(!socket.exists());
     }
 }
@@ -1,5 +1,34 @@
+use hyper::Request;
+
+use error::Result;
+use escape::escape_html;
+use resolution::RouteMap;
 use view::View;
 
+mod template;
+pub use self::template::{Template, Context, Value, TemplateError};
+
+mod layout;
+pub use self::layout::{Layouts, Layout};
+
+mod front_matter;
+pub use self::front_matter::FrontMatter;
+
+mod markdown;
+pub use self::markdown::Markdown;
+
+mod highlight;
+pub use self::highlight::{Highlight, Theme, Token};
+
+mod sanitize;
+pub use self::sanitize::Sanitize;
+
+mod minify;
+pub use self::minify::Minify;
+
+mod combinators;
+pub use self::combinators::{Chain, Identity, WhenMime, WhenRoute};
+
 /// A generic trait for anything which can decorate a [view](View) in some way.
 ///
 /// A decorator will take in a pre-existing view and generate a new one
@@ -8,7 +37,10 @@ use view::View;
 /// applying a certain style to the content of a page, or both!
 ///
 /// Because a decorator is required to return a view, these calls may be
-/// chained by calling view's `apply` method.
+/// chained by calling view's `apply` method, or grouped in a [Chain].
+///
+/// Any closure taking and returning a view is a decorator too, as is a boxed
+/// decorator, so different kinds of them may be kept together in a `Vec`.
 pub trait Decorator
 {
     /// Applies this decorator to the given `view`, altering it or creating
@@ -16,42 +48,125 @@ pub trait Decorator
     fn decorate(&self, view: View) -> View;
 }
 
-/// A decorator which treats the text in the view as markdown and generates
-/// HTML from it. This will also alter the mime type of the view, changing it
-/// to `text/html`.
+impl<F> Decorator for F
+    where F: Fn(View) -> View
+{
+    fn decorate(&self, view: View) -> View
+    {
+        self(view)
+    }
+}
+
+impl Decorator for Box<Decorator>
+{
+    fn decorate(&self, view: View) -> View
+    {
+        (**self).decorate(view)
+    }
+}
+
+impl Decorator for Box<Decorator + Send + Sync>
+{
+    fn decorate(&self, view: View) -> View
+    {
+        (**self).decorate(view)
+    }
+}
+
+/// A decorator which may fail, and which can see the request the view is
+/// being made for, such as its path, headers or the user it's from.
+///
+/// These are chained with [View::try_apply], which stops at the first error.
+/// Any closure taking the view and the context is one too.
 ///
 /// ```rust
-/// use mwf::{View, decorator};
+/// use mwf::{View, Result, RouteMap};
+/// use mwf::decorator::{RequestContext, Markdown};
+/// use mwf::hyper::{Method, Request};
 ///
-/// let dec = decorator::Markdown;
-/// let view = View::raw("# Hello world!").apply(&dec);
+/// // greets the user named in the request, or fails without one
+/// let greet = |view: View, context: &RequestContext| -> Result<View> {
+///     let name = match context.route_map.get("name") {
+///         None => return Err(std::io::Error::new(
+///             std::io::ErrorKind::NotFound,
+///             "no name"
+///         ).into()),
+///         Some(name) => name.clone(),
+///     };
 ///
-/// assert_eq!("<h1>Hello world!</h1>\n", view.content);
-/// assert_eq!("text", view.mime.type_());
-/// assert_eq!("html", view.mime.subtype());
+///     Ok(View::raw(format!("{}, {}!", view.content, name)))
+/// };
+///
+/// let request = Request::new(Method::Get, "/greet".parse().unwrap());
+/// let mut route_map = RouteMap::new();
+/// route_map.insert("name".to_string(), "Sam".to_string());
+/// let context = RequestContext::new(&request, &route_map);
+///
+/// let view = View::raw("Hello")
+///     .try_apply(&greet, &context).unwrap()
+///     .apply(&Markdown::new());
+/// assert_eq!("<p>Hello, Sam!</p>\n", view.content);
+///
+/// let route_map = RouteMap::new();
+/// let context = RequestContext::new(&request, &route_map);
+/// assert!(View::raw("Hello").try_apply(&greet, &context).is_err());
 /// ```
-pub struct Markdown;
-impl Decorator for Markdown
+pub trait TryDecorator
 {
-    fn decorate(&self, view: View) -> View
-    {
-        use pulldown_cmark::{Parser, html};
+    /// Applies this decorator to the given `view` for the request described
+    /// by the `context`, or fails.
+    fn try_decorate(&self, view: View, context: &RequestContext)
+        -> Result<View>;
+}
 
-        let mut output = String::new();
-        let p = Parser::new(&view.content);
-        html::push_html(&mut output, p);
+/// A named tuple of what a [TryDecorator] knows about the request.
+pub struct RequestContext<'a>
+{
+    /// The request the view is being made for.
+    pub request: &'a Request,
 
-        // create a new view with the html output and the correct
-        // mime type
-        View {
-            content: output,
-            mime: "text/html".parse().unwrap(),
+    /// The route map the request was resolved with.
+    pub route_map: &'a RouteMap,
+}
+
+impl<'a> RequestContext<'a>
+{
+    /// Creates the context of the `request`, which was resolved with the
+    /// `route_map`.
+    pub fn new(request: &'a Request, route_map: &'a RouteMap) -> Self
+    {
+        RequestContext {
+            request,
+            route_map,
         }
     }
 }
 
+impl<F> TryDecorator for F
+    where F: Fn(View, &RequestContext) -> Result<View>
+{
+    fn try_decorate(&self, view: View, context: &RequestContext)
+        -> Result<View>
+    {
+        self(view, context)
+    }
+}
+
 /// A decorator whose goal is to surround the text of the view with
 /// preset leading and trailing strings.
+///
+/// Any `{{meta.key}}` placeholders in the strings are replaced by the view's
+/// metadata `key` (escaped if the view is HTML), or removed if the view
+/// doesn't have it.
+///
+/// ```rust
+/// use mwf::{View, decorator};
+///
+/// let dec = decorator::Surround::new("<title>{{meta.title}}</title>", "");
+/// let view = View::raw("bar").with_meta("title", "Foo").apply(&dec);
+///
+/// assert_eq!("<title>Foo</title>bar", view.content);
+/// ```
 pub struct Surround
 {
     pre: String,
@@ -122,11 +237,48 @@ impl Decorator for Surround
 {
     fn decorate(&self, view: View) -> View
     {
+        let pre = substitute_meta(&self.pre, &view);
+        let post = substitute_meta(&self.post, &view);
+
         View {
-            content: format!("{}{}{}", self.pre, view.content, self.post),
-            mime: view.mime,
+            content: format!("{}{}{}", pre, view.content, post),
+            ..view
+        }
+    }
+}
+
+/// Replaces the `{{meta.key}}` placeholders in the `text` with the metadata
+/// of the `view`.
+fn substitute_meta(text: &str, view: &View) -> String
+{
+    const OPEN: &str = "{{meta.";
+
+    let html = view.mime.subtype() == "html";
+    let mut output = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find(OPEN) {
+        let end = match rest[start..].find("}}") {
+            None => break,
+            Some(end) => start + end,
+        };
+
+        output.push_str(&rest[..start]);
+
+        let value = view.meta(rest[start + OPEN.len()..end].trim())
+            .unwrap_or("");
+        if html {
+            output.push_str(&escape_html(value));
+        }
+        else {
+            output.push_str(value);
         }
+
+        rest = &rest[end + 2..];
     }
+
+    output.push_str(rest);
+    output
 }
 
 #[cfg(test)]
@@ -197,16 +349,19 @@ mod test
         assert_eq!("foobarbaz", view.content);
     }
 
-    /// Tests the [Markdown] decorator's ability to correctly convert markdown
-    /// source into html, and also change the mime type to `text/html`.
+    /// Tests that [Surround] substitutes metadata, escaping it in HTML.
     #[test]
-    fn markdown()
+    fn surround_meta()
     {
-        let dec = Markdown;
-        let view = View::raw("# Hello world!").apply(&dec);
-        assert_eq!("<h1>Hello world!</h1>\n", view.content);
-        assert_eq!("text", view.mime.type_());
-        assert_eq!("html", view.mime.subtype());
+        let dec = Surround::from(
+            "[{{meta.title}}|{{meta.missing}}|{{other}}]{{middle}}"
+        );
+
+        let view = View::raw("x").with_meta("title", "a < b").apply(&dec);
+        assert_eq!("[a < b||{{other}}]x", view.content);
+
+        let view = View::raw("# a < b").apply(&Markdown::new()).apply(&dec);
+        assert_eq!("[a &lt; b||{{other}}]<h1>a &lt; b</h1>\n", view.content);
     }
 
     /// Tests the ability to chain [View::apply] functions together and have
@@ -215,10 +370,54 @@ mod test
     fn apply_chaining()
     {
         let surround = Surround::new("*foo", "baz*");
-        let md = Markdown;
+        let md = Markdown::new();
         let view = View::raw("bar").apply(&surround).apply(&md);
         assert_eq!("<p><em>foobarbaz</em></p>\n", view.content);
         assert_eq!("text", view.mime.type_());
         assert_eq!("html", view.mime.subtype());
     }
+
+    /// Tests that [View::try_apply] chains with plain decorators, and stops
+    /// at the first failure.
+    #[test]
+    fn try_apply_chaining()
+    {
+        use std::cell::Cell;
+        use std::io;
+        use hyper::Method;
+
+        let request = Request::new(Method::Get, "/a".parse().unwrap());
+        let route_map = RouteMap::new();
+        let context = RequestContext::new(&request, &route_map);
+
+        let path = |view: View, context: &RequestContext| -> Result<View> {
+            let path = context.request.path();
+            Ok(View::raw(format!("{}{}", view.content, path)))
+        };
+        let fail = |_: View, _: &RequestContext| -> Result<View> {
+            Err(io::Error::other("failed").into())
+        };
+
+        let calls = Cell::new(0);
+        let count = |view: View, _: &RequestContext| -> Result<View> {
+            calls.set(calls.get() + 1);
+            Ok(view)
+        };
+
+        let chain = |view: View| -> Result<View> {
+            Ok(view.apply(&Surround::new("*", "*"))
+                .try_apply(&path, &context)?
+                .try_apply(&count, &context)?
+                .apply(&Markdown::new()))
+        };
+        let view = chain(View::raw("x")).unwrap();
+        assert_eq!("<p><em>x</em>/a</p>\n", view.content);
+        assert_eq!(1, calls.get());
+
+        let result = View::raw("x")
+            .try_apply(&fail, &context)
+            .and_then(|view| view.try_apply(&count, &context));
+        assert_eq!("failed", result.err().unwrap().to_string());
+        assert_eq!(1, calls.get());
+    }
 }
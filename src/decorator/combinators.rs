@@ -0,0 +1,325 @@
+use decorator::{Decorator, TryDecorator, RequestContext};
+use error::Result;
+use view::View;
+
+/// A decorator made of other decorators, which are applied in the order
+/// they were added.
+///
+/// ```rust
+/// use mwf::View;
+/// use mwf::decorator::{Chain, Markdown, Surround};
+///
+/// let chain = Chain::new()
+///     .then(Markdown::new())
+///     .then(Surround::new("<main>", "</main>"));
+/// let view = View::raw("Hello").apply(&chain);
+///
+/// assert_eq!("<main><p>Hello</p>\n</main>", view.content);
+/// ```
+pub struct Chain
+{
+    decorators: Vec<Box<Decorator + Send + Sync>>,
+}
+
+/// A decorator which leaves the view unchanged. This is useful when a
+/// decorator is needed but nothing should happen, as in a default setting.
+pub struct Identity;
+
+/// A decorator applying another one only to views whose mime type matches
+/// a pattern, such as `text/html`. Either half of the pattern may be `*` to
+/// match anything, as in `text/*`; parameters like `charset` are ignored.
+///
+/// ```rust
+/// use mwf::View;
+/// use mwf::decorator::{Surround, WhenMime};
+///
+/// let dec = WhenMime::new("text/html", Surround::new("<body>", "</body>"));
+///
+/// let mut view = View::raw("<p>Hi</p>");
+/// view.mime = "text/html; charset=utf-8".parse().unwrap();
+/// assert_eq!("<body><p>Hi</p></body>", view.apply(&dec).content);
+///
+/// assert_eq!("Hi", View::raw("Hi").apply(&dec).content);
+/// ```
+pub struct WhenMime<D>
+{
+    kind: String,
+    subtype: String,
+    decorator: D,
+}
+
+/// A decorator applying another one only to views made for some requests,
+/// chosen by their path. As it needs the request, it's applied with
+/// [View::try_apply], and never fails itself.
+///
+/// The pattern is either an exact path, such as `/about`, or a prefix ending
+/// with `*`, such as `/blog/*`, which matches `/blog` and everything under
+/// it.
+///
+/// ```rust
+/// use mwf::{View, RouteMap};
+/// use mwf::decorator::{RequestContext, Surround, WhenRoute};
+/// use mwf::hyper::{Method, Request};
+///
+/// let dec = WhenRoute::new("/blog/*", Surround::new("[", "]"));
+/// let route_map = RouteMap::new();
+///
+/// let request = Request::new(Method::Get, "/blog/first".parse().unwrap());
+/// let context = RequestContext::new(&request, &route_map);
+/// let view = View::raw("post").try_apply(&dec, &context).unwrap();
+/// assert_eq!("[post]", view.content);
+///
+/// let request = Request::new(Method::Get, "/about".parse().unwrap());
+/// let context = RequestContext::new(&request, &route_map);
+/// let view = View::raw("me").try_apply(&dec, &context).unwrap();
+/// assert_eq!("me", view.content);
+/// ```
+pub struct WhenRoute<D>
+{
+    pattern: String,
+    decorator: D,
+}
+
+//
+// Implementation
+//
+
+impl Chain
+{
+    /// Creates an empty chain, which leaves the views alone.
+    pub fn new() -> Self
+    {
+        Chain {
+            decorators: Vec::new(),
+        }
+    }
+
+    /// Adds the `decorator` at the end of the chain.
+    pub fn then<D>(mut self, decorator: D) -> Self
+        where D: Decorator + Send + Sync + 'static
+    {
+        self.decorators.push(Box::new(decorator));
+        self
+    }
+}
+
+impl Default for Chain
+{
+    fn default() -> Self
+    {
+        Chain::new()
+    }
+}
+
+impl Decorator for Chain
+{
+    fn decorate(&self, view: View) -> View
+    {
+        self.decorators.iter().fold(view, |view, dec| dec.decorate(view))
+    }
+}
+
+impl Decorator for Identity
+{
+    fn decorate(&self, view: View) -> View
+    {
+        view
+    }
+}
+
+impl<D: Decorator> WhenMime<D>
+{
+    /// Creates a decorator applying the `decorator` to the views whose mime
+    /// type matches the `pattern`. A pattern without a `/` is only matched
+    /// against the top-level type, so `text` is the same as `text/*`.
+    pub fn new<S: AsRef<str>>(pattern: S, decorator: D) -> Self
+    {
+        let pattern = pattern.as_ref().trim().to_ascii_lowercase();
+        let mut parts = pattern.splitn(2, '/');
+        let kind = parts.next().unwrap_or("*").trim();
+        let subtype = parts.next().unwrap_or("*").trim();
+
+        WhenMime {
+            kind: if kind.is_empty() { "*" } else { kind }.to_string(),
+            subtype: if subtype.is_empty() { "*" } else { subtype }
+                .to_string(),
+            decorator,
+        }
+    }
+}
+
+impl<D: Decorator> Decorator for WhenMime<D>
+{
+    fn decorate(&self, view: View) -> View
+    {
+        let matches = {
+            let kind = view.mime.type_().as_str();
+            let subtype = view.mime.subtype().as_str();
+
+            (self.kind == "*" || self.kind.eq_ignore_ascii_case(kind))
+                && (self.subtype == "*"
+                    || self.subtype.eq_ignore_ascii_case(subtype))
+        };
+
+        if matches {
+            self.decorator.decorate(view)
+        }
+        else {
+            view
+        }
+    }
+}
+
+impl<D: Decorator> WhenRoute<D>
+{
+    /// Creates a decorator applying the `decorator` to the views made for
+    /// the requests whose path matches the `pattern`.
+    pub fn new<S: Into<String>>(pattern: S, decorator: D) -> Self
+    {
+        WhenRoute {
+            pattern: pattern.into(),
+            decorator,
+        }
+    }
+
+    /// Checks if the request `path` is matched by the pattern.
+    pub fn matches(&self, path: &str) -> bool
+    {
+        path_matches(&self.pattern, path)
+    }
+}
+
+impl<D: Decorator> TryDecorator for WhenRoute<D>
+{
+    fn try_decorate(&self, view: View, context: &RequestContext)
+        -> Result<View>
+    {
+        if self.matches(context.request.path()) {
+            Ok(self.decorator.decorate(view))
+        }
+        else {
+            Ok(view)
+        }
+    }
+}
+
+/// Checks if the `path` is matched by the `pattern`, ignoring any trailing
+/// slashes on either.
+fn path_matches(pattern: &str, path: &str) -> bool
+{
+    let path = path.trim_end_matches('/');
+
+    if pattern.ends_with('*') {
+        let prefix = pattern.trim_end_matches('*').trim_end_matches('/');
+
+        path == prefix
+            || (path.starts_with(prefix)
+                && path[prefix.len()..].starts_with('/'))
+    }
+    else {
+        path == pattern.trim_end_matches('/')
+    }
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+    use decorator::Surround;
+    use hyper::{Method, Request};
+    use resolution::RouteMap;
+
+    /// Tests that a chain applies its decorators in order, and that an empty
+    /// one changes nothing.
+    #[test]
+    fn chain()
+    {
+        let chain = Chain::new()
+            .then(Surround::new("(", ")"))
+            .then(|view: View| View::raw(view.content.to_uppercase()))
+            .then(Surround::new("[", "]"));
+
+        assert_eq!("[(ABC)]", View::raw("abc").apply(&chain).content);
+        assert_eq!("abc", View::raw("abc").apply(&Chain::new()).content);
+
+        // chains nest, as they're decorators themselves
+        let outer = Chain::new().then(chain).then(Identity);
+        assert_eq!("[(X)]", View::raw("x").apply(&outer).content);
+    }
+
+    /// Tests that closures and boxed decorators may be stored and applied
+    /// together.
+    #[test]
+    fn boxed()
+    {
+        let decorators: Vec<Box<Decorator + Send + Sync>> = vec![
+            Box::new(Identity),
+            Box::new(|view: View| View::raw(view.content + "!")),
+            Box::new(Surround::new("<", ">")),
+        ];
+
+        let view = decorators.iter()
+            .fold(View::raw("hi"), |view, dec| view.apply(dec));
+        assert_eq!("<hi!>", view.content);
+
+        let dec: &(Decorator + Send + Sync) = &*decorators[2];
+        assert_eq!("<a>", View::raw("a").apply(dec).content);
+    }
+
+    /// Tests which mime types the patterns match.
+    #[test]
+    fn when_mime()
+    {
+        let apply = |pattern: &str, mime: &str| {
+            let dec = WhenMime::new(pattern, Surround::new("<", ">"));
+            let mut view = View::raw("x");
+            view.mime = mime.parse().unwrap();
+            view.apply(&dec).content == "<x>"
+        };
+
+        assert!(apply("text/html", "text/html"));
+        assert!(apply("text/html", "text/html; charset=utf-8"));
+        assert!(apply("TEXT/HTML", "text/html"));
+        assert!(apply("text/*", "text/css"));
+        assert!(apply("text", "text/plain"));
+        assert!(apply("*/*", "image/png"));
+        assert!(apply("*/json", "application/json"));
+        assert!(!apply("text/html", "text/plain"));
+        assert!(!apply("text/*", "application/javascript"));
+        assert!(!apply("*/json", "text/html"));
+    }
+
+    /// Tests which request paths the route patterns match, and that the
+    /// decorator is only applied to those.
+    #[test]
+    fn when_route()
+    {
+        let dec = WhenRoute::new("/blog/*", Identity);
+        assert!(dec.matches("/blog"));
+        assert!(dec.matches("/blog/"));
+        assert!(dec.matches("/blog/2017/first"));
+        assert!(!dec.matches("/blogs"));
+        assert!(!dec.matches("/"));
+
+        let dec = WhenRoute::new("/about", Identity);
+        assert!(dec.matches("/about"));
+        assert!(dec.matches("/about/"));
+        assert!(!dec.matches("/about/me"));
+
+        let dec = WhenRoute::new("*", Identity);
+        assert!(dec.matches("/"));
+        assert!(dec.matches("/anything"));
+
+        let dec = WhenRoute::new("/", Surround::new("<", ">"));
+        let route_map = RouteMap::new();
+        let request = Request::new(Method::Get, "/".parse().unwrap());
+        let context = RequestContext::new(&request, &route_map);
+        let view = View::raw("x").try_apply(&dec, &context).unwrap();
+        assert_eq!("<x>", view.content);
+
+        let request = Request::new(Method::Get, "/x".parse().unwrap());
+        let context = RequestContext::new(&request, &route_map);
+        let view = View::raw("x").try_apply(&dec, &context).unwrap();
+        assert_eq!("x", view.content);
+    }
+}
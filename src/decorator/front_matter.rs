@@ -0,0 +1,394 @@
+use std::collections::BTreeMap;
+
+use decorator::Decorator;
+use view::View;
+
+/// A decorator which strips a front matter block from the start of the view,
+/// and adds the values in it to the view's metadata. [Markdown] does this
+/// too, before it converts the rest of the view.
+///
+/// YAML front matter starts and ends with a `---` line, and TOML front matter
+/// with a `+++` line. Only the simple parts of each are understood: keys
+/// with text, number, date or boolean values, and lists of those, which are
+/// joined with `", "`. TOML tables prefix their keys, such as `author.name`.
+/// Lines which can't be understood are skipped.
+///
+/// Values which the view's metadata already has are kept.
+///
+/// ```rust
+/// use mwf::{View, decorator};
+///
+/// let view = View::raw("---\ntitle: Hello\ntags: [rust, web]\n---\nBody")
+///     .apply(&decorator::FrontMatter);
+///
+/// assert_eq!("Body", view.content);
+/// assert_eq!(Some("Hello"), view.title());
+/// assert_eq!(Some("rust, web"), view.meta("tags"));
+/// ```
+pub struct FrontMatter;
+
+//
+// Implementation
+//
+
+impl Decorator for FrontMatter
+{
+    fn decorate(&self, view: View) -> View
+    {
+        let (values, body) = match split(&view.content) {
+            None => return view,
+            Some((values, body)) => (values, body.to_string()),
+        };
+
+        let mut meta = view.meta;
+        for (key, value) in values {
+            meta.entry(key).or_insert(value);
+        }
+
+        View {
+            content: body,
+            meta,
+            ..view
+        }
+    }
+}
+
+/// Splits the front matter off the start of the `content`, returning its
+/// values and the rest of the content, if it has any front matter.
+pub(crate) fn split(content: &str) -> Option<(BTreeMap<String, String>, &str)>
+{
+    let content = content.trim_start_matches('\u{feff}');
+    let first = content.lines().next()?;
+
+    let (yaml, fences): (bool, &[&str]) = match first.trim_end() {
+        "---" => (true, &["---", "..."]),
+        "+++" => (false, &["+++"]),
+        _ => return None,
+    };
+
+    // find the closing fence, and where the body starts after it
+    let mut offset = first.len() + 1;
+    let mut lines = Vec::new();
+    for line in content[offset.min(content.len())..].split('\n') {
+        let next = offset + line.len() + 1;
+        let line = line.trim_end_matches('\r');
+
+        if fences.contains(&line.trim_end()) {
+            let body = &content[next.min(content.len())..];
+            let values = if yaml {
+                parse_yaml(&lines)
+            }
+            else {
+                parse_toml(&lines)
+            };
+            return Some((values, body));
+        }
+
+        lines.push(line);
+        offset = next;
+    }
+
+    None
+}
+
+/// Parses the simple subset of YAML front matter.
+fn parse_yaml(lines: &[&str]) -> BTreeMap<String, String>
+{
+    let mut values = BTreeMap::new();
+
+    // the key of a value which is given as a list on the following lines
+    let mut list: Option<(String, Vec<String>)> = None;
+
+    for line in lines {
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+
+        if let Some(item) = trimmed.strip_prefix("- ") {
+            if let Some((_, ref mut items)) = list {
+                items.push(scalar(item, true));
+            }
+            continue;
+        }
+
+        if let Some((key, items)) = list.take() {
+            values.insert(key, items.join(", "));
+        }
+
+        // nested keys aren't understood
+        if line.starts_with(char::is_whitespace) {
+            continue;
+        }
+
+        let (key, value) = match trimmed.find(':') {
+            None => continue,
+            Some(i) => (unquote(trimmed[..i].trim()), trimmed[i + 1..].trim()),
+        };
+
+        if value.is_empty() {
+            list = Some((key, Vec::new()));
+        }
+        else {
+            values.insert(key, value_text(value, true));
+        }
+    }
+
+    if let Some((key, items)) = list {
+        values.insert(key, items.join(", "));
+    }
+
+    values
+}
+
+/// Parses the simple subset of TOML front matter.
+fn parse_toml(lines: &[&str]) -> BTreeMap<String, String>
+{
+    let mut values = BTreeMap::new();
+    let mut table = String::new();
+
+    for line in lines {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        if line.starts_with('[') && line.ends_with(']') {
+            table = line[1..line.len() - 1].trim().to_string();
+            continue;
+        }
+
+        let (key, value) = match line.find('=') {
+            None => continue,
+            Some(i) => (unquote(line[..i].trim()), line[i + 1..].trim()),
+        };
+
+        let key = if table.is_empty() {
+            key
+        }
+        else {
+            format!("{}.{}", table, key)
+        };
+
+        values.insert(key, value_text(value, false));
+    }
+
+    values
+}
+
+/// The text of a `value`, which may be an inline list.
+fn value_text(value: &str, yaml: bool) -> String
+{
+    let value = strip_comment(value);
+
+    if value.starts_with('[') && value.ends_with(']') {
+        return split_list(&value[1..value.len() - 1])
+            .iter()
+            .map(|it| scalar(it, yaml))
+            .filter(|it| !it.is_empty())
+            .collect::<Vec<_>>()
+            .join(", ");
+    }
+
+    scalar(value, yaml)
+}
+
+/// The text of a single value, without its quotes.
+fn scalar(value: &str, yaml: bool) -> String
+{
+    let value = strip_comment(value.trim());
+
+    // plain YAML values can end in comments, but TOML ones are never plain
+    if yaml || value.starts_with('"') || value.starts_with('\'') {
+        unquote(value)
+    }
+    else {
+        value.to_string()
+    }
+}
+
+/// Removes a trailing comment from the `value`, unless it's quoted.
+fn strip_comment(value: &str) -> &str
+{
+    let mut quote = None;
+    let mut previous = ' ';
+
+    for (i, c) in value.char_indices() {
+        match quote {
+            Some(q) if c == q && previous != '\\' => quote = None,
+            Some(_) => {},
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if c == '#' && previous.is_whitespace() => {
+                return value[..i].trim_end();
+            },
+            None => {},
+        }
+        previous = c;
+    }
+
+    value
+}
+
+/// Splits the items of an inline list on the commas which aren't quoted.
+fn split_list(list: &str) -> Vec<&str>
+{
+    let mut items = Vec::new();
+    let mut quote = None;
+    let mut start = 0;
+
+    for (i, c) in list.char_indices() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => {},
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if c == ',' => {
+                items.push(list[start..i].trim());
+                start = i + 1;
+            },
+            None => {},
+        }
+    }
+
+    items.push(list[start..].trim());
+    items
+}
+
+/// Removes the quotes around the `text`, unescaping it if it was in double
+/// quotes.
+fn unquote(text: &str) -> String
+{
+    let quoted = |q: char| {
+        text.len() >= 2 && text.starts_with(q) && text.ends_with(q)
+    };
+
+    if quoted('\'') {
+        return text[1..text.len() - 1].replace("''", "'");
+    }
+
+    if !quoted('"') {
+        return text.to_string();
+    }
+
+    let mut output = String::new();
+    let mut chars = text[1..text.len() - 1].chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            output.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('n') => output.push('\n'),
+            Some('t') => output.push('\t'),
+            Some(c) => output.push(c),
+            None => {},
+        }
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+
+    /// Collects the given pairs into a metadata map.
+    fn map(pairs: &[(&str, &str)]) -> BTreeMap<String, String>
+    {
+        pairs.iter()
+            .map(|&(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    /// Tests the parts of YAML front matter which are understood.
+    #[test]
+    fn yaml()
+    {
+        let content = "---\r\n\
+                       title: \"Hello: \\\"world\\\"\"\r\n\
+                       date: 2024-01-02 # a comment\n\
+                       draft: false\n\
+                       'quoted key': 'it''s # not a comment'\n\
+                       tags: [rust, \"web, http\"]\n\
+                       authors:\n\
+                       \x20 - Sam\n\
+                       \x20 - 'Alex'\n\
+                       nested:\n\
+                       \x20 key: ignored\n\
+                       # comment\n\
+                       not a pair\n\
+                       ---\n\
+                       # Body\n\
+                       ---\n";
+
+        let (values, body) = split(content).unwrap();
+        assert_eq!(
+            map(&[
+                ("title", "Hello: \"world\""),
+                ("date", "2024-01-02"),
+                ("draft", "false"),
+                ("quoted key", "it's # not a comment"),
+                ("tags", "rust, web, http"),
+                ("authors", "Sam, Alex"),
+                ("nested", ""),
+            ]),
+            values
+        );
+        assert_eq!("# Body\n---\n", body);
+    }
+
+    /// Tests the parts of TOML front matter which are understood.
+    #[test]
+    fn toml()
+    {
+        let content = "+++\n\
+                       title = \"Hello # there\"\n\
+                       weight = 10 # a comment\n\
+                       tags = [ \"a\", 'b' ]\n\
+                       [author]\n\
+                       name = \"Sam\"\n\
+                       +++\n\
+                       Body";
+
+        let (values, body) = split(content).unwrap();
+        assert_eq!(
+            map(&[
+                ("title", "Hello # there"),
+                ("weight", "10"),
+                ("tags", "a, b"),
+                ("author.name", "Sam"),
+            ]),
+            values
+        );
+        assert_eq!("Body", body);
+    }
+
+    /// Tests that content without (complete) front matter is left alone.
+    #[test]
+    fn no_front_matter()
+    {
+        assert!(split("").is_none());
+        assert!(split("# Title\n---\n").is_none());
+        assert!(split("---\ntitle: never closed\n").is_none());
+        assert!(split("+++\ntitle = 'x'\n---\n").is_none());
+
+        let (values, body) = split("---\n---").unwrap();
+        assert!(values.is_empty());
+        assert_eq!("", body);
+    }
+
+    /// Tests that the decorator strips the front matter, without replacing
+    /// metadata the view already has.
+    #[test]
+    fn decorates()
+    {
+        let view = View::raw("---\ntitle: From file\nlayout: post\n---\nHi")
+            .with_meta("title", "From handler")
+            .apply(&FrontMatter);
+
+        assert_eq!("Hi", view.content);
+        assert_eq!(Some("From handler"), view.title());
+        assert_eq!(Some("post"), view.meta("layout"));
+    }
+}
@@ -0,0 +1,1040 @@
+use decorator::Decorator;
+use escape::escape_html;
+use view::View;
+
+/// A decorator which turns a view of source code into highlighted HTML. The
+/// language is found from the extension of the view's file, or else from its
+/// mime type, so a `View::file("src/view.rs")` is highlighted as Rust. Views
+/// in languages which aren't known, including HTML, are left alone.
+///
+/// The highlighted code is in a `<pre class="highlight">` block. By default
+/// each token is in a `<span>` with a class such as `hl-keyword`, which the
+/// [stylesheet](Highlight::stylesheet) colours, but the colours can be put
+/// inline instead.
+///
+/// The highlighting is only lexical: comments, strings, numbers, keywords,
+/// literals, types and function calls are recognised for Rust, C, C++, Go,
+/// Java, JavaScript, TypeScript, Python, shell, JSON, TOML, YAML, CSS and
+/// SQL.
+///
+/// ```rust
+/// use mwf::{View, decorator};
+///
+/// let dec = decorator::Highlight::new();
+/// let mut view = View::raw("let x = 1; // one");
+/// view.mime = "text/x-rust".parse().unwrap();
+/// let view = view.apply(&dec);
+///
+/// assert_eq!(
+///     "<pre class=\"highlight\"><code class=\"language-rust\">\
+///      <span class=\"hl-keyword\">let</span> x = \
+///      <span class=\"hl-number\">1</span>; \
+///      <span class=\"hl-comment\">// one</span>\
+///      </code></pre>\n",
+///     view.content
+/// );
+/// assert_eq!("html", view.mime.subtype());
+/// ```
+///
+/// Fenced code blocks in markdown are highlighted by giving it to the
+/// [Markdown](decorator::Markdown) decorator.
+#[derive(Clone)]
+pub struct Highlight
+{
+    /// The colours of each token.
+    theme: Theme,
+
+    /// If the colours are put inline, rather than in classes.
+    inline: bool,
+}
+
+/// The colours a [Highlight] decorator uses, as CSS colours.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Theme
+{
+    background: String,
+    foreground: String,
+    colors: [String; 7],
+}
+
+/// A kind of token which is highlighted.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Token
+{
+    /// A line or block comment.
+    Comment,
+
+    /// A string or character literal.
+    String,
+
+    /// A number, including its suffix.
+    Number,
+
+    /// A keyword, such as `fn` or `return`.
+    Keyword,
+
+    /// A named constant, such as `true` or `null`.
+    Literal,
+
+    /// A built in type, or a capitalized name in languages where those are
+    /// types.
+    Type,
+
+    /// The name of a function or macro being called or declared.
+    Function,
+}
+
+/// The rules for finding the tokens of a language.
+struct Language
+{
+    /// The name of the language, used in the `language-` class.
+    name: &'static str,
+
+    /// The names, file extensions and mime subtypes which mean the language.
+    aliases: &'static [&'static str],
+
+    keywords: &'static [&'static str],
+    literals: &'static [&'static str],
+    types: &'static [&'static str],
+
+    /// If words starting with a capital letter are types.
+    capitalized_types: bool,
+
+    /// If keywords are matched regardless of their case.
+    ignore_case: bool,
+
+    line_comments: &'static [&'static str],
+    block_comment: Option<(&'static str, &'static str)>,
+
+    /// The characters which start and end a string.
+    quotes: &'static [char],
+
+    /// If `'` only starts a character literal, like `'a'`, rather than a
+    /// string.
+    char_literals: bool,
+}
+
+//
+// Implementation
+//
+
+impl Highlight
+{
+    /// Creates a decorator which uses the light theme, with classes.
+    pub fn new() -> Self
+    {
+        Highlight {
+            theme: Theme::light(),
+            inline: false,
+        }
+    }
+
+    /// Changes the theme used for inline colours and the stylesheet.
+    pub fn theme(mut self, theme: Theme) -> Self
+    {
+        self.theme = theme;
+        self
+    }
+
+    /// Changes whether the colours are put inline in `style` attributes,
+    /// rather than in classes, which needs no stylesheet.
+    pub fn inline(mut self, inline: bool) -> Self
+    {
+        self.inline = inline;
+        self
+    }
+
+    /// The CSS which colours the classes of the highlighted code with the
+    /// theme.
+    pub fn stylesheet(&self) -> String
+    {
+        let mut css = format!(
+            ".highlight {{ background: {}; color: {}; }}\n",
+            self.theme.background,
+            self.theme.foreground
+        );
+
+        for &token in Token::all() {
+            css.push_str(&format!(
+                ".highlight .{} {{ color: {}; }}\n",
+                token.class(),
+                self.theme.get(token)
+            ));
+        }
+
+        css
+    }
+
+    /// Highlights the `source` as the language with the given name or file
+    /// extension, returning the HTML of its `<pre>` block, if the language
+    /// is known.
+    pub fn code(&self, language: &str, source: &str) -> Option<String>
+    {
+        let language = Language::find(language)?;
+
+        let mut output = if self.inline {
+            format!(
+                "<pre class=\"highlight\" style=\"background: {}; \
+                 color: {};\"><code class=\"language-{}\">",
+                self.theme.background,
+                self.theme.foreground,
+                language.name
+            )
+        }
+        else {
+            format!(
+                "<pre class=\"highlight\"><code class=\"language-{}\">",
+                language.name
+            )
+        };
+
+        for (token, text) in language.tokens(source) {
+            let text = escape_html(text);
+            match token {
+                None => output.push_str(&text),
+                Some(token) if self.inline => output.push_str(&format!(
+                    "<span style=\"color: {};\">{}</span>",
+                    self.theme.get(token),
+                    text
+                )),
+                Some(token) => output.push_str(&format!(
+                    "<span class=\"{}\">{}</span>",
+                    token.class(),
+                    text
+                )),
+            }
+        }
+
+        output.push_str("</code></pre>\n");
+        Some(output)
+    }
+}
+
+impl Default for Highlight
+{
+    fn default() -> Self
+    {
+        Highlight::new()
+    }
+}
+
+impl Decorator for Highlight
+{
+    fn decorate(&self, view: View) -> View
+    {
+        // a stream's content isn't in the view, so there's nothing to
+        // highlight
+        if view.stream.is_some() {
+            return view;
+        }
+
+        let extension = view.file.as_ref()
+            .and_then(|it| it.extension())
+            .and_then(|it| it.to_str())
+            .filter(|it| Language::find(it).is_some())
+            .map(String::from);
+        let subtype = view.mime.subtype().as_str().to_string();
+
+        let language = extension.unwrap_or_else(|| {
+            subtype.trim_start_matches("x-").to_string()
+        });
+
+        let content = match self.code(&language, &view.content) {
+            None => return view,
+            Some(content) => content,
+        };
+
+        View {
+            content,
+            mime: "text/html".parse().unwrap(),
+            ..view
+        }
+    }
+}
+
+impl Theme
+{
+    /// A theme with dark text on a light background.
+    pub fn light() -> Self
+    {
+        Theme::new("#fafafa", "#383a42", [
+            "#a0a1a7", "#50a14f", "#986801", "#a626a4",
+            "#0184bc", "#c18401", "#4078f2",
+        ])
+    }
+
+    /// A theme with light text on a dark background.
+    pub fn dark() -> Self
+    {
+        Theme::new("#282c34", "#abb2bf", [
+            "#7f848e", "#98c379", "#d19a66", "#c678dd",
+            "#56b6c2", "#e5c07b", "#61afef",
+        ])
+    }
+
+    /// Changes the background colour.
+    pub fn background<T: Into<String>>(mut self, color: T) -> Self
+    {
+        self.background = color.into();
+        self
+    }
+
+    /// Changes the colour of text which isn't in a token.
+    pub fn foreground<T: Into<String>>(mut self, color: T) -> Self
+    {
+        self.foreground = color.into();
+        self
+    }
+
+    /// Changes the colour of the `token`.
+    pub fn color<T: Into<String>>(mut self, token: Token, color: T) -> Self
+    {
+        self.colors[token as usize] = color.into();
+        self
+    }
+
+    /// Creates a theme from the colours of every token, in the order they're
+    /// declared.
+    fn new(background: &str, foreground: &str, colors: [&str; 7]) -> Self
+    {
+        Theme {
+            background: background.to_string(),
+            foreground: foreground.to_string(),
+            colors: [
+                colors[0].to_string(), colors[1].to_string(),
+                colors[2].to_string(), colors[3].to_string(),
+                colors[4].to_string(), colors[5].to_string(),
+                colors[6].to_string(),
+            ],
+        }
+    }
+
+    /// The colour of the `token`.
+    fn get(&self, token: Token) -> &str
+    {
+        &self.colors[token as usize]
+    }
+}
+
+impl Token
+{
+    /// Every kind of token.
+    fn all() -> &'static [Token]
+    {
+        &[
+            Token::Comment, Token::String, Token::Number, Token::Keyword,
+            Token::Literal, Token::Type, Token::Function,
+        ]
+    }
+
+    /// The class of the token's `<span>`.
+    fn class(self) -> &'static str
+    {
+        match self {
+            Token::Comment => "hl-comment",
+            Token::String => "hl-string",
+            Token::Number => "hl-number",
+            Token::Keyword => "hl-keyword",
+            Token::Literal => "hl-literal",
+            Token::Type => "hl-type",
+            Token::Function => "hl-function",
+        }
+    }
+}
+
+impl Language
+{
+    /// Finds the language with the given name, file extension or mime
+    /// subtype.
+    fn find(name: &str) -> Option<&'static Language>
+    {
+        let name = name.trim().to_lowercase();
+        LANGUAGES.iter().find(|it| it.aliases.contains(&name.as_str()))
+    }
+
+    /// Splits the `source` into pieces, each of which is either plain text
+    /// or a token.
+    fn tokens<'a>(&self, source: &'a str) -> Vec<(Option<Token>, &'a str)>
+    {
+        let mut tokens = Vec::new();
+        let mut plain = 0;
+        let mut i = 0;
+
+        while i < source.len() {
+            let rest = &source[i..];
+            let c = rest.chars().next().unwrap();
+
+            let token = if let Some(end) = self.comment(rest) {
+                Some((Token::Comment, end))
+            }
+            else if let Some(end) = self.string(rest) {
+                Some((Token::String, end))
+            }
+            else if c.is_ascii_digit() {
+                Some((Token::Number, number(rest)))
+            }
+            else if c.is_alphabetic() || c == '_' {
+                let end = rest.find(|c: char| !c.is_alphanumeric() && c != '_')
+                    .unwrap_or(rest.len());
+                let word = self.word(&rest[..end], &rest[end..]);
+
+                // plain words are still skipped whole, so that the digits in
+                // them aren't numbers
+                if word.is_none() {
+                    i += end;
+                    continue;
+                }
+                word.map(|it| (it, end))
+            }
+            else {
+                None
+            };
+
+            match token {
+                None => i += c.len_utf8(),
+                Some((token, end)) => {
+                    if plain < i {
+                        tokens.push((None, &source[plain..i]));
+                    }
+                    tokens.push((Some(token), &rest[..end]));
+                    i += end;
+                    plain = i;
+                },
+            }
+        }
+
+        if plain < source.len() {
+            tokens.push((None, &source[plain..]));
+        }
+
+        tokens
+    }
+
+    /// The length of the comment at the start of the `text`, if there is one.
+    fn comment(&self, text: &str) -> Option<usize>
+    {
+        if self.line_comments.iter().any(|it| text.starts_with(it)) {
+            return Some(text.find('\n').unwrap_or(text.len()));
+        }
+
+        let (start, end) = self.block_comment?;
+        if !text.starts_with(start) {
+            return None;
+        }
+
+        Some(text[start.len()..].find(end)
+            .map(|it| start.len() + it + end.len())
+            .unwrap_or(text.len()))
+    }
+
+    /// The length of the string at the start of the `text`, if there is one.
+    fn string(&self, text: &str) -> Option<usize>
+    {
+        let quote = text.chars().next()?;
+        if !self.quotes.contains(&quote) {
+            return None;
+        }
+
+        let mut escaped = false;
+        for (n, (i, c)) in text.char_indices().enumerate().skip(1) {
+            if self.char_literals && quote == '\'' && n > 2 && !escaped
+                && !text[1..].starts_with('\\')
+            {
+                // a lifetime or label, rather than a character
+                return None;
+            }
+
+            if escaped {
+                escaped = false;
+            }
+            else if c == '\\' {
+                escaped = true;
+            }
+            else if c == quote {
+                return Some(i + c.len_utf8());
+            }
+        }
+
+        if self.char_literals && quote == '\'' {
+            None
+        }
+        else {
+            Some(text.len())
+        }
+    }
+
+    /// The kind of token the `word` is, given the text which follows it.
+    fn word(&self, word: &str, after: &str) -> Option<Token>
+    {
+        let matches = |list: &[&str]| if self.ignore_case {
+            list.iter().any(|it| it.eq_ignore_ascii_case(word))
+        }
+        else {
+            list.contains(&word)
+        };
+
+        if matches(self.keywords) {
+            Some(Token::Keyword)
+        }
+        else if matches(self.literals) {
+            Some(Token::Literal)
+        }
+        else if matches(self.types) {
+            Some(Token::Type)
+        }
+        else if after.starts_with('(') || after.starts_with("!(") {
+            Some(Token::Function)
+        }
+        else if self.capitalized_types
+            && word.starts_with(|c: char| c.is_uppercase())
+        {
+            Some(Token::Type)
+        }
+        else {
+            None
+        }
+    }
+}
+
+/// The length of the number at the start of the `text`, which stops before a
+/// range's `..`.
+fn number(text: &str) -> usize
+{
+    let bytes = text.as_bytes();
+    let mut end = 0;
+
+    while end < bytes.len() {
+        let b = bytes[end];
+        let part = b.is_ascii_alphanumeric()
+            || b == b'_'
+            || (b == b'.' && bytes.get(end + 1) != Some(&b'.'));
+        if !part {
+            break;
+        }
+        end += 1;
+    }
+
+    end
+}
+
+/// Every language which can be highlighted.
+static LANGUAGES: &[Language] = &[
+    Language {
+        name: "rust",
+        aliases: &["rust", "rs"],
+        keywords: &[
+            "as", "async", "await", "break", "const", "continue", "crate",
+            "dyn", "else", "enum", "extern", "fn", "for", "if", "impl", "in",
+            "let", "loop", "match", "mod", "move", "mut", "pub", "ref",
+            "return", "self", "Self", "static", "struct", "super", "trait",
+            "type", "unsafe", "use", "where", "while", "yield",
+        ],
+        literals: &["true", "false"],
+        types: &[
+            "i8", "i16", "i32", "i64", "i128", "isize", "u8", "u16", "u32",
+            "u64", "u128", "usize", "f32", "f64", "bool", "char", "str",
+        ],
+        capitalized_types: true,
+        ignore_case: false,
+        line_comments: &["//"],
+        block_comment: Some(("/*", "*/")),
+        quotes: &['"', '\''],
+        char_literals: true,
+    },
+    Language {
+        name: "c",
+        aliases: &["c", "h", "csrc", "chdr"],
+        keywords: C_KEYWORDS,
+        literals: &["NULL"],
+        types: C_TYPES,
+        capitalized_types: false,
+        ignore_case: false,
+        line_comments: &["//"],
+        block_comment: Some(("/*", "*/")),
+        quotes: &['"', '\''],
+        char_literals: false,
+    },
+    Language {
+        name: "cpp",
+        aliases: &["cpp", "c++", "cc", "cxx", "hpp", "c++src", "c++hdr"],
+        keywords: &[
+            "auto", "break", "case", "catch", "class", "const", "constexpr",
+            "continue", "default", "delete", "do", "else", "enum", "explicit",
+            "extern", "for", "friend", "goto", "if", "inline", "namespace",
+            "new", "operator", "private", "protected", "public", "return",
+            "sizeof", "static", "struct", "switch", "template", "this",
+            "throw", "try", "typedef", "typename", "union", "using",
+            "virtual", "volatile", "while",
+        ],
+        literals: &["true", "false", "nullptr", "NULL"],
+        types: C_TYPES,
+        capitalized_types: false,
+        ignore_case: false,
+        line_comments: &["//"],
+        block_comment: Some(("/*", "*/")),
+        quotes: &['"', '\''],
+        char_literals: false,
+    },
+    Language {
+        name: "go",
+        aliases: &["go", "golang"],
+        keywords: &[
+            "break", "case", "chan", "const", "continue", "default", "defer",
+            "else", "fallthrough", "for", "func", "go", "goto", "if",
+            "import", "interface", "map", "package", "range", "return",
+            "select", "struct", "switch", "type", "var",
+        ],
+        literals: &["true", "false", "nil", "iota"],
+        types: &[
+            "bool", "byte", "error", "float32", "float64", "int", "int8",
+            "int16", "int32", "int64", "rune", "string", "uint", "uint8",
+            "uint16", "uint32", "uint64", "uintptr",
+        ],
+        capitalized_types: false,
+        ignore_case: false,
+        line_comments: &["//"],
+        block_comment: Some(("/*", "*/")),
+        quotes: &['"', '\'', '`'],
+        char_literals: false,
+    },
+    Language {
+        name: "java",
+        aliases: &["java", "java-source"],
+        keywords: &[
+            "abstract", "assert", "break", "case", "catch", "class",
+            "continue", "default", "do", "else", "enum", "extends", "final",
+            "finally", "for", "if", "implements", "import", "instanceof",
+            "interface", "native", "new", "package", "private", "protected",
+            "public", "return", "static", "super", "switch", "synchronized",
+            "this", "throw", "throws", "try", "var", "volatile", "while",
+        ],
+        literals: &["true", "false", "null"],
+        types: &[
+            "boolean", "byte", "char", "double", "float", "int", "long",
+            "short", "void",
+        ],
+        capitalized_types: true,
+        ignore_case: false,
+        line_comments: &["//"],
+        block_comment: Some(("/*", "*/")),
+        quotes: &['"', '\''],
+        char_literals: false,
+    },
+    Language {
+        name: "javascript",
+        aliases: &["javascript", "js", "jsx", "mjs", "ecmascript"],
+        keywords: JS_KEYWORDS,
+        literals: JS_LITERALS,
+        types: &[],
+        capitalized_types: true,
+        ignore_case: false,
+        line_comments: &["//"],
+        block_comment: Some(("/*", "*/")),
+        quotes: &['"', '\'', '`'],
+        char_literals: false,
+    },
+    Language {
+        name: "typescript",
+        aliases: &["typescript", "ts", "tsx"],
+        keywords: &[
+            "abstract", "as", "async", "await", "break", "case", "catch",
+            "class", "const", "continue", "declare", "default", "delete",
+            "do", "else", "enum", "export", "extends", "finally", "for",
+            "from", "function", "if", "implements", "import", "in",
+            "instanceof", "interface", "keyof", "let", "namespace", "new",
+            "of", "private", "protected", "public", "readonly", "return",
+            "static", "super", "switch", "this", "throw", "try", "type",
+            "typeof", "var", "void", "while", "yield",
+        ],
+        literals: JS_LITERALS,
+        types: &[
+            "any", "boolean", "never", "number", "object", "string",
+            "symbol", "unknown",
+        ],
+        capitalized_types: true,
+        ignore_case: false,
+        line_comments: &["//"],
+        block_comment: Some(("/*", "*/")),
+        quotes: &['"', '\'', '`'],
+        char_literals: false,
+    },
+    Language {
+        name: "python",
+        aliases: &["python", "py", "python3"],
+        keywords: &[
+            "and", "as", "assert", "async", "await", "break", "class",
+            "continue", "def", "del", "elif", "else", "except", "finally",
+            "for", "from", "global", "if", "import", "in", "is", "lambda",
+            "nonlocal", "not", "or", "pass", "raise", "return", "try",
+            "while", "with", "yield",
+        ],
+        literals: &["True", "False", "None"],
+        types: &[],
+        capitalized_types: true,
+        ignore_case: false,
+        line_comments: &["#"],
+        block_comment: None,
+        quotes: &['"', '\''],
+        char_literals: false,
+    },
+    Language {
+        name: "shell",
+        aliases: &["shell", "sh", "bash", "zsh", "shellscript"],
+        keywords: &[
+            "case", "do", "done", "elif", "else", "esac", "export", "fi",
+            "for", "function", "if", "in", "local", "return", "then",
+            "until", "while",
+        ],
+        literals: &["true", "false"],
+        types: &[],
+        capitalized_types: false,
+        ignore_case: false,
+        line_comments: &["#"],
+        block_comment: None,
+        quotes: &['"', '\''],
+        char_literals: false,
+    },
+    Language {
+        name: "json",
+        aliases: &["json"],
+        keywords: &[],
+        literals: &["true", "false", "null"],
+        types: &[],
+        capitalized_types: false,
+        ignore_case: false,
+        line_comments: &[],
+        block_comment: None,
+        quotes: &['"'],
+        char_literals: false,
+    },
+    Language {
+        name: "toml",
+        aliases: &["toml"],
+        keywords: &[],
+        literals: &["true", "false"],
+        types: &[],
+        capitalized_types: false,
+        ignore_case: false,
+        line_comments: &["#"],
+        block_comment: None,
+        quotes: &['"', '\''],
+        char_literals: false,
+    },
+    Language {
+        name: "yaml",
+        aliases: &["yaml", "yml"],
+        keywords: &[],
+        literals: &["true", "false", "null", "yes", "no"],
+        types: &[],
+        capitalized_types: false,
+        ignore_case: false,
+        line_comments: &["#"],
+        block_comment: None,
+        quotes: &['"', '\''],
+        char_literals: false,
+    },
+    Language {
+        name: "css",
+        aliases: &["css"],
+        keywords: &["important"],
+        literals: &[],
+        types: &[],
+        capitalized_types: false,
+        ignore_case: false,
+        line_comments: &[],
+        block_comment: Some(("/*", "*/")),
+        quotes: &['"', '\''],
+        char_literals: false,
+    },
+    Language {
+        name: "sql",
+        aliases: &["sql"],
+        keywords: &[
+            "all", "alter", "and", "as", "asc", "by", "create", "delete",
+            "desc", "distinct", "drop", "from", "group", "having", "in",
+            "index", "inner", "insert", "into", "is", "join", "key", "left",
+            "like", "limit", "not", "offset", "on", "or", "order", "outer",
+            "primary", "references", "right", "select", "set", "table",
+            "union", "update", "values", "where",
+        ],
+        literals: &["true", "false", "null"],
+        types: &[
+            "bigint", "boolean", "char", "date", "integer", "int", "text",
+            "timestamp", "varchar",
+        ],
+        capitalized_types: false,
+        ignore_case: true,
+        line_comments: &["--"],
+        block_comment: Some(("/*", "*/")),
+        quotes: &['\'', '"'],
+        char_literals: false,
+    },
+];
+
+static C_KEYWORDS: &[&str] = &[
+    "break", "case", "const", "continue", "default", "do", "else", "enum",
+    "extern", "for", "goto", "if", "inline", "register", "return", "sizeof",
+    "static", "struct", "switch", "typedef", "union", "volatile", "while",
+];
+
+static C_TYPES: &[&str] = &[
+    "bool", "char", "double", "float", "int", "long", "short", "signed",
+    "size_t", "unsigned", "void",
+];
+
+static JS_KEYWORDS: &[&str] = &[
+    "async", "await", "break", "case", "catch", "class", "const", "continue",
+    "debugger", "default", "delete", "do", "else", "export", "extends",
+    "finally", "for", "from", "function", "if", "import", "in", "instanceof",
+    "let", "new", "of", "return", "static", "super", "switch", "this",
+    "throw", "try", "typeof", "var", "void", "while", "with", "yield",
+];
+
+static JS_LITERALS: &[&str] = &[
+    "true", "false", "null", "undefined", "NaN", "Infinity",
+];
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+
+    /// Highlights the `source`, showing each token as `[kind:text]`.
+    fn tokens(language: &str, source: &str) -> String
+    {
+        Language::find(language).unwrap()
+            .tokens(source)
+            .into_iter()
+            .map(|(token, text)| match token {
+                None => text.to_string(),
+                Some(token) => format!("[{}:{}]", &token.class()[3..], text),
+            })
+            .collect()
+    }
+
+    /// Tests that the tokens of a few languages are found.
+    #[test]
+    fn languages()
+    {
+        assert_eq!(
+            "[keyword:fn] main<'a>() { \
+             [function:println]!([string:\"{}\\\"\"], [string:'\\'']); \
+             [keyword:let] x: [type:Vec]<[type:u8]> = \
+             [number:0x1F]..[number:2]; [comment:// done]\n}",
+            tokens(
+                "rs",
+                "fn main<'a>() { println!(\"{}\\\"\", '\\''); \
+                 let x: Vec<u8> = 0x1F..2; // done\n}"
+            )
+        );
+
+        assert_eq!(
+            "[keyword:def] [function:f](x2): [comment:# hi]\n    \
+             [keyword:return] [literal:None] [keyword:if] x2 [keyword:else] \
+             [string:'it'][string:'s']",
+            tokens(
+                "python",
+                "def f(x2): # hi\n    return None if x2 else 'it''s'"
+            )
+        );
+
+        assert_eq!(
+            "[keyword:SELECT] name [keyword:from] users [keyword:WHERE] id = \
+             [number:1.5] [comment:-- one]\n[comment:/* a\nb */]",
+            tokens(
+                "sql",
+                "SELECT name from users WHERE id = 1.5 -- one\n/* a\nb */"
+            )
+        );
+
+        assert_eq!(
+            "{[string:\"a\"]: [[literal:true], [number:12], [string:\"b]",
+            tokens("json", "{\"a\": [true, 12, \"b")
+        );
+    }
+
+    /// Tests the tokens of the C family of languages.
+    #[test]
+    fn c_languages()
+    {
+        assert_eq!(
+            "[keyword:static] [type:int] [function:f]([type:char] *s) { \
+             [keyword:return] s ? [string:'a'] : [literal:NULL]; } \
+             [comment:/* x */]",
+            tokens(
+                "c",
+                "static int f(char *s) { return s ? 'a' : NULL; } /* x */"
+            )
+        );
+
+        assert_eq!(
+            "[keyword:template]<[keyword:typename] T> [keyword:class] Box { \
+             [type:bool] ok = [literal:nullptr] != [string:\"x\"]; };",
+            tokens(
+                "c++",
+                "template<typename T> class Box { bool ok = nullptr != \"x\"; \
+                 };"
+            )
+        );
+
+        assert_eq!(
+            "[keyword:func] [function:f]() [type:error] { [keyword:return] \
+             [literal:nil] } [comment:// `raw` 'r' 0.5]",
+            tokens("go", "func f() error { return nil } // `raw` 'r' 0.5")
+        );
+
+        assert_eq!(
+            "[keyword:public] [type:String] [function:name]() { \
+             [keyword:return] [literal:null]; } [comment:/** doc */] \
+             [number:1L]",
+            tokens(
+                "java",
+                "public String name() { return null; } /** doc */ 1L"
+            )
+        );
+    }
+
+    /// Tests the tokens of the scripting languages.
+    #[test]
+    fn script_languages()
+    {
+        assert_eq!(
+            "[keyword:const] x = [keyword:new] [function:Map](); \
+             [keyword:let] s = [string:`a${b}`]; \
+             [comment:// undefined NaN]",
+            tokens(
+                "js",
+                "const x = new Map(); let s = `a${b}`; // undefined NaN"
+            )
+        );
+
+        assert_eq!(
+            "[keyword:function] [function:f](x: [type:number]): \
+             [type:Promise]<[keyword:void]> { [keyword:return] x \
+             [keyword:as] [type:any] }",
+            tokens(
+                "ts",
+                "function f(x: number): Promise<void> { return x as any }"
+            )
+        );
+
+        assert_eq!(
+            "[keyword:if] [ -f [string:\"$x\"] ]; [keyword:then] echo \
+             [string:'hi'] [comment:# done]\n[keyword:fi]",
+            tokens("bash", "if [ -f \"$x\" ]; then echo 'hi' # done\nfi")
+        );
+    }
+
+    /// Tests the tokens of the configuration and style languages.
+    #[test]
+    fn data_languages()
+    {
+        assert_eq!(
+            "[package]\nname = [string:'mwf'] [comment:# name]\n\
+             version = [string:\"0.1\"]\nok = [literal:true]",
+            tokens(
+                "toml",
+                "[package]\nname = 'mwf' # name\nversion = \"0.1\"\nok = true"
+            )
+        );
+
+        assert_eq!(
+            "key: [literal:yes] [comment:# comment]\n\
+             list:\n  - [string:\"a\"]\n  - [number:3]",
+            tokens("yml", "key: yes # comment\nlist:\n  - \"a\"\n  - 3")
+        );
+
+        assert_eq!(
+            "a { color: red ![keyword:important]; width: [number:10px] } \
+             [comment:/* x */] [string:\"q\"]",
+            tokens(
+                "css",
+                "a { color: red !important; width: 10px } /* x */ \"q\""
+            )
+        );
+    }
+
+    /// Tests that character literals may be any character, and that other
+    /// quotes are lifetimes or labels.
+    #[test]
+    fn char_literals()
+    {
+        assert_eq!(
+            "[string:'é'] [string:'\\u{e9}'] [string:'日'] 'a: \
+             [keyword:loop] {} &'[keyword:static] [type:str]",
+            tokens("rust", "'é' '\\u{e9}' '日' 'a: loop {} &'static str")
+        );
+    }
+
+    /// Tests that source views are highlighted by their extension or mime
+    /// type, and that others are left alone.
+    #[test]
+    fn decorates()
+    {
+        use stream::BodyStream;
+
+        let dec = Highlight::new();
+
+        let view = View::file("Cargo.toml").unwrap().apply(&dec);
+        assert_eq!("html", view.mime.subtype());
+        assert!(view.content.starts_with(
+            "<pre class=\"highlight\"><code class=\"language-toml\">[package]\n\
+             name = <span class=\"hl-string\">&quot;mwf&quot;</span>"
+        ));
+
+        let view = View::file("src/view.rs").unwrap().apply(&dec);
+        assert!(view.content.contains("class=\"language-rust\""));
+        assert!(view.content.contains("<span class=\"hl-keyword\">use</span>"));
+
+        let mut view = View::raw("echo <b>");
+        view.mime = "application/x-sh".parse().unwrap();
+        assert_eq!(
+            "<pre class=\"highlight\"><code class=\"language-shell\">\
+             echo &lt;b&gt;</code></pre>\n",
+            view.apply(&dec).content
+        );
+
+        let mut view = View::raw("<b>bold</b>");
+        view.mime = "text/html".parse().unwrap();
+        assert_eq!("<b>bold</b>", view.apply(&dec).content);
+
+        let view = View::raw("plain");
+        assert_eq!("plain", view.apply(&dec).content);
+
+        let mut view = View::stream(BodyStream::chunks(vec!["fn main() {}"]));
+        view.mime = "text/x-rust".parse().unwrap();
+        let view = dec.decorate(view);
+        assert!(view.stream.is_some());
+        assert!(view.content.is_empty());
+        assert_eq!("x-rust", view.mime.subtype());
+    }
+
+    /// Tests the inline colours, and the stylesheet for the classes.
+    #[test]
+    fn themes()
+    {
+        let theme = Theme::dark()
+            .background("black")
+            .color(Token::Keyword, "red");
+        let dec = Highlight::new().theme(theme).inline(true);
+
+        assert_eq!(
+            Some(
+                "<pre class=\"highlight\" style=\"background: black; \
+                 color: #abb2bf;\"><code class=\"language-go\">\
+                 <span style=\"color: red;\">go</span> \
+                 <span style=\"color: #61afef;\">f</span>()</code></pre>\n"
+                    .to_string()
+            ),
+            dec.code("Go", "go f()")
+        );
+        assert_eq!(None, dec.code("brainfuck", "+"));
+
+        let css = dec.stylesheet();
+        assert!(css.starts_with(
+            ".highlight { background: black; color: #abb2bf; }\n\
+             .highlight .hl-comment { color: #7f848e; }\n"
+        ));
+        assert!(css.contains(".highlight .hl-keyword { color: red; }\n"));
+    }
+}
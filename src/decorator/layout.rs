@@ -0,0 +1,470 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use decorator::{Decorator, TryDecorator, RequestContext};
+use decorator::template::{self, Context, Node, Scope, Template, Value};
+use error::{Error, Result};
+use view::View;
+
+/// How many templates a layout may extend, one after the other.
+const MAX_DEPTH: usize = 16;
+
+/// The prefix of the metadata keys which fill in a layout's blocks.
+const BLOCK_PREFIX: &str = "block.";
+
+/// Loads templates from a directory by their paths relative to it, and keeps
+/// them once they've been parsed. Templates loaded this way can extend each
+/// other, and include each other as if they were partials.
+///
+/// Cloning the layouts is cheap, and the clones share their cache.
+///
+/// ```rust,no_run
+/// use mwf::{View, decorator};
+/// use mwf::decorator::{Layouts, Context};
+///
+/// // templates/base.html:
+/// //   <title>{{#block title}}My site{{/block}}</title>
+/// //   {{#block head}}{{/block}}
+/// //   <main>{{#block content}}{{/block}}</main>
+/// //
+/// // templates/blog.html:
+/// //   {{extends base.html}}
+/// //   {{#block content}}<article>{{{content}}}</article>{{/block}}
+/// let layouts = Layouts::new("templates");
+/// let blog = layouts.layout("blog.html").unwrap()
+///     .context(Context::new().set("author", "Sam"));
+///
+/// let view = View::raw("<p>The post itself.</p>")
+///     .with_block("title", "Hello")
+///     .with_block("head", "<link rel='stylesheet' href='/post.css'>")
+///     .apply(&blog);
+/// ```
+#[derive(Clone)]
+pub struct Layouts
+{
+    dir: PathBuf,
+    reload: bool,
+    cache: Arc<Mutex<HashMap<String, Cached>>>,
+}
+
+/// A decorator which renders the view into a template from [Layouts], and
+/// every template it extends.
+///
+/// The view fills in the layout's blocks with its `block.<name>` metadata,
+/// such as the page's title or extra stylesheets, which is HTML and isn't
+/// escaped; [View::with_block] sets it. The view's content is never treated
+/// as a template. It fills in the `content` block (unless the view fills it
+/// in itself), and is available as the `content` value too.
+///
+/// All of the view's metadata is available as the `meta` value, so a layout
+/// can fill in its title with a Markdown view's title by including
+/// `{{meta.title}}` in its `title` block, for instance.
+///
+/// If the templates can't be loaded again, the view is left alone. Applied
+/// as a [TryDecorator] instead, the layout fails, and its templates can use
+/// the `request` value: the request's `path`, `method`, `query` and `route`
+/// map.
+pub struct Layout
+{
+    layouts: Layouts,
+    name: String,
+    context: Context,
+}
+
+/// A template in the cache, along with the source it was parsed from.
+struct Cached
+{
+    source: String,
+    template: Arc<Template>,
+}
+
+//
+// Implementation
+//
+
+impl Layouts
+{
+    /// Creates the layouts for the templates in the directory `dir`.
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Self
+    {
+        Layouts {
+            dir: dir.into(),
+            reload: false,
+            cache: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Reads a template's file every time it's used, and parses it again if
+    /// it's changed. This is useful while the templates are being written.
+    pub fn reload(mut self, reload: bool) -> Self
+    {
+        self.reload = reload;
+        self
+    }
+
+    /// Loads the template `name`, or returns it from the cache if it's been
+    /// loaded before.
+    pub fn get(&self, name: &str) -> Result<Arc<Template>>
+    {
+        if !self.reload {
+            if let Some(cached) = self.cache.lock().unwrap().get(name) {
+                return Ok(cached.template.clone());
+            }
+        }
+
+        // modification times are too coarse to notice quick edits, so the
+        // file is compared as a whole
+        let source = fs::read_to_string(self.dir.join(name))?;
+        if let Some(cached) = self.cache.lock().unwrap().get(name) {
+            if cached.source == source {
+                return Ok(cached.template.clone());
+            }
+        }
+
+        let template = Arc::new(Template::new(&source)?);
+
+        self.cache.lock().unwrap().insert(name.to_string(), Cached {
+            source,
+            template: template.clone(),
+        });
+
+        Ok(template)
+    }
+
+    /// Creates a decorator which renders views into the template `name`.
+    /// It's loaded straight away, along with every template it extends, so
+    /// that any problems with them are found early.
+    pub fn layout<T: Into<String>>(&self, name: T) -> Result<Layout>
+    {
+        let name = name.into();
+        self.chain(&name)?;
+
+        Ok(Layout {
+            layouts: self.clone(),
+            name,
+            context: Context::new(),
+        })
+    }
+
+    /// Loads the template `name` and every template it extends, starting
+    /// with `name` itself.
+    fn chain(&self, name: &str) -> Result<Vec<Arc<Template>>>
+    {
+        let mut names = vec![name.to_string()];
+        let mut chain = vec![self.get(name)?];
+
+        loop {
+            let parent = match chain.last().unwrap().parent() {
+                None => break,
+                Some(parent) => parent.to_string(),
+            };
+
+            if names.contains(&parent) || names.len() >= MAX_DEPTH {
+                names.push(parent);
+                let message = format!(
+                    "Templates extend each other: {}",
+                    names.join(" -> ")
+                );
+                return Err(Error::Io(
+                    io::Error::new(io::ErrorKind::InvalidData, message)
+                ));
+            }
+
+            chain.push(self.get(&parent)?);
+            names.push(parent);
+        }
+
+        Ok(chain)
+    }
+}
+
+impl Layout
+{
+    /// Sets the `context` the layout is rendered with. The template's own
+    /// context is ignored.
+    pub fn context(mut self, context: Context) -> Self
+    {
+        self.context = context;
+        self
+    }
+
+    /// Renders the `view` into the `chain` of templates, with the `request`
+    /// value if there is one.
+    fn render(
+        &self,
+        chain: &[Arc<Template>],
+        view: View,
+        request: Option<Value>
+    ) -> View
+    {
+        let content_nodes = [Node::Text(view.content.clone())];
+        let view_blocks: Vec<(&str, [Node; 1])> = view.meta.iter()
+            .filter_map(|(key, value)| {
+                let name = key.strip_prefix(BLOCK_PREFIX)?;
+                Some((name, [Node::Text(value.clone())]))
+            })
+            .collect();
+
+        // the view's blocks take precedence, then its content, then the
+        // blocks of each template from the one the view is rendered into up
+        // to the base
+        let mut blocks: HashMap<String, &[Node]> = HashMap::new();
+        for &(name, ref nodes) in &view_blocks {
+            blocks.entry(name.to_string()).or_insert(&nodes[..]);
+        }
+        blocks.entry("content".to_string()).or_insert(&content_nodes[..]);
+
+        for template in chain {
+            for (name, nodes) in template.blocks() {
+                blocks.entry(name.clone()).or_insert(&nodes[..]);
+            }
+        }
+
+        let root = self.context.to_value();
+        let mut page = template::page_value(view.content.clone(), &view.meta);
+        if let (&mut Value::Map(ref mut page), Some(request)) =
+            (&mut page, request)
+        {
+            page.insert("request".to_string(), request);
+        }
+        let scopes = [Scope::new(&root), Scope::new(&page)];
+
+        let base = chain.last().unwrap();
+        let mut output = String::new();
+        base.renderer(Some(&self.layouts), blocks)
+            .render(base.nodes(), &scopes, 0, &mut output);
+
+        View {
+            content: output,
+            mime: "text/html".parse().unwrap(),
+            ..view
+        }
+    }
+}
+
+impl Decorator for Layout
+{
+    fn decorate(&self, view: View) -> View
+    {
+        // the files were loaded fine when the layout was created, so they
+        // can only fail here if they're being reloaded
+        match self.layouts.chain(&self.name) {
+            Ok(chain) => self.render(&chain, view, None),
+            Err(error) => {
+                warn!("Failed to load the layout {}: {}", self.name, error);
+                view
+            },
+        }
+    }
+}
+
+impl TryDecorator for Layout
+{
+    fn try_decorate(&self, view: View, context: &RequestContext)
+        -> Result<View>
+    {
+        let chain = self.layouts.chain(&self.name)?;
+
+        let request = context.request;
+        let route = context.route_map.iter()
+            .map(|(key, value)| (key.clone(), Value::from(value.as_str())))
+            .collect();
+
+        let mut value = BTreeMap::new();
+        value.insert("path".to_string(), Value::from(request.path()));
+        value.insert(
+            "method".to_string(),
+            Value::from(request.method().to_string())
+        );
+        value.insert(
+            "query".to_string(),
+            Value::from(request.query().unwrap_or(""))
+        );
+        value.insert("route".to_string(), Value::Map(route));
+
+        Ok(self.render(&chain, view, Some(Value::Map(value))))
+    }
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+    use testing::TempDir;
+
+    /// Creates a fresh directory of templates for a test.
+    fn templates(name: &str, files: &[(&str, &str)]) -> TempDir
+    {
+        let dir = TempDir::new(&format!("layouts-{}", name));
+
+        for &(file, source) in files {
+            fs::write(dir.join(file), source).unwrap();
+        }
+
+        dir
+    }
+
+    /// Tests that blocks are overridden through a chain of layouts, and by
+    /// the view itself.
+    #[test]
+    fn nested_layouts()
+    {
+        let dir = templates("nested", &[
+            (
+                "base.html",
+                "<title>{{#block title}}Site{{/block}}</title>\
+                 {{#block head}}{{/block}}\
+                 <body>{{#block body}}{{#block content}}{{/block}}{{/block}}\
+                 {{> footer.html}}</body>",
+            ),
+            ("footer.html", "<footer>{{owner}}</footer>"),
+            (
+                "section.html",
+                "{{extends base.html}}ignored\
+                 {{#block body}}<nav>{{#block sidebar}}Links{{/block}}</nav>\
+                 <main>{{#block content}}{{/block}}</main>{{/block}}",
+            ),
+            (
+                "page.html",
+                "{{extends \"section.html\"}}\
+                 {{#block sidebar}}More links{{/block}}",
+            ),
+        ]);
+
+        let layouts = Layouts::new(dir.path());
+        let page = layouts.layout("page.html").unwrap()
+            .context(Context::new().set("owner", "A & B"));
+
+        let view = View::raw("<p>Hi</p>!")
+            .with_block("title", "Post")
+            .with_block("head", "<link href='post.css'>")
+            .apply(&page);
+
+        assert_eq!(
+            "<title>Post</title><link href='post.css'>\
+             <body><nav>More links</nav><main><p>Hi</p>!</main>\
+             <footer>A &amp; B</footer></body>",
+            view.content
+        );
+        assert_eq!("text/html", view.mime.to_string());
+
+        // only metadata with the block prefix fills in blocks, and the
+        // content is never searched for them
+        let view = View::raw("{{#block head}}<style>{{/block}}Hi")
+            .with_meta("title", "Meta")
+            .with_meta("block.sidebar", "<b>Nav</b>")
+            .apply(&page);
+        assert_eq!(
+            "<title>Site</title>\
+             <body><nav><b>Nav</b></nav>\
+             <main>{{#block head}}<style>{{/block}}Hi</main>\
+             <footer>A &amp; B</footer></body>",
+            view.content
+        );
+
+        // the view can fill in its content block with something else
+        let view = View::raw("Hi")
+            .with_block("content", "Bye")
+            .apply(&page);
+        assert!(view.content.contains("<main>Bye</main>"));
+
+        // the defaults are used when nothing overrides them
+        let base = layouts.layout("base.html").unwrap();
+        let view = View::raw("{{plain}}").apply(&base);
+        assert_eq!(
+            "<title>Site</title><body>{{plain}}<footer></footer></body>",
+            view.content
+        );
+    }
+
+    /// Tests that missing templates and cycles are found when the layout is
+    /// created.
+    #[test]
+    fn invalid_layouts()
+    {
+        let dir = templates("invalid", &[
+            ("a.html", "{{extends b.html}}"),
+            ("b.html", "{{extends a.html}}"),
+            ("c.html", "{{extends missing.html}}"),
+            ("d.html", "{{#if x}}"),
+        ]);
+        let layouts = Layouts::new(dir.path());
+
+        let message = |name: &str| match layouts.layout(name) {
+            Ok(_) => panic!("{} should be invalid", name),
+            Err(error) => error.to_string(),
+        };
+
+        assert_eq!(
+            "Templates extend each other: a.html -> b.html -> a.html",
+            message("a.html")
+        );
+        assert!(layouts.layout("c.html").is_err());
+        assert_eq!(
+            "{{#if}} is never closed on line 1 of the template",
+            message("d.html")
+        );
+    }
+
+    /// Tests that templates are cached, unless they should be reloaded.
+    #[test]
+    fn caching()
+    {
+        let dir = templates("caching", &[("page.html", "one")]);
+        let cached = Layouts::new(dir.path());
+        let reloaded = Layouts::new(dir.path()).reload(true);
+
+        let render = |layouts: &Layouts| {
+            View::raw("").apply(&layouts.layout("page.html").unwrap()).content
+        };
+
+        assert_eq!("one", render(&cached));
+        assert_eq!("one", render(&reloaded));
+
+        // an edit straight away, which likely leaves the file with the same
+        // modification time and length
+        fs::write(dir.join("page.html"), "two").unwrap();
+
+        assert_eq!("one", render(&cached));
+        assert_eq!("two", render(&reloaded));
+    }
+
+    /// Tests that a layout applied as a [TryDecorator] sees the request, and
+    /// fails when its template can't be loaded again.
+    #[test]
+    fn requests()
+    {
+        use hyper::{Method, Request};
+        use resolution::RouteMap;
+
+        let dir = templates("requests", &[(
+            "page.html",
+            "{{request.method}} {{request.path}}?{{request.query}} \
+             {{request.route.id}}: {{{content}}}",
+        )]);
+        let layouts = Layouts::new(dir.path()).reload(true);
+        let page = layouts.layout("page.html").unwrap();
+
+        let request = Request::new(
+            Method::Post,
+            "/posts/1?a=<b>".parse().unwrap()
+        );
+        let mut route_map = RouteMap::new();
+        route_map.insert("id".to_string(), "1".to_string());
+        let context = RequestContext::new(&request, &route_map);
+
+        let view = View::raw("<p>Hi</p>").try_apply(&page, &context).unwrap();
+        assert_eq!("POST /posts/1?a=&lt;b&gt; 1: <p>Hi</p>", view.content);
+
+        // without the request, the values are empty
+        let view = View::raw("<p>Hi</p>").apply(&page);
+        assert_eq!(" ? : <p>Hi</p>", view.content);
+
+        fs::remove_file(dir.join("page.html")).unwrap();
+        assert!(View::raw("Hi").try_apply(&page, &context).is_err());
+        assert_eq!("Hi", View::raw("Hi").apply(&page).content);
+    }
+}
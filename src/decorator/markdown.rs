@@ -0,0 +1,891 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+use pulldown_cmark::{Parser, Event, Tag, Options, html};
+use pulldown_cmark::{OPTION_ENABLE_TABLES, OPTION_ENABLE_FOOTNOTES};
+
+use decorator::{Decorator, FrontMatter, Highlight};
+use decorator::sanitize::safe_url;
+use escape::escape_html;
+use view::View;
+
+/// A decorator which treats the text in the view as markdown and generates
+/// HTML from it. This will also alter the mime type of the view, changing it
+/// to `text/html`.
+///
+/// Any [front matter](FrontMatter) is stripped first and added to the view's
+/// metadata. If the view still has no title, the text of the first top level
+/// heading becomes its title.
+///
+/// ```rust
+/// use mwf::{View, decorator};
+///
+/// let dec = decorator::Markdown::new();
+/// let view = View::raw("# Hello world!").apply(&dec);
+///
+/// assert_eq!("<h1>Hello world!</h1>\n", view.content);
+/// assert_eq!("text", view.mime.type_());
+/// assert_eq!("html", view.mime.subtype());
+/// assert_eq!(Some("Hello world!"), view.title());
+/// ```
+///
+/// Only CommonMark is understood by default, but the GitHub flavoured
+/// extensions, heading anchors, a table of contents and link rewriting can
+/// each be turned on.
+///
+/// ```rust
+/// use mwf::{View, decorator};
+///
+/// let dec = decorator::Markdown::new()
+///     .gfm()
+///     .anchors(true)
+///     .links("");
+/// let view = View::raw("# Set up\n\nSee [the guide](guide.md#start) ~~now~~")
+///     .apply(&dec);
+///
+/// assert_eq!(
+///     "<h1 id=\"set-up\">Set up</h1>\n\
+///      <p>See <a href=\"guide#start\">the guide</a> <del>now</del></p>\n",
+///     view.content
+/// );
+/// ```
+pub struct Markdown
+{
+    /// If GitHub flavoured tables are understood.
+    tables: bool,
+
+    /// If footnotes are understood.
+    footnotes: bool,
+
+    /// If `~~text~~` is struck through.
+    strikethrough: bool,
+
+    /// If list items starting with `[ ]` or `[x]` become checkboxes.
+    task_lists: bool,
+
+    /// If headings are given `id` anchors.
+    anchors: bool,
+
+    /// If a table of contents is generated.
+    toc: bool,
+
+    /// What the `.md` extension of relative links is replaced with, if they
+    /// are rewritten.
+    links: Option<String>,
+
+    /// What fenced code blocks are highlighted with, if they are.
+    highlight: Option<Highlight>,
+
+    /// If raw HTML and unsafe URLs are escaped.
+    safe: bool,
+}
+
+/// The URL schemes allowed in safe mode.
+const SCHEMES: &[&str] = &["http", "https", "mailto"];
+
+/// A heading in the table of contents.
+struct Heading
+{
+    level: i32,
+    anchor: String,
+    text: String,
+}
+
+//
+// Implementation
+//
+
+impl Markdown
+{
+    /// Creates a decorator which only understands CommonMark.
+    pub fn new() -> Self
+    {
+        Markdown {
+            tables: false,
+            footnotes: false,
+            strikethrough: false,
+            task_lists: false,
+            anchors: false,
+            toc: false,
+            links: None,
+            highlight: None,
+            safe: false,
+        }
+    }
+
+    /// Turns on every GitHub flavoured extension: tables, footnotes,
+    /// strikethrough and task lists.
+    pub fn gfm(self) -> Self
+    {
+        self.tables(true)
+            .footnotes(true)
+            .strikethrough(true)
+            .task_lists(true)
+    }
+
+    /// Changes whether GitHub flavoured tables are understood.
+    pub fn tables(mut self, enabled: bool) -> Self
+    {
+        self.tables = enabled;
+        self
+    }
+
+    /// Changes whether footnotes (`[^1]`) are understood.
+    pub fn footnotes(mut self, enabled: bool) -> Self
+    {
+        self.footnotes = enabled;
+        self
+    }
+
+    /// Changes whether text surrounded by `~~` is struck through. The
+    /// markers must be in the same run of text, so they can't surround
+    /// emphasis or links.
+    pub fn strikethrough(mut self, enabled: bool) -> Self
+    {
+        self.strikethrough = enabled;
+        self
+    }
+
+    /// Changes whether list items starting with `[ ]` or `[x]` become
+    /// disabled checkboxes.
+    pub fn task_lists(mut self, enabled: bool) -> Self
+    {
+        self.task_lists = enabled;
+        self
+    }
+
+    /// Changes whether headings are given `id` anchors, made from their
+    /// text. Headings with the same text are numbered, so the first
+    /// `# Usage` is `usage`, and the second `usage-1`.
+    pub fn anchors(mut self, enabled: bool) -> Self
+    {
+        self.anchors = enabled;
+        self
+    }
+
+    /// Changes whether a table of contents linking to every heading is
+    /// generated, which also turns on anchors. It replaces a paragraph of
+    /// just `[TOC]`, or starts the page if there isn't one.
+    pub fn toc(mut self, enabled: bool) -> Self
+    {
+        self.toc = enabled;
+        self
+    }
+
+    /// Rewrites relative links to markdown files so that they link to where
+    /// they're served, by replacing their `.md` extension with `extension`.
+    /// With `""`, `guide/start.md#usage` becomes `guide/start#usage`.
+    pub fn links<T: Into<String>>(mut self, extension: T) -> Self
+    {
+        self.links = Some(extension.into());
+        self
+    }
+
+    /// Highlights fenced code blocks in the language named by their info
+    /// string, such as `rust` in a block starting with ```` ```rust ````.
+    /// Blocks in languages which aren't known are left alone.
+    pub fn highlight(mut self, highlight: Highlight) -> Self
+    {
+        self.highlight = Some(highlight);
+        self
+    }
+
+    /// Changes whether the markdown is treated as untrusted. Any raw HTML in
+    /// it is escaped, so that it shows up as text, and links and images
+    /// lose their URLs unless they're relative or `http`, `https` or
+    /// `mailto` URLs. Use [Sanitize](decorator::Sanitize) instead to allow
+    /// some of the HTML.
+    pub fn safe(mut self, safe: bool) -> Self
+    {
+        self.safe = safe;
+        self
+    }
+
+    /// Removes the `url` of a link or image, if it's unsafe.
+    fn check_url<'a>(&self, url: Cow<'a, str>) -> Cow<'a, str>
+    {
+        if self.safe && !safe_url(&url, SCHEMES) {
+            Cow::Borrowed("")
+        }
+        else {
+            url
+        }
+    }
+
+    /// Rewrites a link's destination, if it's a relative link to a markdown
+    /// file.
+    fn rewrite_link<'a>(&self, dest: Cow<'a, str>) -> Cow<'a, str>
+    {
+        let extension = match self.links {
+            None => return dest,
+            Some(ref extension) => extension,
+        };
+
+        let absolute = dest.starts_with('/')
+            || dest.starts_with('#')
+            || dest.contains("://")
+            || dest.starts_with("mailto:");
+        if absolute {
+            return dest;
+        }
+
+        let end = dest.find(&['#', '?'][..]).unwrap_or(dest.len());
+        if !dest[..end].ends_with(".md") {
+            return dest;
+        }
+
+        Cow::Owned(format!(
+            "{}{}{}",
+            &dest[..end - 3],
+            extension,
+            &dest[end..]
+        ))
+    }
+
+    /// Replaces the `~~` pairs in the `text` with `<del>` tags. Longer runs
+    /// of tildes are left alone, as is text starting or ending in a space.
+    fn strike<'a>(text: Cow<'a, str>, events: &mut Vec<Event<'a>>)
+    {
+        let mut rest: &str = &text;
+        let mut pieces = Vec::new();
+
+        while let Some(start) = marker(rest, 0) {
+            let end = match marker(rest, start + 2) {
+                Some(end) => end,
+                None => break,
+            };
+
+            let inner = &rest[start + 2..end];
+            if inner.is_empty() || inner.trim() != inner {
+                break;
+            }
+
+            pieces.push(Event::Text(Cow::Owned(rest[..start].to_string())));
+            pieces.push(Event::InlineHtml(Cow::Borrowed("<del>")));
+            pieces.push(Event::Text(Cow::Owned(inner.to_string())));
+            pieces.push(Event::InlineHtml(Cow::Borrowed("</del>")));
+            rest = &rest[end + 2..];
+        }
+
+        if pieces.is_empty() {
+            events.push(Event::Text(text));
+            return;
+        }
+
+        pieces.push(Event::Text(Cow::Owned(rest.to_string())));
+        events.extend(pieces);
+    }
+
+    /// Replaces the `[ ]` or `[x]` at the start of the `text` with a
+    /// checkbox, returning the rest of the text.
+    fn task<'a>(text: Cow<'a, str>, events: &mut Vec<Event<'a>>)
+        -> Cow<'a, str>
+    {
+        let checked = if text.starts_with("[ ] ") {
+            false
+        }
+        else if text.starts_with("[x] ") || text.starts_with("[X] ") {
+            true
+        }
+        else {
+            return text;
+        };
+
+        events.push(Event::InlineHtml(Cow::Borrowed(if checked {
+            "<input type=\"checkbox\" disabled=\"\" checked=\"\" /> "
+        }
+        else {
+            "<input type=\"checkbox\" disabled=\"\" /> "
+        })));
+
+        Cow::Owned(text[4..].to_string())
+    }
+}
+
+impl Default for Markdown
+{
+    fn default() -> Self
+    {
+        Markdown::new()
+    }
+}
+
+impl Decorator for Markdown
+{
+    fn decorate(&self, view: View) -> View
+    {
+        let view = FrontMatter.decorate(view);
+
+        let mut options = Options::empty();
+        if self.tables {
+            options.insert(OPTION_ENABLE_TABLES);
+        }
+        if self.footnotes {
+            options.insert(OPTION_ENABLE_FOOTNOTES);
+        }
+
+        let mut events = merge_text(Parser::new_ext(&view.content, options));
+        if self.tables {
+            trim_cells(&mut events);
+        }
+
+        // the title is all of the text in the first <h1>
+        let title = events.iter()
+            .skip_while(|it| !matches!(**it, Event::Start(Tag::Header(1))))
+            .take_while(|it| !matches!(**it, Event::End(Tag::Header(1))))
+            .filter_map(|it| match *it {
+                Event::Text(ref text) => Some(text.as_ref()),
+                _ => None,
+            })
+            .collect::<String>();
+
+        let anchors = self.anchors || self.toc;
+        let mut used = HashMap::new();
+        let mut headings = Vec::new();
+        let mut toc = None;
+
+        // where the start of the heading being converted is in the output,
+        // and its text so far
+        let mut heading: Option<(usize, String)> = None;
+
+        let mut output = Vec::with_capacity(events.len());
+        let mut item = false;
+        let mut events = events.into_iter();
+        while let Some(event) = events.next() {
+            // a task list item's checkbox is in its first text, which may
+            // be in a paragraph
+            let start_of_item = item;
+            item = match event {
+                Event::Start(Tag::Item) => true,
+                Event::Start(Tag::Paragraph) => item,
+                _ => false,
+            };
+
+            if let Some((_, ref mut text)) = heading {
+                if let Event::Text(ref it) = event {
+                    text.push_str(it);
+                }
+            }
+
+            match event {
+                Event::Start(Tag::Header(level)) if anchors => {
+                    heading = Some((output.len(), String::new()));
+                    output.push(Event::Start(Tag::Header(level)));
+                },
+                Event::End(Tag::Header(level)) if anchors => {
+                    if let Some((start, text)) = heading.take() {
+                        let anchor = unique(slug(&text), &mut used);
+                        output[start] = Event::Html(Cow::Owned(
+                            format!("<h{} id=\"{}\">", level, anchor)
+                        ));
+                        headings.push(Heading { level, anchor, text });
+                    }
+                    output.push(Event::End(Tag::Header(level)));
+                },
+                Event::Start(Tag::Paragraph) if self.toc && toc.is_none() => {
+                    // only a paragraph of nothing but the marker is replaced
+                    let marker = match events.as_slice() {
+                        [Event::Text(text), Event::End(Tag::Paragraph), ..] =>
+                            text == "[TOC]",
+                        _ => false,
+                    };
+
+                    if marker {
+                        events.nth(1);
+                        toc = Some(output.len());
+                    }
+                    else {
+                        output.push(event);
+                    }
+                },
+                Event::Start(Tag::CodeBlock(info)) => match self.highlight {
+                    None => output.push(Event::Start(Tag::CodeBlock(info))),
+                    Some(ref highlight) => {
+                        let code = code_text(&mut events);
+                        code_block(highlight, info, code, &mut output);
+                    },
+                },
+                Event::Start(Tag::Link(dest, title)) => {
+                    let dest = self.check_url(self.rewrite_link(dest));
+                    output.push(Event::Start(Tag::Link(dest, title)));
+                },
+                Event::Start(Tag::Image(src, title)) => {
+                    let src = self.check_url(src);
+                    output.push(Event::Start(Tag::Image(src, title)));
+                },
+                Event::Html(html) | Event::InlineHtml(html) if self.safe => {
+                    output.push(Event::Text(html));
+                },
+                Event::Text(text) => {
+                    let text = if self.task_lists && start_of_item {
+                        Markdown::task(text, &mut output)
+                    }
+                    else {
+                        text
+                    };
+
+                    if self.strikethrough {
+                        Markdown::strike(text, &mut output);
+                    }
+                    else {
+                        output.push(Event::Text(text));
+                    }
+                },
+                event => output.push(event),
+            }
+        }
+
+        if self.toc {
+            let contents = table_of_contents(&headings);
+            output.insert(toc.unwrap_or(0), Event::Html(Cow::Owned(contents)));
+        }
+
+        let mut content = String::new();
+        html::push_html(&mut content, output.into_iter());
+
+        let mut meta = view.meta;
+        if !title.is_empty() && !meta.contains_key("title") {
+            meta.insert("title".to_string(), title);
+        }
+
+        // create a new view with the html output and the correct
+        // mime type
+        View {
+            content,
+            mime: "text/html".parse().unwrap(),
+            meta,
+            ..view
+        }
+    }
+}
+
+/// Collects the `events`, joining runs of text, which the parser may split
+/// up around characters it treats specially.
+fn merge_text<'a, I>(events: I) -> Vec<Event<'a>>
+    where I: Iterator<Item = Event<'a>>
+{
+    let mut merged: Vec<Event<'a>> = Vec::new();
+
+    for event in events {
+        if let Event::Text(ref text) = event {
+            if let Some(&mut Event::Text(ref mut previous)) = merged.last_mut()
+            {
+                previous.to_mut().push_str(text);
+                continue;
+            }
+        }
+
+        merged.push(event);
+    }
+
+    merged
+}
+
+/// Finds the next `~~` in the `text` from the index `from`, skipping longer
+/// runs of tildes.
+fn marker(text: &str, from: usize) -> Option<usize>
+{
+    let bytes = text.as_bytes();
+    let mut i = from;
+
+    while i < bytes.len() {
+        if bytes[i] != b'~' {
+            i += 1;
+            continue;
+        }
+
+        let run = bytes[i..].iter().take_while(|&&b| b == b'~').count();
+        if run == 2 {
+            return Some(i);
+        }
+
+        i += run;
+    }
+
+    None
+}
+
+/// Trims the spaces around the text of each table cell, which the parser
+/// leaves in.
+fn trim_cells(events: &mut [Event])
+{
+    for i in 0..events.len() {
+        let (first, last) = match events[i] {
+            Event::Text(_) => (
+                i > 0 && is_cell(&events[i - 1], true),
+                i + 1 < events.len() && is_cell(&events[i + 1], false),
+            ),
+            _ => continue,
+        };
+
+        if let Event::Text(ref mut text) = events[i] {
+            if first && text.starts_with(' ') {
+                *text = Cow::Owned(text.trim_start().to_string());
+            }
+            if last && text.ends_with(' ') {
+                *text = Cow::Owned(text.trim_end().to_string());
+            }
+        }
+    }
+}
+
+/// If the `event` starts (or ends) a table cell.
+fn is_cell(event: &Event, start: bool) -> bool
+{
+    matches!(
+        (event, start),
+        (&Event::Start(Tag::TableCell), true)
+            | (&Event::End(Tag::TableCell), false)
+    )
+}
+
+/// Consumes the events of a code block, up to and including its end,
+/// returning its text.
+fn code_text<'a, I>(events: &mut I) -> String
+    where I: Iterator<Item = Event<'a>>
+{
+    let mut code = String::new();
+
+    for event in events {
+        match event {
+            Event::End(Tag::CodeBlock(_)) => break,
+            Event::Text(ref text) => code.push_str(text),
+            _ => {},
+        }
+    }
+
+    code
+}
+
+/// Adds the events of a code block in the language named by its `info`
+/// string to the `output`, highlighting it if the language is known.
+fn code_block<'a>(
+    highlight: &Highlight,
+    info: Cow<'a, str>,
+    code: String,
+    output: &mut Vec<Event<'a>>
+)
+{
+    let html = {
+        let language = info.split_whitespace().next().unwrap_or("");
+        highlight.code(language, &code)
+    };
+
+    match html {
+        Some(html) => output.push(Event::Html(Cow::Owned(html))),
+        None => {
+            output.push(Event::Start(Tag::CodeBlock(info.clone())));
+            output.push(Event::Text(Cow::Owned(code)));
+            output.push(Event::End(Tag::CodeBlock(info)));
+        },
+    }
+}
+
+/// Turns the text of a heading into an anchor: lowercase letters and digits,
+/// with dashes between words.
+fn slug(text: &str) -> String
+{
+    let mut slug = String::with_capacity(text.len());
+
+    for c in text.trim().chars() {
+        if c.is_alphanumeric() {
+            slug.extend(c.to_lowercase());
+        }
+        else if c == ' ' || c == '-' || c == '_' {
+            slug.push('-');
+        }
+    }
+
+    if slug.is_empty() {
+        "section".to_string()
+    }
+    else {
+        slug
+    }
+}
+
+/// Numbers the `anchor` if it has already been `used`.
+fn unique(anchor: String, used: &mut HashMap<String, usize>) -> String
+{
+    let count = used.entry(anchor.clone()).or_insert(0);
+    *count += 1;
+
+    if *count == 1 {
+        anchor
+    }
+    else {
+        format!("{}-{}", anchor, *count - 1)
+    }
+}
+
+/// Generates the HTML of a table of contents, nesting each heading under the
+/// one before it with a higher level.
+fn table_of_contents(headings: &[Heading]) -> String
+{
+    let mut output = "<nav class=\"toc\">\n".to_string();
+    let mut levels: Vec<i32> = Vec::new();
+
+    for heading in headings {
+        while levels.len() > 1 && heading.level < levels[levels.len() - 1] {
+            levels.pop();
+            output.push_str("</li>\n</ul>\n");
+        }
+
+        match levels.last().cloned() {
+            Some(level) if heading.level <= level => {
+                output.push_str("</li>\n<li>");
+            },
+            _ => {
+                levels.push(heading.level);
+                output.push_str("<ul>\n<li>");
+            },
+        }
+
+        output.push_str(&format!(
+            "<a href=\"#{}\">{}</a>",
+            heading.anchor,
+            escape_html(&heading.text)
+        ));
+    }
+
+    for _ in levels {
+        output.push_str("</li>\n</ul>\n");
+    }
+
+    output.push_str("</nav>\n");
+    output
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+
+    /// Tests the [Markdown] decorator's ability to correctly convert markdown
+    /// source into html, and also change the mime type to `text/html`.
+    #[test]
+    fn markdown()
+    {
+        let dec = Markdown::new();
+        let view = View::raw("# Hello world!").apply(&dec);
+        assert_eq!("<h1>Hello world!</h1>\n", view.content);
+        assert_eq!("text", view.mime.type_());
+        assert_eq!("html", view.mime.subtype());
+    }
+
+    /// Tests that the first top level heading becomes the title, unless the
+    /// view already has one.
+    #[test]
+    fn markdown_title()
+    {
+        let dec = Markdown::new();
+
+        let source = "Intro\n\n## Sub\n\n# The *`main`* title\n\n# Other";
+        let view = View::raw(source).apply(&dec);
+        assert_eq!(Some("The main title"), view.title());
+
+        let view = View::raw("# Heading")
+            .with_meta("title", "Set")
+            .apply(&dec);
+        assert_eq!(Some("Set"), view.title());
+
+        let view = View::raw("No headings").apply(&dec);
+        assert_eq!(None, view.title());
+    }
+
+    /// Tests that front matter is stripped before the markdown is converted,
+    /// and that its title beats the heading's.
+    #[test]
+    fn markdown_front_matter()
+    {
+        let source = "+++\ntitle = 'Front'\ndate = 2024-01-02\n+++\n# Heading";
+        let view = View::raw(source).apply(&Markdown::new());
+
+        assert_eq!("<h1>Heading</h1>\n", view.content);
+        assert_eq!(Some("Front"), view.title());
+        assert_eq!(Some("2024-01-02"), view.meta("date"));
+    }
+
+    /// Tests that the GitHub flavoured extensions are only understood once
+    /// they're turned on.
+    #[test]
+    fn extensions()
+    {
+        let source = "| a | b |\n|---|--:|\n| 1 | 2 |\n\n\
+                      - [ ] todo ~~not~~\n\
+                      - [x] done\n\
+                      - [y] other\n\n\
+                      Note[^1] ~~~ ~~~~\n\n\
+                      [^1]: The note.\n";
+
+        let view = View::raw(source).apply(&Markdown::new());
+        assert!(!view.content.contains("<table>"));
+        assert!(!view.content.contains("<del>"));
+        assert!(!view.content.contains("checkbox"));
+
+        let view = View::raw(source).apply(&Markdown::new().gfm());
+        assert_eq!(
+            "<table><thead><tr><th>a</th><th align=\"right\">b</th></tr>\
+             </thead><tbody>\n\
+             <tr><td>1</td><td align=\"right\">2</td></tr>\n\
+             </tbody></table>\n\
+             <ul>\n\
+             <li><input type=\"checkbox\" disabled=\"\" /> todo <del>not</del>\
+             </li>\n\
+             <li><input type=\"checkbox\" disabled=\"\" checked=\"\" /> done\
+             </li>\n\
+             <li>[y] other</li>\n\
+             </ul>\n\
+             <p>Note<sup class=\"footnote-reference\"><a href=\"#1\">1</a>\
+             </sup> ~~~ ~~~~</p>\n\
+             <div class=\"footnote-definition\" id=\"1\">\
+             <sup class=\"footnote-definition-label\">1</sup>\n\
+             <p>The note.</p>\n\
+             </div>\n",
+            view.content
+        );
+    }
+
+    /// Tests that headings are given unique anchors, and listed in the
+    /// table of contents.
+    #[test]
+    fn table_of_contents()
+    {
+        let source = "# Intro\n\n[TOC]\n\n## Set *up*\n\n### Linux & Mac\n\n\
+                      ## Usage\n\n# Usage\n\n### Deep";
+
+        let view = View::raw(source).apply(&Markdown::new().anchors(true));
+        assert_eq!(
+            "<h1 id=\"intro\">Intro</h1>\n\
+             <p>[TOC]</p>\n\
+             <h2 id=\"set-up\">Set <em>up</em></h2>\n\
+             <h3 id=\"linux--mac\">Linux &amp; Mac</h3>\n\
+             <h2 id=\"usage\">Usage</h2>\n\
+             <h1 id=\"usage-1\">Usage</h1>\n\
+             <h3 id=\"deep\">Deep</h3>\n",
+            view.content
+        );
+        assert_eq!(Some("Intro"), view.title());
+
+        let view = View::raw(source).apply(&Markdown::new().toc(true));
+        assert_eq!(
+            "<h1 id=\"intro\">Intro</h1>\n\
+             <nav class=\"toc\">\n\
+             <ul>\n<li><a href=\"#intro\">Intro</a>\
+             <ul>\n<li><a href=\"#set-up\">Set up</a>\
+             <ul>\n<li><a href=\"#linux--mac\">Linux &amp; Mac</a>\
+             </li>\n</ul>\n\
+             </li>\n<li><a href=\"#usage\">Usage</a>\
+             </li>\n</ul>\n\
+             </li>\n<li><a href=\"#usage-1\">Usage</a>\
+             <ul>\n<li><a href=\"#deep\">Deep</a>\
+             </li>\n</ul>\n\
+             </li>\n</ul>\n\
+             </nav>\n\
+             <h2 id=\"set-up\">Set <em>up</em></h2>\n\
+             <h3 id=\"linux--mac\">Linux &amp; Mac</h3>\n\
+             <h2 id=\"usage\">Usage</h2>\n\
+             <h1 id=\"usage-1\">Usage</h1>\n\
+             <h3 id=\"deep\">Deep</h3>\n",
+            view.content
+        );
+
+        let view = View::raw("Text\n\n## A").apply(&Markdown::new().toc(true));
+        assert_eq!(
+            "<nav class=\"toc\">\n\
+             <ul>\n<li><a href=\"#a\">A</a></li>\n</ul>\n\
+             </nav>\n\
+             <p>Text</p>\n\
+             <h2 id=\"a\">A</h2>\n",
+            view.content
+        );
+
+        let view = View::raw("[TOC]\nmore").apply(&Markdown::new().toc(true));
+        assert_eq!(
+            "<nav class=\"toc\">\n</nav>\n\
+             <p>[TOC]\nmore</p>\n",
+            view.content
+        );
+    }
+
+    /// Tests that fenced code blocks are highlighted by their info string,
+    /// unless the language isn't known.
+    #[test]
+    fn highlighting()
+    {
+        let source = "```rust ignore\nlet x = \"<\";\n```\n\n\
+                      ```klingon\nqapla'\n```\n\n    indented";
+
+        let view = View::raw(source).apply(&Markdown::new());
+        assert!(!view.content.contains("highlight"));
+
+        let dec = Markdown::new().highlight(Highlight::new());
+        assert_eq!(
+            "<pre class=\"highlight\"><code class=\"language-rust\">\
+             <span class=\"hl-keyword\">let</span> x = \
+             <span class=\"hl-string\">&quot;&lt;&quot;</span>;\n\
+             </code></pre>\n\
+             <pre><code class=\"language-klingon\">qapla'\n</code></pre>\n\
+             <pre><code>indented</code></pre>\n",
+            View::raw(source).apply(&dec).content
+        );
+    }
+
+    /// Tests that safe mode escapes raw HTML and removes unsafe URLs.
+    #[test]
+    fn safe()
+    {
+        let source = "<div onclick=\"x()\">\n\n*hi*\n\n</div>\n\n\
+                      a <b>b</b> [c](javascript:x()) [d](http://d.com) \
+                      ![e](data:x)";
+
+        let view = View::raw(source).apply(&Markdown::new());
+        assert!(view.content.contains("<b>b</b>"));
+        assert!(view.content.contains("javascript:"));
+
+        let view = View::raw(source).apply(&Markdown::new().safe(true));
+        assert_eq!(
+            "&lt;div onclick=&quot;x()&quot;&gt;\n\
+             <p><em>hi</em></p>\n\
+             &lt;/div&gt;\n\
+             <p>a &lt;b&gt;b&lt;/b&gt; <a href=\"\">c</a> \
+             <a href=\"http://d.com\">d</a> <img src=\"\" alt=\"e\" /></p>\n",
+            view.content
+        );
+
+        let source = "# <b>x</b> [y](javascript:y()) [z](z.md)";
+        let dec = Markdown::new().safe(true).toc(true).links("");
+        assert_eq!(
+            "<nav class=\"toc\">\n\
+             <ul>\n<li><a href=\"#x-y-z\">x y z</a></li>\n</ul>\n\
+             </nav>\n\
+             <h1 id=\"x-y-z\">&lt;b&gt;x&lt;/b&gt; <a href=\"\">y</a> \
+             <a href=\"z\">z</a></h1>\n",
+            View::raw(source).apply(&dec).content
+        );
+    }
+
+    /// Tests that only relative links to markdown files are rewritten.
+    #[test]
+    fn links()
+    {
+        let source = "[a](guide.md) [b](../api/view.md#apply) [c](x.md?raw) \
+                      [d](/abs.md) [e](https://example.com/x.md) \
+                      [f](#local) [g](notes.txt)";
+
+        let view = View::raw(source).apply(&Markdown::new());
+        assert!(view.content.contains("href=\"guide.md\""));
+
+        let view = View::raw(source).apply(&Markdown::new().links(".html"));
+        assert_eq!(
+            "<p><a href=\"guide.html\">a</a> \
+             <a href=\"../api/view.html#apply\">b</a> \
+             <a href=\"x.html?raw\">c</a> \
+             <a href=\"/abs.md\">d</a> \
+             <a href=\"https://example.com/x.md\">e</a> \
+             <a href=\"#local\">f</a> \
+             <a href=\"notes.txt\">g</a></p>\n",
+            view.content
+        );
+    }
+}
@@ -0,0 +1,680 @@
+use decorator::Decorator;
+use view::View;
+
+/// A decorator which removes the comments and whitespace a browser doesn't
+/// need from HTML, CSS and JavaScript views, chosen by their mime type.
+/// Views of any other type are left alone.
+///
+/// HTML keeps its conditional comments, and the contents of its `<pre>` and
+/// `<textarea>` elements. Inline scripts and styles are minified as
+/// JavaScript and CSS, unless a script's `type` isn't JavaScript, as in
+/// `<script type="text/template">`, when it's left alone.
+///
+/// The minification is conservative: whitespace is only removed where it
+/// can't change how the page is shown or how the script runs, so a space
+/// between two inline elements is kept, as are newlines in JavaScript which
+/// might end a statement.
+///
+/// ```rust
+/// use mwf::{View, decorator};
+///
+/// let mut view = View::raw(
+///     "<ul>\n  <li>One <!-- first -->  item</li>\n  <li>Two</li>\n</ul>\n"
+/// );
+/// view.mime = "text/html".parse().unwrap();
+/// let view = view.apply(&decorator::Minify::new());
+///
+/// assert_eq!("<ul><li>One item</li><li>Two</li></ul>", view.content);
+/// ```
+pub struct Minify
+{
+    /// If HTML views are minified.
+    html: bool,
+
+    /// If CSS views and styles are minified.
+    css: bool,
+
+    /// If JavaScript views and scripts are minified.
+    js: bool,
+}
+
+/// The elements whose surrounding whitespace is never shown.
+const BLOCKS: &[&str] = &[
+    "address", "article", "aside", "base", "blockquote", "body", "br",
+    "caption", "col", "colgroup", "dd", "details", "dialog", "div", "dl",
+    "dt", "fieldset", "figcaption", "figure", "footer", "form", "h1", "h2",
+    "h3", "h4", "h5", "h6", "head", "header", "hr", "html", "legend", "li",
+    "link", "main", "meta", "nav", "ol", "option", "p", "pre", "script",
+    "section", "style", "summary", "table", "tbody", "td", "tfoot", "th",
+    "thead", "title", "tr", "ul",
+];
+
+/// The keywords after which a `/` starts a regular expression, rather than
+/// dividing.
+const BEFORE_REGEX: &[&str] = &[
+    "return", "typeof", "instanceof", "in", "of", "new", "delete", "void",
+    "throw", "case", "do", "else", "yield", "await",
+];
+
+//
+// Implementation
+//
+
+impl Minify
+{
+    /// Creates a decorator which minifies every type it knows.
+    pub fn new() -> Self
+    {
+        Minify {
+            html: true,
+            css: true,
+            js: true,
+        }
+    }
+
+    /// Changes whether HTML views are minified.
+    pub fn html(mut self, enabled: bool) -> Self
+    {
+        self.html = enabled;
+        self
+    }
+
+    /// Changes whether CSS views, and styles in HTML, are minified.
+    pub fn css(mut self, enabled: bool) -> Self
+    {
+        self.css = enabled;
+        self
+    }
+
+    /// Changes whether JavaScript views, and scripts in HTML, are minified.
+    pub fn js(mut self, enabled: bool) -> Self
+    {
+        self.js = enabled;
+        self
+    }
+
+    /// Minifies the `source` HTML.
+    fn minify_html(&self, source: &str) -> String
+    {
+        let mut output = String::with_capacity(source.len());
+        let mut rest = source;
+
+        // if whitespace here wouldn't be shown
+        let mut block = true;
+
+        // if the output ends with the contents of a raw element, like <pre>,
+        // whose whitespace has to be kept
+        let mut raw = false;
+
+        while !rest.is_empty() {
+            let start = rest.find('<').unwrap_or(rest.len());
+            if start > 0 {
+                block = push_text(&mut output, &rest[..start], block);
+                rest = &rest[start..];
+                continue;
+            }
+
+            if rest.starts_with("<!--") {
+                let end = rest.find("-->").map_or(rest.len(), |it| it + 3);
+                let comment = &rest[..end];
+                rest = &rest[end..];
+
+                if comment.starts_with("<!--[if")
+                    || comment.starts_with("<!--<![endif]")
+                {
+                    trim_end(&mut output);
+                    output.push_str(comment);
+                    block = true;
+                }
+                continue;
+            }
+
+            let end = match tag_end(rest) {
+                None => {
+                    block = push_text(&mut output, "<", block);
+                    rest = &rest[1..];
+                    continue;
+                },
+                Some(end) => end,
+            };
+
+            let tag = &rest[..end];
+            rest = &rest[end..];
+
+            let name = tag_name(tag);
+            if tag.starts_with("<!") || BLOCKS.contains(&name.as_str()) {
+                if !raw {
+                    trim_end(&mut output);
+                }
+                block = true;
+            }
+            else {
+                block = false;
+            }
+            output.push_str(&collapse_tag(tag));
+
+            // the contents of these elements aren't html
+            let elements = ["pre", "textarea", "script", "style"];
+            raw = !tag.starts_with("</") && elements.contains(&name.as_str());
+            if !raw {
+                continue;
+            }
+
+            let close = rest.to_ascii_lowercase()
+                .find(&format!("</{}", name))
+                .unwrap_or(rest.len());
+            let contents = &rest[..close];
+            rest = &rest[close..];
+
+            match name.as_str() {
+                "script" if self.js && is_javascript(tag) => {
+                    output.push_str(&minify_js(contents));
+                },
+                "style" if self.css => output.push_str(&minify_css(contents)),
+                _ => output.push_str(contents),
+            }
+        }
+
+        trim_end(&mut output);
+        output
+    }
+}
+
+impl Default for Minify
+{
+    fn default() -> Self
+    {
+        Minify::new()
+    }
+}
+
+impl Decorator for Minify
+{
+    fn decorate(&self, view: View) -> View
+    {
+        let content = {
+            let subtype = view.mime.subtype().as_str();
+            match subtype {
+                "html" if self.html => self.minify_html(&view.content),
+                "css" if self.css => minify_css(&view.content),
+                "javascript" | "x-javascript" | "ecmascript" if self.js => {
+                    minify_js(&view.content)
+                },
+                _ => return view,
+            }
+        };
+
+        View {
+            content,
+            ..view
+        }
+    }
+}
+
+/// Adds the `text` between tags to the `output`, collapsing its whitespace,
+/// and removing it entirely after a `block`. Returns if whitespace would
+/// still not be shown after the text.
+fn push_text(output: &mut String, text: &str, block: bool) -> bool
+{
+    let mut space = false;
+    let mut block = block;
+
+    for c in text.chars() {
+        if c.is_whitespace() {
+            space = true;
+            continue;
+        }
+
+        if space && !block && !output.ends_with(' ') {
+            output.push(' ');
+        }
+        output.push(c);
+        space = false;
+        block = false;
+    }
+
+    if space && !block && !output.ends_with(' ') {
+        output.push(' ');
+    }
+
+    block
+}
+
+/// Removes the whitespace at the end of the `output`.
+fn trim_end(output: &mut String)
+{
+    let length = output.trim_end().len();
+    output.truncate(length);
+}
+
+/// The length of the tag at the start of the `html`, if it is one.
+fn tag_end(html: &str) -> Option<usize>
+{
+    let mut chars = html.chars().skip(1);
+    match chars.next() {
+        Some('/') | Some('!') | Some('?') => {},
+        Some(c) if c.is_ascii_alphabetic() => {},
+        _ => return None,
+    }
+
+    let mut quote = None;
+    for (i, c) in html.char_indices().skip(1) {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => {},
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if c == '>' => return Some(i + 1),
+            None => {},
+        }
+    }
+
+    None
+}
+
+/// The lowercase name of the `tag`.
+fn tag_name(tag: &str) -> String
+{
+    tag.trim_start_matches(&['<', '/', '!'][..])
+        .chars()
+        .take_while(|c| c.is_ascii_alphanumeric())
+        .collect::<String>()
+        .to_ascii_lowercase()
+}
+
+/// Collapses the whitespace between the attributes of the `tag`.
+fn collapse_tag(tag: &str) -> String
+{
+    let mut output = String::with_capacity(tag.len());
+    let mut quote = None;
+    let mut space = false;
+
+    for c in tag.chars() {
+        if quote.is_none() && c.is_whitespace() {
+            space = true;
+            continue;
+        }
+
+        if space && c != '>' && !(c == '/' && tag.ends_with("/>")) {
+            output.push(' ');
+        }
+        space = false;
+
+        match quote {
+            Some(q) if c == q => quote = None,
+            None if c == '"' || c == '\'' => quote = Some(c),
+            _ => {},
+        }
+        output.push(c);
+    }
+
+    output
+}
+
+/// If the script `tag` has no type, or a JavaScript one.
+fn is_javascript(tag: &str) -> bool
+{
+    let lower = tag.to_ascii_lowercase();
+    let start = match lower.find("type") {
+        None => return true,
+        Some(start) => start + 4,
+    };
+
+    let kind = lower[start..]
+        .trim_start()
+        .trim_start_matches('=')
+        .trim_start()
+        .trim_start_matches(&['"', '\''][..]);
+
+    ["text/javascript", "application/javascript", "module"].iter()
+        .any(|it| kind.starts_with(it))
+}
+
+/// Minifies the `source` CSS.
+fn minify_css(source: &str) -> String
+{
+    let chars: Vec<char> = source.chars().collect();
+    let mut output = String::with_capacity(source.len());
+    let mut space = false;
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+
+        if c == '/' && chars.get(i + 1) == Some(&'*') {
+            let end = find(&chars, i + 2, &['*', '/'])
+                .map_or(chars.len(), |it| it + 2);
+
+            // comments marked with a ! are kept, as they're usually licenses
+            if chars.get(i + 2) == Some(&'!') {
+                output.extend(&chars[i..end]);
+            }
+            else {
+                space = true;
+            }
+
+            i = end;
+            continue;
+        }
+
+        if c.is_whitespace() {
+            space = true;
+            i += 1;
+            continue;
+        }
+
+        if space {
+            let keep = match output.chars().last() {
+                None => false,
+                Some(previous) => !"{};:,>~(/".contains(previous)
+                    && !"{};,>~)!".contains(c),
+            };
+            if keep {
+                output.push(' ');
+            }
+            space = false;
+        }
+
+        if c == '}' && output.ends_with(';') {
+            output.pop();
+        }
+
+        if c == '"' || c == '\'' {
+            let end = string_end(&chars, i);
+            output.extend(&chars[i..end]);
+            i = end;
+            continue;
+        }
+
+        output.push(c);
+        i += 1;
+    }
+
+    output
+}
+
+/// Minifies the `source` JavaScript.
+fn minify_js(source: &str) -> String
+{
+    let chars: Vec<char> = source.chars().collect();
+    let mut output = String::with_capacity(source.len());
+    let mut space = false;
+    let mut newline = false;
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).cloned();
+
+        if c == '/' && next == Some('/') {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+            space = true;
+            continue;
+        }
+
+        if c == '/' && next == Some('*') {
+            let end = find(&chars, i + 2, &['*', '/'])
+                .map_or(chars.len(), |it| it + 2);
+            newline |= chars[i..end].contains(&'\n');
+            space = true;
+            i = end;
+            continue;
+        }
+
+        if c.is_whitespace() {
+            newline |= c == '\n' || c == '\r' || c == '\u{2028}';
+            space = true;
+            i += 1;
+            continue;
+        }
+
+        if space {
+            if let Some(previous) = output.chars().last() {
+                if keep_space(previous, c, newline) {
+                    output.push(if newline { '\n' } else { ' ' });
+                }
+            }
+            space = false;
+            newline = false;
+        }
+
+        let end = match c {
+            '"' | '\'' | '`' => string_end(&chars, i),
+            '/' if regex_allowed(&output) => regex_end(&chars, i),
+            _ => i + 1,
+        };
+
+        output.extend(&chars[i..end]);
+        i = end;
+    }
+
+    output
+}
+
+/// If the whitespace between the `previous` and `next` characters of
+/// JavaScript has to be kept, as a newline if it's a `newline`.
+fn keep_space(previous: char, next: char, newline: bool) -> bool
+{
+    let word = |c: char| c.is_alphanumeric() || c == '_' || c == '$'
+        || !c.is_ascii();
+
+    if word(previous) && word(next) {
+        return true;
+    }
+
+    // these would join into different operators, comments or a method call
+    // on a number
+    let joins = (previous == '+' && next == '+')
+        || (previous == '-' && next == '-')
+        || (previous == '/' && (next == '/' || next == '*'))
+        || (previous.is_ascii_digit() && next == '.');
+    if joins {
+        return true;
+    }
+
+    // a newline might end a statement, unless the line can't end here or
+    // the next one can't start here
+    newline
+        && !"{([,;:=&|?!*%<>^~+-".contains(previous)
+        && !"})],;.?:=&|*%<>^".contains(next)
+}
+
+/// If a `/` after the `output` starts a regular expression.
+fn regex_allowed(output: &str) -> bool
+{
+    let previous = match output.chars().last() {
+        None => return true,
+        Some(previous) => previous,
+    };
+
+    if "(,=:[!&|?{};+-*%<>~^".contains(previous) {
+        return true;
+    }
+
+    let word: String = output.chars().rev()
+        .take_while(|c| c.is_alphanumeric() || *c == '_' || *c == '$')
+        .collect::<Vec<_>>()
+        .into_iter()
+        .rev()
+        .collect();
+
+    BEFORE_REGEX.contains(&word.as_str())
+}
+
+/// The end of the string starting at `start`, after its closing quote.
+fn string_end(chars: &[char], start: usize) -> usize
+{
+    let quote = chars[start];
+    let mut i = start + 1;
+
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 1,
+            c if c == quote => return i + 1,
+            '\n' if quote != '`' => return i,
+            _ => {},
+        }
+        i += 1;
+    }
+
+    chars.len()
+}
+
+/// The end of the regular expression starting at `start`, after its closing
+/// `/`.
+fn regex_end(chars: &[char], start: usize) -> usize
+{
+    let mut class = false;
+    let mut i = start + 1;
+
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 1,
+            '[' => class = true,
+            ']' => class = false,
+            '/' if !class => return i + 1,
+            '\n' => return i,
+            _ => {},
+        }
+        i += 1;
+    }
+
+    chars.len()
+}
+
+/// Finds the `pattern` in the `chars`, from the index `from`.
+fn find(chars: &[char], from: usize, pattern: &[char]) -> Option<usize>
+{
+    if from > chars.len() {
+        return None;
+    }
+
+    chars[from..].windows(pattern.len())
+        .position(|it| it == pattern)
+        .map(|it| from + it)
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+
+    /// Tests that HTML keeps its conditional comments, its preformatted text
+    /// and only the whitespace which is shown.
+    #[test]
+    fn html()
+    {
+        let source = "<!DOCTYPE html>\n\
+            <html>\n\
+              <head>\n\
+                <!-- a comment -->\n\
+                <!--[if IE]><p>IE</p><![endif]-->\n\
+                <style>\n\
+                  body > p { color: red ; }\n\
+                </style>\n\
+              </head>\n\
+              <body  class = \"a  b\" >\n\
+                <p>Some   <b>bold</b>\n\
+                   text <br/> x</p>\n\
+                <pre>  keep\n   this </pre>\n\
+                <textarea>  and  this </textarea>\n\
+                <script>\n\
+                  // greet\n\
+                  if (a < b) { alert(\"<!-- hi -->\"); }\n\
+                </script>\n\
+                <SCRIPT type='text/template'> <p>  raw </p> </SCRIPT>\n\
+              </body>\n\
+            </html>\n";
+
+        assert_eq!(
+            "<!DOCTYPE html><html><head><!--[if IE]><p>IE</p><![endif]-->\
+             <style>body>p{color:red}</style></head>\
+             <body class = \"a  b\"><p>Some <b>bold</b> text<br/>x</p>\
+             <pre>  keep\n   this </pre><textarea>  and  this </textarea>\
+             <script>if(a<b){alert(\"<!-- hi -->\");}</script>\
+             <SCRIPT type='text/template'> <p>  raw </p> </SCRIPT>\
+             </body></html>",
+            Minify::new().minify_html(source)
+        );
+
+        assert_eq!(
+            "<b>a</b> <i>b</i> 1 < 2",
+            Minify::new().minify_html("  <b>a</b>\n <i>b</i> 1 < 2 ")
+        );
+    }
+
+    /// Tests that CSS keeps its strings, licenses and the spaces selectors
+    /// need.
+    #[test]
+    fn css()
+    {
+        let source = "/*! License */\n\
+            /* comment */\n\
+            @media screen and (max-width: 10px) {\n\
+              a:hover , div  :first-child ~ p {\n\
+                content: \"a  ;  b\";\n\
+                margin: 0 auto !important;\n\
+                width: calc(100% - 2px);\n\
+              }\n\
+            }\n";
+
+        assert_eq!(
+            "/*! License */@media screen and (max-width:10px){a:hover,div \
+             :first-child~p{content:\"a  ;  b\";margin:0 auto!important;\
+             width:calc(100% - 2px)}}",
+            minify_css(source)
+        );
+    }
+
+    /// Tests that JavaScript keeps its strings, regular expressions and the
+    /// newlines which might end statements.
+    #[test]
+    fn javascript()
+    {
+        let source = "/* header */\n\
+            var a = 1 // one\n\
+            var b = a + +2 - -3\n\
+            a\n\
+            ++b\n\
+            let re = /[/]\\/ x/g, c = a / 2 / 1;\n\
+            return /x/.test(`multi\n   line ${a}`) + 1 .toString() + 'it\\'s'\n\
+            x = {\n\
+              y: [1, 2],\n\
+            }\n\
+            f()\n";
+
+        assert_eq!(
+            "var a=1\n\
+             var b=a+ +2- -3\n\
+             a\n\
+             ++b\n\
+             let re=/[/]\\/ x/g,c=a/2/1;\
+             return/x/.test(`multi\n   line ${a}`)+1 .toString()+'it\\'s'\n\
+             x={y:[1,2],}\n\
+             f()",
+            minify_js(source)
+        );
+    }
+
+    /// Tests that views are minified by their mime type, and only when
+    /// their type is enabled.
+    #[test]
+    fn decorates()
+    {
+        let view = |mime: &str| {
+            let mut view = View::raw(" a {  } ");
+            view.mime = mime.parse().unwrap();
+            view
+        };
+
+        let dec = Minify::new();
+        assert_eq!("a { }", view("text/html").apply(&dec).content);
+        assert_eq!("a{}", view("text/css").apply(&dec).content);
+        assert_eq!("a{}", view("application/javascript").apply(&dec).content);
+        assert_eq!(" a {  } ", view("text/plain").apply(&dec).content);
+
+        let dec = Minify::new().css(false);
+        assert_eq!(" a {  } ", view("text/css").apply(&dec).content);
+    }
+}
@@ -0,0 +1,624 @@
+use std::collections::{HashMap, HashSet};
+
+use decorator::Decorator;
+use escape::escape_html;
+use view::View;
+
+/// A decorator which makes untrusted HTML safe to serve, by removing every
+/// tag, attribute and URL which its allow-list doesn't permit. Only
+/// `text/html` views are changed.
+///
+/// By default the tags which [Markdown](decorator::Markdown) generates are
+/// allowed, with links and images limited to `http`, `https` and `mailto`
+/// URLs, or relative ones. Event handler attributes such as `onclick` are
+/// never allowed, and comments are always removed.
+///
+/// Tags which aren't allowed are stripped, leaving their contents, except
+/// for tags like `<script>` and `<style>` whose contents are removed as well.
+/// They can be escaped instead, so that they show up as text.
+///
+/// ```rust
+/// use mwf::{View, decorator};
+///
+/// let dec = decorator::Sanitize::new();
+/// let mut view = View::raw(
+///     "<p onclick='steal()'>Hi <a href='javascript:steal()'>there</a>\
+///      <script>steal()</script><blink>!</blink>"
+/// );
+/// view.mime = "text/html".parse().unwrap();
+///
+/// assert_eq!("<p>Hi <a>there</a>!</p>", view.apply(&dec).content);
+/// ```
+#[derive(Clone)]
+pub struct Sanitize
+{
+    /// Every allowed tag, and the attributes allowed on it.
+    tags: HashMap<String, HashSet<String>>,
+
+    /// The attributes allowed on every tag.
+    attributes: HashSet<String>,
+
+    /// The allowed schemes of URLs.
+    schemes: Vec<String>,
+
+    /// If tags which aren't allowed are escaped, rather than stripped.
+    escape: bool,
+}
+
+/// The tags whose contents are removed along with them.
+const DANGEROUS: &[&str] = &[
+    "script", "style", "iframe", "object", "embed", "noscript", "template",
+    "title", "textarea", "xmp", "svg", "math", "noembed", "noframes",
+];
+
+/// The tags which have no contents or end tag.
+const VOID: &[&str] = &["br", "hr", "img", "input", "wbr", "col"];
+
+/// The attributes which hold URLs.
+const URLS: &[&str] = &[
+    "href", "src", "cite", "action", "formaction", "poster", "background",
+    "longdesc", "xlink:href",
+];
+
+/// The tags allowed by default, and their attributes.
+const DEFAULT_TAGS: &[(&str, &[&str])] = &[
+    ("a", &["href", "title"]),
+    ("abbr", &["title"]),
+    ("b", &[]),
+    ("blockquote", &["cite"]),
+    ("br", &[]),
+    ("code", &["class"]),
+    ("dd", &[]),
+    ("del", &[]),
+    ("div", &["class", "id"]),
+    ("dl", &[]),
+    ("dt", &[]),
+    ("em", &[]),
+    ("h1", &["id"]),
+    ("h2", &["id"]),
+    ("h3", &["id"]),
+    ("h4", &["id"]),
+    ("h5", &["id"]),
+    ("h6", &["id"]),
+    ("hr", &[]),
+    ("i", &[]),
+    ("img", &["src", "alt", "title", "width", "height"]),
+    ("input", &["type", "disabled", "checked"]),
+    ("kbd", &[]),
+    ("li", &[]),
+    ("ol", &["start"]),
+    ("p", &[]),
+    ("pre", &["class"]),
+    ("s", &[]),
+    ("span", &["class"]),
+    ("strong", &[]),
+    ("sub", &[]),
+    ("sup", &["class"]),
+    ("table", &[]),
+    ("tbody", &[]),
+    ("td", &["align"]),
+    ("th", &["align"]),
+    ("thead", &[]),
+    ("tr", &[]),
+    ("ul", &[]),
+];
+
+//
+// Implementation
+//
+
+impl Sanitize
+{
+    /// Creates a decorator with the default allow-list.
+    pub fn new() -> Self
+    {
+        let tags = DEFAULT_TAGS.iter()
+            .map(|&(tag, attributes)| (
+                tag.to_string(),
+                attributes.iter().map(|it| it.to_string()).collect(),
+            ))
+            .collect();
+
+        Sanitize {
+            tags,
+            attributes: ["title"].iter().map(|it| it.to_string()).collect(),
+            schemes: ["http", "https", "mailto"].iter()
+                .map(|it| it.to_string())
+                .collect(),
+            escape: false,
+        }
+    }
+
+    /// Creates a decorator which allows nothing, so that every tag is
+    /// stripped, to be built up with [Sanitize::allow_tag].
+    pub fn empty() -> Self
+    {
+        Sanitize {
+            tags: HashMap::new(),
+            attributes: HashSet::new(),
+            schemes: Vec::new(),
+            escape: false,
+        }
+    }
+
+    /// Allows the `tag`, with the given `attributes`, in addition to any it
+    /// already allowed.
+    pub fn allow_tag(mut self, tag: &str, attributes: &[&str]) -> Self
+    {
+        self.tags.entry(tag.to_lowercase())
+            .or_default()
+            .extend(attributes.iter().map(|it| it.to_lowercase()));
+        self
+    }
+
+    /// Stops allowing the `tag`.
+    pub fn deny_tag(mut self, tag: &str) -> Self
+    {
+        self.tags.remove(&tag.to_lowercase());
+        self
+    }
+
+    /// Allows the `attribute` on every allowed tag. Event handlers, like
+    /// `onclick`, are never allowed.
+    pub fn allow_attribute(mut self, attribute: &str) -> Self
+    {
+        self.attributes.insert(attribute.to_lowercase());
+        self
+    }
+
+    /// Changes which URL schemes are allowed, such as `https`. Relative URLs
+    /// are always allowed, and attributes with other URLs are removed.
+    pub fn schemes(mut self, schemes: &[&str]) -> Self
+    {
+        self.schemes = schemes.iter().map(|it| it.to_lowercase()).collect();
+        self
+    }
+
+    /// Changes whether tags which aren't allowed are escaped, rather than
+    /// stripped.
+    pub fn escape(mut self, escape: bool) -> Self
+    {
+        self.escape = escape;
+        self
+    }
+
+    /// Sanitizes the `html`.
+    pub fn clean(&self, html: &str) -> String
+    {
+        let mut output = String::with_capacity(html.len());
+        let mut open: Vec<String> = Vec::new();
+        let mut rest = html;
+
+        while let Some(start) = rest.find('<') {
+            push_text(&mut output, &rest[..start]);
+            rest = &rest[start..];
+
+            // comments, doctypes and processing instructions
+            if rest.starts_with("<!--") {
+                rest = rest.find("-->").map_or("", |it| &rest[it + 3..]);
+                continue;
+            }
+            if rest.starts_with("<!") || rest.starts_with("<?") {
+                rest = rest.find('>').map_or("", |it| &rest[it + 1..]);
+                continue;
+            }
+
+            let tag = match Tag::parse(rest) {
+                None => {
+                    output.push_str("&lt;");
+                    rest = &rest[1..];
+                    continue;
+                },
+                Some(tag) => tag,
+            };
+
+            let source = &rest[..tag.length];
+            rest = &rest[tag.length..];
+
+            if !self.tags.contains_key(&tag.name) {
+                if self.escape {
+                    output.push_str(&escape_html(source));
+                }
+                else if !tag.end && DANGEROUS.contains(&tag.name.as_str()) {
+                    rest = skip_contents(rest, &tag.name);
+                }
+                continue;
+            }
+
+            if tag.end {
+                // close any tags left open inside of this one
+                if let Some(i) = open.iter().rposition(|it| *it == tag.name) {
+                    for name in open.drain(i..).rev() {
+                        output.push_str(&format!("</{}>", name));
+                    }
+                }
+                continue;
+            }
+
+            output.push('<');
+            output.push_str(&tag.name);
+            for (name, value) in tag.attributes {
+                let value = match self.attribute(&tag.name, &name, value) {
+                    None => continue,
+                    Some(value) => value,
+                };
+
+                output.push(' ');
+                output.push_str(&name);
+                if let Some(value) = value {
+                    output.push_str(&format!("=\"{}\"", escape_html(&value)));
+                }
+            }
+
+            if VOID.contains(&tag.name.as_str()) {
+                output.push_str(if tag.closed { " />" } else { ">" });
+            }
+            else {
+                output.push('>');
+                open.push(tag.name);
+            }
+        }
+
+        push_text(&mut output, rest);
+        for name in open.into_iter().rev() {
+            output.push_str(&format!("</{}>", name));
+        }
+
+        output
+    }
+
+    /// The value to keep of an attribute on the `tag`, if it's allowed.
+    fn attribute(&self, tag: &str, name: &str, value: Option<String>)
+        -> Option<Option<String>>
+    {
+        let allowed = !name.starts_with("on")
+            && (self.attributes.contains(name)
+                || self.tags.get(tag).is_some_and(|it| it.contains(name)));
+        if !allowed {
+            return None;
+        }
+
+        match value {
+            Some(ref url) if URLS.contains(&name)
+                && !safe_url(url, &self.schemes) => None,
+            value => Some(value),
+        }
+    }
+}
+
+impl Default for Sanitize
+{
+    fn default() -> Self
+    {
+        Sanitize::new()
+    }
+}
+
+impl Decorator for Sanitize
+{
+    fn decorate(&self, view: View) -> View
+    {
+        if view.mime.subtype() != "html" {
+            return view;
+        }
+
+        View {
+            content: self.clean(&view.content),
+            ..view
+        }
+    }
+}
+
+/// A tag, which starts an element or ends it.
+struct Tag
+{
+    /// The lowercase name of the tag.
+    name: String,
+
+    /// If this ends an element.
+    end: bool,
+
+    /// If the tag closed itself with `/>`.
+    closed: bool,
+
+    /// The lowercase names of the attributes, and their decoded values.
+    attributes: Vec<(String, Option<String>)>,
+
+    /// The length of the tag's source, including the `<` and `>`.
+    length: usize,
+}
+
+impl Tag
+{
+    /// Parses the tag at the start of the `html`, if it is one.
+    fn parse(html: &str) -> Option<Tag>
+    {
+        let bytes = html.as_bytes();
+        let end = bytes.get(1) == Some(&b'/');
+        let mut i = if end { 2 } else { 1 };
+
+        let start = i;
+        while i < bytes.len() && (bytes[i].is_ascii_alphanumeric()) {
+            i += 1;
+        }
+        if i == start || !bytes[start].is_ascii_alphabetic() {
+            return None;
+        }
+
+        let mut tag = Tag {
+            name: html[start..i].to_lowercase(),
+            end,
+            closed: false,
+            attributes: Vec::new(),
+            length: 0,
+        };
+
+        loop {
+            while i < bytes.len() && is_space(bytes[i]) {
+                i += 1;
+            }
+
+            match bytes.get(i) {
+                None => return None,
+                Some(&b'>') => {
+                    tag.length = i + 1;
+                    return Some(tag);
+                },
+                Some(&b'/') => {
+                    tag.closed = true;
+                    i += 1;
+                    continue;
+                },
+                Some(_) => {},
+            }
+
+            // the attribute's name
+            let start = i;
+            while i < bytes.len()
+                && !is_space(bytes[i])
+                && !b"/>=".contains(&bytes[i])
+            {
+                i += 1;
+            }
+            let name = html[start..i].to_lowercase();
+
+            while i < bytes.len() && is_space(bytes[i]) {
+                i += 1;
+            }
+            if bytes.get(i) != Some(&b'=') {
+                tag.attributes.push((name, None));
+                continue;
+            }
+
+            // and its value, which may be quoted
+            i += 1;
+            while i < bytes.len() && is_space(bytes[i]) {
+                i += 1;
+            }
+
+            let value = match bytes.get(i) {
+                Some(&quote) if quote == b'"' || quote == b'\'' => {
+                    let end = html[i + 1..].find(quote as char)? + i + 1;
+                    let value = &html[i + 1..end];
+                    i = end + 1;
+                    value
+                },
+                _ => {
+                    let start = i;
+                    while i < bytes.len()
+                        && !is_space(bytes[i])
+                        && bytes[i] != b'>'
+                    {
+                        i += 1;
+                    }
+                    &html[start..i]
+                },
+            };
+
+            tag.attributes.push((name, Some(decode(value))));
+        }
+    }
+}
+
+/// If the `url` is relative, or has one of the allowed `schemes`.
+pub(crate) fn safe_url<T: AsRef<str>>(url: &str, schemes: &[T]) -> bool
+{
+    // browsers ignore whitespace and control characters in schemes
+    let url: String = decode(url).chars()
+        .filter(|c| !c.is_whitespace() && !c.is_control())
+        .collect();
+
+    let scheme = match url.find(':') {
+        None => return true,
+        Some(i) => &url[..i],
+    };
+
+    // a colon after a path, query or fragment doesn't start a scheme
+    if scheme.contains(&['/', '?', '#'][..]) {
+        return true;
+    }
+
+    schemes.iter().any(|it| it.as_ref().eq_ignore_ascii_case(scheme))
+}
+
+/// Adds the `text` between tags to the `output`, escaping any `>`.
+fn push_text(output: &mut String, text: &str)
+{
+    output.push_str(&text.replace('>', "&gt;"));
+}
+
+/// Skips the contents of a dangerous element, and its end tag.
+fn skip_contents<'a>(html: &'a str, name: &str) -> &'a str
+{
+    let lower = html.to_ascii_lowercase();
+    let close = format!("</{}", name);
+
+    match lower.find(&close) {
+        None => "",
+        Some(i) => match html[i..].find('>') {
+            None => "",
+            Some(end) => &html[i + end + 1..],
+        },
+    }
+}
+
+/// Decodes the character references in the `text`.
+fn decode(text: &str) -> String
+{
+    let mut output = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find('&') {
+        output.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let end = rest.find(';').filter(|&it| it < 12);
+        let decoded = end.and_then(|end| reference(&rest[1..end]));
+
+        match (end, decoded) {
+            (Some(end), Some(c)) => {
+                output.push(c);
+                rest = &rest[end + 1..];
+            },
+            _ => {
+                output.push('&');
+                rest = &rest[1..];
+            },
+        }
+    }
+
+    output.push_str(rest);
+    output
+}
+
+/// The character a reference, between its `&` and `;`, stands for.
+fn reference(name: &str) -> Option<char>
+{
+    let code = if name.starts_with("#x") || name.starts_with("#X") {
+        u32::from_str_radix(&name[2..], 16).ok()?
+    }
+    else if let Some(number) = name.strip_prefix('#') {
+        number.parse().ok()?
+    }
+    else {
+        return match name {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            "colon" => Some(':'),
+            "tab" => Some('\t'),
+            "newline" => Some('\n'),
+            "nbsp" => Some('\u{a0}'),
+            _ => None,
+        };
+    };
+
+    ::std::char::from_u32(code)
+}
+
+/// If the byte is HTML whitespace.
+fn is_space(byte: u8) -> bool
+{
+    byte == b' ' || byte == b'\t' || byte == b'\n' || byte == b'\r'
+        || byte == b'\x0c'
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+
+    /// Tests that tags and attributes which aren't allowed are removed.
+    #[test]
+    fn cleans()
+    {
+        let dec = Sanitize::new();
+
+        assert_eq!(
+            "<p title=\"&lt;x&gt;\">a <b>b</b>  c</p>",
+            dec.clean("<P TITLE='&lt;x>' class=x onClick=\"x()\">\
+                       a <b>b</b> <!-- hidden --> c</p>")
+        );
+        assert_eq!(
+            "<img src=\"/a.png\" alt=\"a\" /><br><hr />x &gt; y",
+            dec.clean("<img src=/a.png alt=a onerror=x() /><br><hr/>x > y")
+        );
+        assert_eq!(
+            "before  after",
+            dec.clean("before <SCRIPT>alert('ß')</script > <style>*{}</style>\
+                       after<iframe src=x>")
+        );
+        assert_eq!(
+            "<ul><li>a</li><li><em>b</em></li></ul>&lt; 3",
+            dec.clean("<ul><li>a</em></li><li><em>b</ul>< 3")
+        );
+        assert_eq!(
+            "<p>text&lt;div unclosed</p>",
+            dec.clean("<!DOCTYPE html><p>text<div unclosed")
+        );
+    }
+
+    /// Tests that URLs with schemes which aren't allowed are removed, even
+    /// when they're disguised.
+    #[test]
+    fn urls()
+    {
+        let dec = Sanitize::new();
+        let link = |href: &str| {
+            dec.clean(&format!("<a href=\"{}\">x</a>", href))
+        };
+
+        assert_eq!("<a href=\"HTTPS://a.com\">x</a>", link("HTTPS://a.com"));
+        assert_eq!(
+            "<a href=\"../b?c=d&amp;e=f\">x</a>",
+            link("../b?c=d&amp;e=f")
+        );
+        assert_eq!("<a href=\"/x:y\">x</a>", link("/x:y"));
+        assert_eq!("<a href=\"mailto:a@b.c\">x</a>", link("mailto:a@b.c"));
+        assert_eq!("<a>x</a>", link("javascript:alert(1)"));
+        assert_eq!("<a>x</a>", link(" JaVa\tScRiPt:alert(1)"));
+        assert_eq!("<a>x</a>", link("jav&#x61;script&colon;alert(1)"));
+        assert_eq!("<a>x</a>", link("data:text/html,x"));
+
+        let dec = Sanitize::new().schemes(&["ftp"]);
+        assert_eq!("<a>x</a>", dec.clean("<a href='https://a.com'>x</a>"));
+        assert_eq!(
+            "<a href=\"ftp://a.com\">x</a>",
+            dec.clean("<a href='ftp://a.com'>x</a>")
+        );
+    }
+
+    /// Tests the policy can be changed, and that disallowed tags can be
+    /// escaped instead.
+    #[test]
+    fn policy()
+    {
+        let dec = Sanitize::empty()
+            .allow_tag("P", &["Data-X", "onclick"])
+            .allow_attribute("lang");
+        assert_eq!(
+            "<p data-x=\"1\" lang=\"en\">a b</p>",
+            dec.clean("<p data-x=1 lang=en onclick=x id=y>a <em>b</em></p>")
+        );
+
+        let dec = Sanitize::new().deny_tag("img").escape(true);
+        assert_eq!(
+            "<p>&lt;img src=x&gt;&lt;script&gt;x()&lt;/script&gt;</p>",
+            dec.clean("<p><img src=x><script>x()</script></p>")
+        );
+    }
+
+    /// Tests that only HTML views are sanitized.
+    #[test]
+    fn decorates()
+    {
+        let view = View::raw("<script>x()</script>").apply(&Sanitize::new());
+        assert_eq!("<script>x()</script>", view.content);
+
+        let mut view = View::raw("<script>x()</script>ok");
+        view.mime = "text/html".parse().unwrap();
+        assert_eq!("ok", view.apply(&Sanitize::new()).content);
+    }
+}
@@ -0,0 +1,981 @@
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error as StdError;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use decorator::Decorator;
+use decorator::layout::Layouts;
+use error::{Error, Result};
+use escape::escape_html;
+use view::View;
+
+/// How deeply templates may include each other, so that a template which
+/// includes itself can't recurse forever.
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// A decorator which renders a template around the view, with a context of
+/// named values.
+///
+/// The template's tags are:
+///
+/// * `{{name}}` is replaced by the value of `name`, with HTML escaped.
+///   Fields of maps are found with dots, like `{{user.name}}`.
+/// * `{{{name}}}` (or `{{& name}}`) is replaced by the raw value.
+/// * `{{#if name}}...{{else}}...{{/if}}` is rendered if the value is
+///   truthy: it's `true`, or isn't empty. `{{#unless name}}` is the opposite.
+/// * `{{#each name}}...{{else}}...{{/each}}` is rendered for every item in a
+///   list, with the item as `{{this}}` (and its fields by name), and its
+///   position as `{{@index}}`. The `else` part is rendered if the list is
+///   empty.
+/// * `{{> name}}` includes the template registered as the partial `name`.
+/// * `{{#block name}}...{{/block}}` is a named block, which a template
+///   extending this one can replace.
+/// * `{{extends name}}` makes this template extend another one, so that only
+///   its blocks are rendered, into the other template. This only works for
+///   templates loaded through [Layouts].
+/// * `{{! comment }}` is left out.
+///
+/// Values which aren't in the context are rendered as nothing. When the
+/// template decorates a view, the view's content is available as `content`,
+/// its metadata as `meta` (such as `{{meta.title}}`), and the view becomes
+/// `text/html`.
+///
+/// ```rust
+/// use mwf::{View, decorator};
+/// use mwf::decorator::{Template, Context};
+///
+/// let page = Template::new(
+///     "<h1>{{title}}</h1>{{#each tags}}<i>{{this}}</i>{{/each}}{{{content}}}"
+/// ).unwrap().context(Context::new()
+///     .set("title", "Fish & Chips")
+///     .set("tags", vec!["food", "uk"]));
+///
+/// let view = View::raw("<p>Hello!</p>").apply(&page);
+/// assert_eq!(
+///     "<h1>Fish &amp; Chips</h1><i>food</i><i>uk</i><p>Hello!</p>",
+///     view.content
+/// );
+/// ```
+#[derive(Clone, Debug)]
+pub struct Template
+{
+    nodes: Vec<Node>,
+    parent: Option<String>,
+    blocks: HashMap<String, Vec<Node>>,
+    partials: HashMap<String, Vec<Node>>,
+    context: Context,
+}
+
+/// The named values a [Template] is rendered with.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Context
+{
+    values: BTreeMap<String, Value>,
+}
+
+/// A value in a [Context].
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value
+{
+    /// No value, which renders as nothing.
+    Null,
+    Bool(bool),
+
+    /// Text, which numbers are converted to as well.
+    Text(String),
+
+    /// A list, which can be looped over with `{{#each}}`.
+    List(Vec<Value>),
+
+    /// Named fields, which are found with dots, like `{{user.name}}`.
+    Map(BTreeMap<String, Value>),
+}
+
+/// The error for a template which couldn't be parsed.
+#[derive(Debug)]
+pub struct TemplateError
+{
+    line: usize,
+    message: String,
+}
+
+/// A parsed piece of a template.
+#[derive(Clone, Debug)]
+pub(crate) enum Node
+{
+    Text(String),
+    Var { path: Vec<String>, raw: bool },
+    If { path: Vec<String>, negate: bool, then: Vec<Node>, otherwise: Vec<Node> },
+    Each { path: Vec<String>, body: Vec<Node>, otherwise: Vec<Node> },
+    Include(String),
+    Block { name: String, body: Vec<Node> },
+    Extends(String),
+}
+
+/// Renders the nodes of a template, along with everything they include.
+pub(crate) struct Renderer<'a>
+{
+    /// The partials registered on the template being rendered.
+    partials: &'a HashMap<String, Vec<Node>>,
+
+    /// Where templates which aren't registered partials are loaded from.
+    layouts: Option<&'a Layouts>,
+
+    /// The contents of blocks, replacing their defaults.
+    blocks: HashMap<String, &'a [Node]>,
+}
+
+/// A piece of a template's source, along with the line it starts on.
+enum Token
+{
+    Text(String),
+    Tag { body: String, raw: bool, line: usize },
+}
+
+/// What ended a run of nodes while parsing.
+enum End
+{
+    Eof,
+    Else(usize),
+    Close(String, usize),
+}
+
+/// A value which names are looked up in while rendering, along with the
+/// position of the loop item it is, if it is one.
+#[derive(Clone, Copy)]
+pub(crate) struct Scope<'a>
+{
+    value: &'a Value,
+    index: Option<usize>,
+}
+
+//
+// Implementation
+//
+
+impl Template
+{
+    /// Parses the template `source`.
+    pub fn new<T: AsRef<str>>(source: T) -> Result<Self>
+    {
+        let nodes = parse(source.as_ref())?;
+
+        let parent = nodes.iter()
+            .filter_map(|node| match *node {
+                Node::Extends(ref parent) => Some(parent.clone()),
+                _ => None,
+            })
+            .next();
+
+        let mut blocks = HashMap::new();
+        collect_blocks(&nodes, &mut blocks);
+
+        Ok(Template {
+            nodes,
+            parent,
+            blocks,
+            partials: HashMap::new(),
+            context: Context::new(),
+        })
+    }
+
+    /// Reads and parses the template in the given `file`.
+    pub fn file<P: AsRef<Path>>(file: P) -> Result<Self>
+    {
+        Template::new(fs::read_to_string(file)?)
+    }
+
+    /// Registers the `template` as the partial `name`, which can be
+    /// included with `{{> name}}`. The partial is rendered with the context
+    /// at the point it's included, and can include any other partial
+    /// registered here.
+    pub fn partial<T: Into<String>>(mut self, name: T, template: Template)
+        -> Self
+    {
+        self.partials.insert(name.into(), template.nodes);
+        self
+    }
+
+    /// Sets the `context` the template is rendered with when it decorates a
+    /// view.
+    pub fn context(mut self, context: Context) -> Self
+    {
+        self.context = context;
+        self
+    }
+
+    /// The name of the template this one extends, if it extends one.
+    pub fn parent(&self) -> Option<&str>
+    {
+        self.parent.as_deref()
+    }
+
+    /// Renders the template with the given `context`, ignoring the one set
+    /// with [context].
+    pub fn render(&self, context: &Context) -> String
+    {
+        let root = Value::Map(context.values.clone());
+        let mut output = String::new();
+        self.renderer(None, HashMap::new())
+            .render(&self.nodes, &[Scope::new(&root)], 0, &mut output);
+        output
+    }
+
+    /// The parsed nodes of the template.
+    pub(crate) fn nodes(&self) -> &[Node]
+    {
+        &self.nodes
+    }
+
+    /// The blocks the template defines, by name.
+    pub(crate) fn blocks(&self) -> &HashMap<String, Vec<Node>>
+    {
+        &self.blocks
+    }
+
+    /// Creates a renderer for the template, which loads any templates it
+    /// includes that aren't partials from the `layouts`, and renders the
+    /// `blocks` instead of the defaults of the blocks with the same names.
+    pub(crate) fn renderer<'a>(
+        &'a self,
+        layouts: Option<&'a Layouts>,
+        blocks: HashMap<String, &'a [Node]>
+    ) -> Renderer<'a>
+    {
+        Renderer {
+            partials: &self.partials,
+            layouts,
+            blocks,
+        }
+    }
+}
+
+impl<'a> Renderer<'a>
+{
+    /// Renders the `nodes` into the `output`, looking names up in the
+    /// `scopes`. The `depth` is how many includes (and blocks) deep the
+    /// nodes are.
+    pub(crate) fn render(
+        &self,
+        nodes: &[Node],
+        scopes: &[Scope],
+        depth: usize,
+        output: &mut String
+    )
+    {
+        for node in nodes {
+            match *node {
+                Node::Text(ref text) => output.push_str(text),
+
+                Node::Var { ref path, raw } => {
+                    let text = match text(scopes, path) {
+                        None => continue,
+                        Some(text) => text,
+                    };
+
+                    if raw {
+                        output.push_str(&text);
+                    }
+                    else {
+                        output.push_str(&escape_html(&text));
+                    }
+                },
+
+                Node::If { ref path, negate, ref then, ref otherwise } => {
+                    let truthy = lookup(scopes, path)
+                        .map(|it| it.is_truthy())
+                        .unwrap_or(false);
+
+                    let branch = if truthy != negate { then } else { otherwise };
+                    self.render(branch, scopes, depth, output);
+                },
+
+                Node::Each { ref path, ref body, ref otherwise } => {
+                    let items = match lookup(scopes, path) {
+                        Some(&Value::List(ref items)) if !items.is_empty() => {
+                            items
+                        },
+                        _ => {
+                            self.render(otherwise, scopes, depth, output);
+                            continue;
+                        },
+                    };
+
+                    for (index, item) in items.iter().enumerate() {
+                        let mut inner = scopes.to_vec();
+                        inner.push(Scope {
+                            value: item,
+                            index: Some(index),
+                        });
+                        self.render(body, &inner, depth, output);
+                    }
+                },
+
+                Node::Include(ref name) => {
+                    if depth >= MAX_INCLUDE_DEPTH {
+                        continue;
+                    }
+
+                    if let Some(partial) = self.partials.get(name) {
+                        self.render(partial, scopes, depth + 1, output);
+                    }
+                    else if let Some(layouts) = self.layouts {
+                        if let Ok(template) = layouts.get(name) {
+                            let nodes = template.nodes();
+                            self.render(nodes, scopes, depth + 1, output);
+                        }
+                    }
+                },
+
+                Node::Block { ref name, ref body } => {
+                    if depth >= MAX_INCLUDE_DEPTH {
+                        continue;
+                    }
+
+                    match self.blocks.get(name) {
+                        None => self.render(body, scopes, depth + 1, output),
+                        Some(nodes) => {
+                            self.render(nodes, scopes, depth + 1, output)
+                        },
+                    }
+                },
+
+                Node::Extends(_) => {},
+            }
+        }
+    }
+}
+
+impl Decorator for Template
+{
+    fn decorate(&self, view: View) -> View
+    {
+        let root = Value::Map(self.context.values.clone());
+
+        let page = page_value(view.content, &view.meta);
+
+        let mut output = String::new();
+        let scopes = [Scope::new(&root), Scope::new(&page)];
+        self.renderer(None, HashMap::new())
+            .render(&self.nodes, &scopes, 0, &mut output);
+
+        View {
+            content: output,
+            mime: "text/html".parse().unwrap(),
+            ..view
+        }
+    }
+}
+
+/// The values a decorating template has for the view: its `content`, and
+/// its metadata as `meta`.
+pub(crate) fn page_value(content: String, meta: &BTreeMap<String, String>)
+    -> Value
+{
+    let meta = meta.iter()
+        .map(|(key, value)| (key.clone(), Value::Text(value.clone())))
+        .collect();
+
+    let mut page = BTreeMap::new();
+    page.insert("content".to_string(), Value::Text(content));
+    page.insert("meta".to_string(), Value::Map(meta));
+    Value::Map(page)
+}
+
+impl Context
+{
+    /// Creates an empty context.
+    pub fn new() -> Self
+    {
+        Context::default()
+    }
+
+    /// Sets the value of `name`, replacing any value it already had.
+    pub fn set<K: Into<String>, V: Into<Value>>(mut self, name: K, value: V)
+        -> Self
+    {
+        self.insert(name, value);
+        self
+    }
+
+    /// Sets the value of `name` in place, replacing any value it already
+    /// had.
+    pub fn insert<K: Into<String>, V: Into<Value>>(&mut self, name: K, value: V)
+    {
+        self.values.insert(name.into(), value.into());
+    }
+
+    /// Converts the context into a map [Value].
+    pub(crate) fn to_value(&self) -> Value
+    {
+        Value::Map(self.values.clone())
+    }
+
+    /// The value of `name`, if it's set.
+    pub fn get(&self, name: &str) -> Option<&Value>
+    {
+        self.values.get(name)
+    }
+}
+
+impl Value
+{
+    /// If the value counts as true in an `{{#if}}`: it's `true`, or a
+    /// non-empty text, list or map.
+    pub fn is_truthy(&self) -> bool
+    {
+        match *self {
+            Value::Null => false,
+            Value::Bool(value) => value,
+            Value::Text(ref text) => !text.is_empty(),
+            Value::List(ref items) => !items.is_empty(),
+            Value::Map(ref fields) => !fields.is_empty(),
+        }
+    }
+}
+
+impl fmt::Display for Value
+{
+    /// Formats the value as it's rendered in a template. Lists are
+    /// separated by commas, and maps are rendered as nothing.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        match *self {
+            Value::Null | Value::Map(_) => Ok(()),
+            Value::Bool(value) => write!(f, "{}", value),
+            Value::Text(ref text) => f.write_str(text),
+            Value::List(ref items) => {
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(",")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                Ok(())
+            },
+        }
+    }
+}
+
+impl<'a> From<&'a str> for Value
+{
+    fn from(text: &'a str) -> Self
+    {
+        Value::Text(text.to_string())
+    }
+}
+
+impl From<String> for Value
+{
+    fn from(text: String) -> Self
+    {
+        Value::Text(text)
+    }
+}
+
+impl From<bool> for Value
+{
+    fn from(value: bool) -> Self
+    {
+        Value::Bool(value)
+    }
+}
+
+/// Implements `From` for number types, which are rendered as text.
+macro_rules! from_numbers {
+    ($($number:ty),*) => {
+        $(
+            impl From<$number> for Value
+            {
+                fn from(number: $number) -> Self
+                {
+                    Value::Text(number.to_string())
+                }
+            }
+        )*
+    };
+}
+
+from_numbers!(i32, i64, u32, u64, usize, f32, f64);
+
+impl<T: Into<Value>> From<Vec<T>> for Value
+{
+    fn from(items: Vec<T>) -> Self
+    {
+        Value::List(items.into_iter().map(Into::into).collect())
+    }
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value
+{
+    fn from(value: Option<T>) -> Self
+    {
+        match value {
+            None => Value::Null,
+            Some(value) => value.into(),
+        }
+    }
+}
+
+impl From<Context> for Value
+{
+    fn from(context: Context) -> Self
+    {
+        Value::Map(context.values)
+    }
+}
+
+impl TemplateError
+{
+    /// Creates the error for a problem on the given `line`.
+    fn error<T: Into<String>>(line: usize, message: T) -> Error
+    {
+        Error::Other(Box::new(TemplateError {
+            line,
+            message: message.into(),
+        }))
+    }
+
+    /// The line of the template the error is on, starting at 1.
+    pub fn line(&self) -> usize
+    {
+        self.line
+    }
+
+    /// What's wrong with the template.
+    pub fn message(&self) -> &str
+    {
+        &self.message
+    }
+}
+
+impl fmt::Display for TemplateError
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        write!(f, "{} on line {} of the template", self.message, self.line)
+    }
+}
+
+impl StdError for TemplateError {}
+
+impl<'a> Scope<'a>
+{
+    pub(crate) fn new(value: &'a Value) -> Self
+    {
+        Scope {
+            value,
+            index: None,
+        }
+    }
+}
+
+/// The text the value at the `path` is rendered as, if there is one.
+fn text(scopes: &[Scope], path: &[String]) -> Option<String>
+{
+    if path.len() == 1 && path[0] == "@index" {
+        return scopes.iter()
+            .rev()
+            .filter_map(|scope| scope.index)
+            .next()
+            .map(|index| index.to_string());
+    }
+
+    lookup(scopes, path).map(|value| value.to_string())
+}
+
+/// Finds the value at the `path`, looking its first name up in the
+/// innermost scope which has it.
+fn lookup<'a>(scopes: &[Scope<'a>], path: &[String]) -> Option<&'a Value>
+{
+    let (first, rest) = path.split_first()?;
+
+    let mut value = match first.as_str() {
+        "this" => scopes.last()?.value,
+        "@index" => return None,
+        name => scopes.iter()
+            .rev()
+            .filter_map(|scope| match *scope.value {
+                Value::Map(ref fields) => fields.get(name),
+                _ => None,
+            })
+            .next()?,
+    };
+
+    for name in rest {
+        value = match *value {
+            Value::Map(ref fields) => fields.get(name)?,
+            _ => return None,
+        };
+    }
+
+    Some(value)
+}
+
+/// Parses the template `source` into its nodes.
+fn parse(source: &str) -> Result<Vec<Node>>
+{
+    let mut tokens = tokenize(source)?.into_iter();
+
+    match parse_nodes(&mut tokens)? {
+        (nodes, End::Eof) => Ok(nodes),
+        (_, End::Else(line)) => {
+            Err(TemplateError::error(line, "{{else}} outside of a block"))
+        },
+        (_, End::Close(name, line)) => Err(TemplateError::error(
+            line,
+            format!("{{{{/{}}}}} without a matching block", name)
+        )),
+    }
+}
+
+/// Splits the `source` into text and tags.
+fn tokenize(source: &str) -> Result<Vec<Token>>
+{
+    let mut tokens = Vec::new();
+    let mut rest = source;
+    let mut line = 1;
+
+    while let Some(start) = rest.find("{{") {
+        if start > 0 {
+            tokens.push(Token::Text(rest[..start].to_string()));
+        }
+        line += rest[..start].matches('\n').count();
+
+        let tag = &rest[start..];
+        let (open, close) = if tag.starts_with("{{{") {
+            ("{{{", "}}}")
+        }
+        else {
+            ("{{", "}}")
+        };
+
+        let end = match tag[open.len()..].find(close) {
+            None => return Err(TemplateError::error(line, "Unclosed tag")),
+            Some(end) => open.len() + end,
+        };
+
+        let body = &tag[open.len()..end];
+        tokens.push(Token::Tag {
+            body: body.trim().to_string(),
+            raw: open == "{{{",
+            line,
+        });
+
+        line += body.matches('\n').count();
+        rest = &tag[end + close.len()..];
+    }
+
+    if !rest.is_empty() {
+        tokens.push(Token::Text(rest.to_string()));
+    }
+
+    Ok(tokens)
+}
+
+/// Parses nodes until the end of the `tokens`, or a tag which ends a block.
+fn parse_nodes<I>(tokens: &mut I) -> Result<(Vec<Node>, End)>
+    where I: Iterator<Item=Token>
+{
+    let mut nodes = Vec::new();
+
+    while let Some(token) = tokens.next() {
+        let (body, raw, line) = match token {
+            Token::Text(text) => {
+                nodes.push(Node::Text(text));
+                continue;
+            },
+            Token::Tag { body, raw, line } => (body, raw, line),
+        };
+
+        if raw {
+            nodes.push(Node::Var { path: parse_path(&body, line)?, raw });
+        }
+        else if body.starts_with('!') {
+            continue;
+        }
+        else if body == "else" {
+            return Ok((nodes, End::Else(line)));
+        }
+        else if let Some(closed) = body.strip_prefix('/') {
+            return Ok((nodes, End::Close(closed.trim().to_string(), line)));
+        }
+        else if let Some(parent) = body.strip_prefix("extends ") {
+            let parent = parent.trim().trim_matches('"');
+            nodes.push(Node::Extends(parent.to_string()));
+        }
+        else if let Some(name) = body.strip_prefix('>') {
+            nodes.push(Node::Include(name.trim().to_string()));
+        }
+        else if let Some(name) = body.strip_prefix('&') {
+            nodes.push(Node::Var {
+                path: parse_path(name.trim(), line)?,
+                raw: true,
+            });
+        }
+        else if let Some(tag) = body.strip_prefix('#') {
+            nodes.push(parse_block(tokens, tag, line)?);
+        }
+        else {
+            nodes.push(Node::Var { path: parse_path(&body, line)?, raw });
+        }
+    }
+
+    Ok((nodes, End::Eof))
+}
+
+/// Parses the rest of the block opened by the tag `{{#<tag>}}`.
+fn parse_block<I>(tokens: &mut I, tag: &str, line: usize) -> Result<Node>
+    where I: Iterator<Item=Token>
+{
+    let mut words = tag.splitn(2, char::is_whitespace);
+    let name = words.next().unwrap_or("");
+    let argument = words.next().unwrap_or("").trim();
+
+    if !["if", "unless", "each", "block"].contains(&name) {
+        return Err(TemplateError::error(
+            line,
+            format!("Unknown block {{{{#{}}}}}", name)
+        ));
+    }
+
+    let (then, end) = parse_nodes(tokens)?;
+    let (otherwise, end) = match end {
+        End::Else(line) if name == "block" => {
+            return Err(TemplateError::error(line, "{{else}} in a {{#block}}"));
+        },
+        End::Else(_) => parse_nodes(tokens)?,
+        end => (Vec::new(), end),
+    };
+
+    match end {
+        End::Close(ref closed, _) if closed == name => {},
+        End::Close(closed, line) => return Err(TemplateError::error(
+            line,
+            format!("{{{{/{}}}}} closes a {{{{#{}}}}} block", closed, name)
+        )),
+        End::Else(line) => {
+            return Err(TemplateError::error(line, "A block has two {{else}}s"));
+        },
+        End::Eof => return Err(TemplateError::error(
+            line,
+            format!("{{{{#{}}}}} is never closed", name)
+        )),
+    }
+
+    Ok(match name {
+        "block" => {
+            parse_path(argument, line)?;
+            Node::Block { name: argument.to_string(), body: then }
+        },
+        "each" => Node::Each {
+            path: parse_path(argument, line)?,
+            body: then,
+            otherwise,
+        },
+        negate => Node::If {
+            path: parse_path(argument, line)?,
+            negate: negate == "unless",
+            then,
+            otherwise,
+        },
+    })
+}
+
+/// Collects the blocks defined anywhere in the `nodes` by name. If a block
+/// is defined more than once, the first definition is used.
+fn collect_blocks(nodes: &[Node], blocks: &mut HashMap<String, Vec<Node>>)
+{
+    for node in nodes {
+        match *node {
+            Node::Block { ref name, ref body } => {
+                if !blocks.contains_key(name) {
+                    blocks.insert(name.clone(), body.clone());
+                }
+                collect_blocks(body, blocks);
+            },
+            Node::If { ref then, ref otherwise, .. } => {
+                collect_blocks(then, blocks);
+                collect_blocks(otherwise, blocks);
+            },
+            Node::Each { ref body, ref otherwise, .. } => {
+                collect_blocks(body, blocks);
+                collect_blocks(otherwise, blocks);
+            },
+            _ => {},
+        }
+    }
+}
+
+/// Splits the name of a value on its dots. The current value can be named
+/// `this` or `.`.
+fn parse_path(name: &str, line: usize) -> Result<Vec<String>>
+{
+    if name == "." {
+        return Ok(vec!["this".to_string()]);
+    }
+
+    let path: Vec<String> = name.split('.').map(String::from).collect();
+
+    let invalid = path.iter().any(|it| {
+        it.is_empty() || it.contains(char::is_whitespace)
+    });
+    if invalid {
+        return Err(TemplateError::error(
+            line,
+            format!("Invalid name {:?}", name)
+        ));
+    }
+
+    Ok(path)
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+
+    fn render(source: &str, context: Context) -> String
+    {
+        Template::new(source).unwrap().render(&context)
+    }
+
+    /// Tests that variables are substituted, escaped unless they're raw,
+    /// and that missing ones render as nothing.
+    #[test]
+    fn variables()
+    {
+        let context = Context::new()
+            .set("name", "<b>Tom & Jerry</b>")
+            .set("count", 3)
+            .set("user", Context::new().set("email", "tom@example.com"));
+
+        assert_eq!(
+            "&lt;b&gt;Tom &amp; Jerry&lt;/b&gt; <b>Tom & Jerry</b> \
+             <b>Tom & Jerry</b> 3 tom@example.com []",
+            render(
+                "{{name}} {{{name}}} {{& name }} {{ count }} {{user.email}} \
+                 [{{missing}}{{user.missing.deeper}}{{! a comment }}]",
+                context
+            )
+        );
+    }
+
+    /// Tests conditionals and their `else` parts.
+    #[test]
+    fn conditionals()
+    {
+        let context = Context::new()
+            .set("yes", true)
+            .set("no", false)
+            .set("empty", "")
+            .set("none", Vec::<String>::new());
+        let source = "{{#if yes}}a{{else}}b{{/if}}\
+                      {{#if no}}c{{else}}d{{/if}}\
+                      {{#if empty}}e{{/if}}\
+                      {{#if none}}f{{/if}}\
+                      {{#unless missing}}g{{/unless}}";
+
+        assert_eq!("adg", render(source, context));
+    }
+
+    /// Tests that loops render their body for every item, with the item's
+    /// fields and the outer values in scope.
+    #[test]
+    fn loops()
+    {
+        let context = Context::new()
+            .set("sep", "|")
+            .set("tags", vec!["a", "b"])
+            .set("files", vec![
+                Context::new().set("name", "x.txt"),
+                Context::new().set("name", "y<.txt"),
+            ])
+            .set("empty", Vec::<String>::new());
+        let source = "{{#each tags}}{{@index}}{{.}}{{sep}}{{/each}} \
+                      {{#each files}}<{{name}}>{{this.name}}{{/each}} \
+                      {{#each empty}}x{{else}}nothing{{/each}}";
+
+        assert_eq!(
+            "0a|1b| <x.txt>x.txt<y&lt;.txt>y&lt;.txt nothing",
+            render(source, context)
+        );
+    }
+
+    /// Tests that partials are included with the current context, and can't
+    /// recurse forever.
+    #[test]
+    fn includes()
+    {
+        let item = Template::new("<li>{{this}}</li>").unwrap();
+        let list = Template::new("<ul>{{#each items}}{{> item}}{{/each}}</ul>")
+            .unwrap()
+            .partial("item", item)
+            .partial("self", Template::new("x{{> self}}").unwrap());
+
+        let context = Context::new().set("items", vec![1, 2]);
+        assert_eq!("<ul><li>1</li><li>2</li></ul>", list.render(&context));
+
+        let recursive = Template::new("{{> self}}{{> unknown}}").unwrap()
+            .partial("self", Template::new("x{{> self}}").unwrap());
+        assert_eq!(MAX_INCLUDE_DEPTH, recursive.render(&context).len());
+    }
+
+    /// Tests that decorating a view puts its content and metadata in the
+    /// context.
+    #[test]
+    fn decorates()
+    {
+        let page = Template::new(
+            "<title>{{meta.title}} - {{site}}</title>{{{content}}}"
+        ).unwrap().context(Context::new().set("site", "Home"));
+        let view = View::raw("<p>hi</p>")
+            .with_meta("title", "A&B")
+            .apply(&page);
+
+        assert_eq!("<title>A&amp;B - Home</title><p>hi</p>", view.content);
+        assert_eq!(Some("A&B"), view.title());
+        assert_eq!("text/html", view.mime.to_string());
+    }
+
+    /// Tests that malformed templates are rejected, with the line of the
+    /// problem.
+    #[test]
+    fn errors()
+    {
+        let error = |source: &str| match Template::new(source) {
+            Err(Error::Other(error)) => {
+                let error = error.downcast_ref::<TemplateError>().unwrap();
+                (error.line(), error.message().to_string())
+            },
+            _ => panic!("{:?} should be invalid", source),
+        };
+
+        assert_eq!((1, "Unclosed tag".to_string()), error("a {{b"));
+        assert_eq!(
+            (3, "{{#if}} is never closed".to_string()),
+            error("\n\n{{#if a}}")
+        );
+        assert_eq!(
+            (2, "{{/each}} closes a {{#if}} block".to_string()),
+            error("{{#if a}}\n{{/each}}")
+        );
+        assert_eq!(
+            (1, "{{/if}} without a matching block".to_string()),
+            error("{{/if}}")
+        );
+        assert_eq!(
+            (1, "{{else}} outside of a block".to_string()),
+            error("{{else}}")
+        );
+        assert_eq!(
+            (1, "Unknown block {{#with}}".to_string()),
+            error("{{#with a}}{{/with}}")
+        );
+        assert_eq!(
+            (1, "Invalid name \"a b\"".to_string()),
+            error("{{a b}}")
+        );
+    }
+}
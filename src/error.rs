@@ -42,6 +42,14 @@ impl StdError for Error
             &Error::Other(ref cause) => cause.cause(),
         }
     }
+
+    fn source(&self) -> Option<&(StdError + 'static)>
+    {
+        match self {
+            &Error::Io(ref cause) => cause.source(),
+            &Error::Other(ref cause) => cause.source(),
+        }
+    }
 }
 
 impl fmt::Display for Error
@@ -0,0 +1,65 @@
+/// Escapes `text` so it can be safely inserted into HTML.
+pub(crate) fn escape_html(text: &str) -> String
+{
+    let mut output = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => output.push_str("&amp;"),
+            '<' => output.push_str("&lt;"),
+            '>' => output.push_str("&gt;"),
+            '"' => output.push_str("&quot;"),
+            '\'' => output.push_str("&#39;"),
+            c => output.push(c),
+        }
+    }
+    output
+}
+
+/// Escapes `text` so it can be used inside of a JSON string.
+pub(crate) fn escape_json(text: &str) -> String
+{
+    let mut output = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '"' => output.push_str("\\\""),
+            '\\' => output.push_str("\\\\"),
+            '\n' => output.push_str("\\n"),
+            '\r' => output.push_str("\\r"),
+            '\t' => output.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                output.push_str(&format!("\\u{:04x}", c as u32))
+            },
+            c => output.push(c),
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+
+    /// Tests that the characters which are special in HTML are escaped.
+    #[test]
+    fn html()
+    {
+        assert_eq!(
+            "&lt;a href=&quot;x&quot;&gt;Tom &amp; Jerry&#39;s&lt;/a&gt;",
+            escape_html("<a href=\"x\">Tom & Jerry's</a>")
+        );
+        assert_eq!("plain é", escape_html("plain é"));
+    }
+
+    /// Tests that quotes, backslashes and control characters are escaped
+    /// in JSON strings.
+    #[test]
+    fn json()
+    {
+        assert_eq!(
+            "\\\"a\\\\b\\n\\t\\u0001\\\"",
+            escape_json("\"a\\b\n\t\u{1}\"")
+        );
+        assert_eq!("plain é", escape_json("plain é"));
+    }
+}
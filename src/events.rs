@@ -0,0 +1,542 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::io;
+use std::sync::{Arc, Mutex, Weak};
+use std::thread;
+use std::time::Duration;
+
+use futures::Stream;
+use futures::sync::mpsc::{self, Receiver, Sender};
+use hyper::Request;
+use hyper::header::{CacheControl, CacheDirective};
+
+use error::Result;
+use resolution::RouteMap;
+use request_handler::RequestHandler;
+use stream::BodyStream;
+use view::View;
+
+/// A single server-sent event.
+///
+/// ```rust
+/// use mwf::Event;
+///
+/// let event = Event::new("hello\nworld").event("greeting").id("7");
+/// assert_eq!(
+///     "id: 7\nevent: greeting\ndata: hello\ndata: world\n\n",
+///     event.to_string()
+/// );
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct Event
+{
+    id: Option<String>,
+    event: Option<String>,
+    data: String,
+    retry: Option<Duration>,
+}
+
+/// A request handler which keeps its clients connected with a long-lived
+/// `text/event-stream` response, so that events can be pushed to them.
+///
+/// The event source is cheap to clone, and every clone pushes to the same set
+/// of clients, so a clone can be bound to a route while another is handed to
+/// whichever threads produce events.
+///
+/// Every event is given an id (a counter, if one wasn't set explicitly), and
+/// the most recent events are remembered so that clients which reconnect
+/// with a `Last-Event-ID` header receive everything they missed.
+///
+/// Each client has a buffer of events which haven't been sent to it yet. A
+/// client too slow to keep up fills its buffer, and is then disconnected so
+/// that it doesn't hold on to ever more memory; it can reconnect and catch
+/// up from the history.
+///
+/// ```rust,no_run
+/// use std::thread;
+/// use std::time::Duration;
+/// use mwf::{ServerBuilder, EventSource, Event};
+///
+/// let events = EventSource::new();
+///
+/// let producer = events.clone();
+/// thread::spawn(move || loop {
+///     producer.send(Event::new("tick"));
+///     thread::sleep(Duration::from_secs(1));
+/// });
+///
+/// ServerBuilder::new()
+///     .bind("/events", events)
+///     .start();
+/// ```
+#[derive(Clone)]
+pub struct EventSource
+{
+    shared: Arc<Mutex<Shared>>,
+}
+
+/// The state shared between every clone of an [EventSource].
+struct Shared
+{
+    /// The channels to each of the connected clients.
+    clients: Vec<Sender<Vec<u8>>>,
+
+    /// How many messages may be waiting to be sent to a single client.
+    buffer: usize,
+
+    /// The most recent events, oldest first.
+    history: VecDeque<Event>,
+
+    /// How many events to keep in the history.
+    history_size: usize,
+
+    /// The id given to the next event without one.
+    next_id: u64,
+
+    /// How often to send a comment to keep connections alive, if at all.
+    heartbeat: Option<Duration>,
+
+    /// If the heartbeat thread has been started yet.
+    heartbeat_started: bool,
+}
+
+//
+// Implementation
+//
+
+impl Event
+{
+    /// Creates a new unnamed event carrying the given `data`.
+    pub fn new<T: Into<String>>(data: T) -> Self
+    {
+        Event {
+            id: None,
+            event: None,
+            data: data.into(),
+            retry: None,
+        }
+    }
+
+    /// Sets the `id` of this event, which the client will send back in the
+    /// `Last-Event-ID` header if it has to reconnect.
+    pub fn id<T: Into<String>>(mut self, id: T) -> Self
+    {
+        self.id = Some(single_line(id.into()));
+        self
+    }
+
+    /// Sets the `name` of this event, which is the type of the event the
+    /// client's `EventSource` will dispatch.
+    pub fn event<T: Into<String>>(mut self, name: T) -> Self
+    {
+        self.event = Some(single_line(name.into()));
+        self
+    }
+
+    /// Tells the client to wait for the given `delay` before reconnecting
+    /// if the connection is lost.
+    pub fn retry(mut self, delay: Duration) -> Self
+    {
+        self.retry = Some(delay);
+        self
+    }
+}
+
+impl fmt::Display for Event
+{
+    /// Formats this event as it's sent over the wire.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        let mut output = String::new();
+
+        if let Some(ref id) = self.id {
+            output.push_str(&format!("id: {}\n", id));
+        }
+
+        if let Some(ref event) = self.event {
+            output.push_str(&format!("event: {}\n", event));
+        }
+
+        if let Some(retry) = self.retry {
+            let millis = retry.as_secs() * 1000
+                + u64::from(retry.subsec_millis());
+            output.push_str(&format!("retry: {}\n", millis));
+        }
+
+        for line in lines(&self.data) {
+            output.push_str(&format!("data: {}\n", line));
+        }
+
+        output.push('\n');
+        f.write_str(&output)
+    }
+}
+
+impl EventSource
+{
+    /// Creates a new event source, which remembers the last 100 events,
+    /// buffers up to 256 messages for each client, and sends a heartbeat
+    /// comment every 15 seconds.
+    pub fn new() -> Self
+    {
+        EventSource {
+            shared: Arc::new(Mutex::new(Shared {
+                clients: Vec::new(),
+                buffer: 256,
+                history: VecDeque::new(),
+                history_size: 100,
+                next_id: 0,
+                heartbeat: Some(Duration::from_secs(15)),
+                heartbeat_started: false,
+            })),
+        }
+    }
+
+    /// Changes how many of the most recent events are remembered for clients
+    /// which reconnect. `0` disables replaying events entirely.
+    pub fn history(self, size: usize) -> Self
+    {
+        {
+            let mut shared = self.shared.lock().unwrap();
+            shared.history_size = size;
+            while shared.history.len() > size {
+                shared.history.pop_front();
+            }
+        }
+        self
+    }
+
+    /// Changes how many messages may be waiting to be sent to a single
+    /// client before it's disconnected for being too slow.
+    ///
+    /// This must be set before any clients connect.
+    pub fn buffer(self, size: usize) -> Self
+    {
+        self.shared.lock().unwrap().buffer = size;
+        self
+    }
+
+    /// Changes how often a heartbeat comment is sent to every client to keep
+    /// idle connections from being closed. `None` disables heartbeats.
+    ///
+    /// This must be set before any clients connect.
+    pub fn heartbeat(self, interval: Option<Duration>) -> Self
+    {
+        self.shared.lock().unwrap().heartbeat = interval;
+        self
+    }
+
+    /// Sends the `event` to every connected client. If the event doesn't
+    /// have an id, then it will be given the next one in sequence.
+    pub fn send(&self, event: Event)
+    {
+        let mut shared = self.shared.lock().unwrap();
+
+        let event = match event.id {
+            Some(_) => event,
+            None => {
+                let id = shared.next_id;
+                shared.next_id += 1;
+                event.id(id.to_string())
+            },
+        };
+
+        shared.broadcast(event.to_string().into_bytes());
+
+        if shared.history_size > 0 {
+            if shared.history.len() == shared.history_size {
+                shared.history.pop_front();
+            }
+            shared.history.push_back(event);
+        }
+    }
+
+    /// Sends a comment to every connected client. Comments are ignored by
+    /// clients, but keep the connection from going idle.
+    pub fn comment<T: AsRef<str>>(&self, text: T)
+    {
+        let comment = lines(text.as_ref())
+            .into_iter()
+            .map(|it| format!(":{}\n", it))
+            .collect::<String>();
+
+        self.shared.lock().unwrap()
+            .broadcast(format!("{}\n", comment).into_bytes());
+    }
+
+    /// The number of clients which are currently connected.
+    pub fn clients(&self) -> usize
+    {
+        self.shared.lock().unwrap().clients.len()
+    }
+
+    /// Adds a new client, which has already received every event up to and
+    /// including the one with the `last_id`, if given.
+    fn subscribe(&self, last_id: Option<&str>) -> Receiver<Vec<u8>>
+    {
+        let mut shared = self.shared.lock().unwrap();
+
+        // replay everything after the last event the client saw. If we don't
+        // remember that event then there's nothing we can do
+        let missed = match last_id {
+            None => shared.history.len(),
+            Some(last_id) => shared.history.iter()
+                .position(|it| it.id.as_deref() == Some(last_id))
+                .map(|it| it + 1)
+                .unwrap_or(shared.history.len()),
+        };
+
+        // the replayed events don't count against the client's buffer
+        let replayed = shared.history.len() - missed;
+        let (mut sender, receiver) = mpsc::channel(shared.buffer + replayed);
+        for event in shared.history.iter().skip(missed) {
+            let _ = sender.try_send(event.to_string().into_bytes());
+        }
+
+        shared.clients.push(sender);
+
+        if let Some(interval) = shared.heartbeat {
+            if !shared.heartbeat_started {
+                shared.heartbeat_started = true;
+                start_heartbeat(Arc::downgrade(&self.shared), interval);
+            }
+        }
+
+        receiver
+    }
+}
+
+impl Default for EventSource
+{
+    fn default() -> Self
+    {
+        EventSource::new()
+    }
+}
+
+impl Shared
+{
+    /// Sends the `message` to every client, forgetting about the ones which
+    /// have disconnected or whose buffer is full.
+    fn broadcast(&mut self, message: Vec<u8>)
+    {
+        self.clients.retain_mut(|client| {
+            client.try_send(message.clone()).is_ok()
+        });
+    }
+}
+
+impl RequestHandler for EventSource
+{
+    fn handle(&self, _route_map: RouteMap) -> Result<View>
+    {
+        Ok(stream_view(self.subscribe(None)))
+    }
+
+    fn handle_request(&self, request: &Request, _route_map: RouteMap)
+        -> Result<View>
+    {
+        let last_id = request.headers()
+            .get_raw("Last-Event-ID")
+            .and_then(|it| it.one())
+            .and_then(|it| ::std::str::from_utf8(it).ok());
+
+        Ok(stream_view(self.subscribe(last_id)))
+    }
+}
+
+/// Creates the view which streams the messages sent to the `receiver`. It's
+/// never cached, as every response has different events.
+fn stream_view(receiver: Receiver<Vec<u8>>) -> View
+{
+    let stream = receiver.map_err(|_| {
+        io::Error::other("event stream closed")
+    });
+
+    let mut view = View::stream(BodyStream::stream(stream))
+        .with_header(CacheControl(vec![CacheDirective::NoCache]));
+    view.mime = "text/event-stream".parse().unwrap();
+    view
+}
+
+/// Starts a thread which sends a heartbeat comment every `interval` until
+/// the event source has been dropped.
+fn start_heartbeat(shared: Weak<Mutex<Shared>>, interval: Duration)
+{
+    thread::spawn(move || loop {
+        thread::sleep(interval);
+
+        let shared = match shared.upgrade() {
+            None => return,
+            Some(x) => x,
+        };
+
+        shared.lock().unwrap().broadcast(b":\n\n".to_vec());
+    });
+}
+
+/// Strips any line breaks out of `text`, which would otherwise end the field
+/// they're in.
+fn single_line(text: String) -> String
+{
+    text.replace(['\r', '\n'], "")
+}
+
+/// Splits `text` into lines the way an event stream is, at every `\r\n`,
+/// `\r` or `\n`.
+fn lines(text: &str) -> Vec<&str>
+{
+    let mut lines = Vec::new();
+    let mut rest = text;
+
+    while let Some(end) = rest.find(['\r', '\n']) {
+        lines.push(&rest[..end]);
+        rest = if rest[end..].starts_with("\r\n") {
+            &rest[end + 2..]
+        }
+        else {
+            &rest[end + 1..]
+        };
+    }
+
+    lines.push(rest);
+    lines
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+    use futures::stream::Wait;
+
+    /// Reads the next message a client received as a string.
+    fn next(receiver: &mut Wait<Receiver<Vec<u8>>>) -> String
+    {
+        let message = receiver.next().unwrap().unwrap();
+        String::from_utf8(message).unwrap()
+    }
+
+    /// Tests the wire format of events with every field set.
+    #[test]
+    fn event_format()
+    {
+        let event = Event::new("foo\nbar")
+            .id("1")
+            .event("up\ndate")
+            .retry(Duration::from_millis(1500));
+
+        assert_eq!(
+            "id: 1\nevent: update\nretry: 1500\ndata: foo\ndata: bar\n\n",
+            event.to_string()
+        );
+    }
+
+    /// Tests that every kind of line break in the data starts a new `data`
+    /// field, so that it can't be used to inject other fields.
+    #[test]
+    fn data_line_breaks()
+    {
+        assert_eq!(
+            "data: x\ndata: id: 9\ndata: y\ndata: z\ndata: \n\n",
+            Event::new("x\rid: 9\r\ny\nz\r").to_string()
+        );
+        assert_eq!(vec!["", ""], lines("\r\n"));
+        assert_eq!(vec!["a", "", "b"], lines("a\n\rb"));
+    }
+
+    /// Tests that the event stream response is never cached.
+    #[test]
+    fn not_cached()
+    {
+        let events = EventSource::new().heartbeat(None);
+        let view = events.handle(RouteMap::new()).unwrap();
+
+        assert_eq!("event-stream", view.mime.subtype());
+        assert_eq!(
+            Some(&CacheControl(vec![CacheDirective::NoCache])),
+            view.headers.get::<CacheControl>()
+        );
+    }
+
+    /// Tests that clients which don't keep up with the events are
+    /// disconnected once their buffer is full, while others stay.
+    #[test]
+    fn disconnects_slow_clients()
+    {
+        let events = EventSource::new().heartbeat(None).buffer(2);
+        let slow = events.subscribe(None);
+        let mut fast = events.subscribe(None).wait();
+
+        for i in 0..5 {
+            events.send(Event::new(i.to_string()));
+            assert_eq!(format!("id: {}\ndata: {}\n\n", i, i), next(&mut fast));
+        }
+
+        assert_eq!(1, events.clients());
+        drop(slow);
+    }
+
+    /// Tests that events are sent to every client and given ids if they
+    /// don't have one.
+    #[test]
+    fn broadcasts()
+    {
+        let events = EventSource::new().heartbeat(None);
+        let mut first = events.subscribe(None).wait();
+        let mut second = events.subscribe(None).wait();
+        assert_eq!(2, events.clients());
+
+        events.send(Event::new("foo"));
+        events.send(Event::new("bar").id("custom"));
+
+        assert_eq!("id: 0\ndata: foo\n\n", next(&mut first));
+        assert_eq!("id: custom\ndata: bar\n\n", next(&mut first));
+        assert_eq!("id: 0\ndata: foo\n\n", next(&mut second));
+    }
+
+    /// Tests that clients which reconnect are sent the events after the
+    /// last one they saw.
+    #[test]
+    fn replays_after_last_event_id()
+    {
+        let events = EventSource::new().heartbeat(None).history(2);
+        events.send(Event::new("a"));
+        events.send(Event::new("b"));
+        events.send(Event::new("c"));
+
+        let mut client = events.subscribe(Some("1")).wait();
+        events.send(Event::new("d"));
+
+        assert_eq!("id: 2\ndata: c\n\n", next(&mut client));
+        assert_eq!("id: 3\ndata: d\n\n", next(&mut client));
+
+        // "0" has been forgotten, so nothing is replayed
+        let mut client = events.subscribe(Some("0")).wait();
+        events.send(Event::new("e"));
+        assert_eq!("id: 4\ndata: e\n\n", next(&mut client));
+    }
+
+    /// Tests that clients which have gone away are forgotten.
+    #[test]
+    fn forgets_disconnected_clients()
+    {
+        let events = EventSource::new().heartbeat(None);
+        let client = events.subscribe(None);
+        assert_eq!(1, events.clients());
+
+        drop(client);
+        events.comment("anyone there?");
+        assert_eq!(0, events.clients());
+    }
+
+    /// Tests that heartbeats are sent to idle clients.
+    #[test]
+    fn heartbeat()
+    {
+        let events = EventSource::new()
+            .heartbeat(Some(Duration::from_millis(10)));
+        let mut client = events.subscribe(None).wait();
+
+        assert_eq!(":\n\n", next(&mut client));
+    }
+}
@@ -0,0 +1,307 @@
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use hyper::{Method, Request, StatusCode};
+
+use escape::escape_json;
+use reporting::Panic;
+
+/// The result of a single health check.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Health
+{
+    /// The check passed.
+    Healthy,
+
+    /// The check failed, for the reason in the message.
+    Unhealthy(String),
+}
+
+/// A named check which decides if the server is ready for traffic, such as
+/// whether its database can be reached.
+///
+/// Any closure returning a [Health] is a check too.
+pub trait HealthCheck
+    where Self: Send + Sync
+{
+    /// Runs the check. This is called for every readiness request, so it
+    /// should be quick. A check which panics is reported as failing.
+    fn check(&self) -> Health;
+}
+
+/// Serves a liveness endpoint, which responds as long as the server does,
+/// and a readiness endpoint, which runs every registered check.
+///
+/// Both respond with JSON. Readiness responds with `503 Service Unavailable`
+/// if any check fails, or once the server has started to shut down, so that
+/// load balancers stop sending it new requests.
+///
+/// ```rust,no_run
+/// use mwf::{ServerBuilder, HealthChecks, Health};
+///
+/// ServerBuilder::new()
+///     .health(HealthChecks::new()
+///         .check("disk", || {
+///             if std::path::Path::new("/srv/data").exists() {
+///                 Health::Healthy
+///             }
+///             else {
+///                 Health::Unhealthy("/srv/data is missing".to_string())
+///             }
+///         }))
+///     .start();
+/// ```
+pub struct HealthChecks
+{
+    /// The path of the liveness endpoint.
+    liveness: String,
+
+    /// The path of the readiness endpoint.
+    readiness: String,
+
+    /// Every check, in the order they were registered.
+    checks: Vec<(String, Box<HealthCheck>)>,
+
+    /// If the server is shutting down.
+    shutting_down: AtomicBool,
+}
+
+//
+// Implementation
+//
+
+impl HealthChecks
+{
+    /// Creates the endpoints without any checks, with liveness served on
+    /// `/healthz` and readiness on `/readyz`.
+    pub fn new() -> Self
+    {
+        HealthChecks {
+            liveness: "/healthz".to_string(),
+            readiness: "/readyz".to_string(),
+            checks: Vec::new(),
+            shutting_down: AtomicBool::new(false),
+        }
+    }
+
+    /// Changes the path of the liveness endpoint.
+    pub fn liveness<T: Into<String>>(mut self, path: T) -> Self
+    {
+        self.liveness = path.into();
+        self
+    }
+
+    /// Changes the path of the readiness endpoint.
+    pub fn readiness<T: Into<String>>(mut self, path: T) -> Self
+    {
+        self.readiness = path.into();
+        self
+    }
+
+    /// Registers a readiness `check` with the given `name`, which is used
+    /// to report its result.
+    pub fn check<T: Into<String>, C: 'static>(mut self, name: T, check: C)
+        -> Self
+        where C: HealthCheck
+    {
+        self.checks.push((name.into(), Box::new(check)));
+        self
+    }
+
+    /// Marks the server as shutting down, so that it's no longer ready.
+    pub(crate) fn shutting_down(&self)
+    {
+        self.shutting_down.store(true, Ordering::SeqCst);
+    }
+
+    /// Responds to the `request` if it's for one of the endpoints,
+    /// returning its status and JSON body.
+    pub(crate) fn respond(&self, request: &Request)
+        -> Option<(StatusCode, String)>
+    {
+        if *request.method() != Method::Get {
+            return None;
+        }
+
+        if request.path() == self.liveness {
+            Some((StatusCode::Ok, "{\"status\":\"ok\"}".to_string()))
+        }
+        else if request.path() == self.readiness {
+            Some(self.ready())
+        }
+        else {
+            None
+        }
+    }
+
+    /// Runs every check, returning the readiness status and JSON body.
+    fn ready(&self) -> (StatusCode, String)
+    {
+        let shutting_down = self.shutting_down.load(Ordering::SeqCst);
+        let mut healthy = true;
+
+        let checks: Vec<String> = self.checks.iter()
+            .map(|&(ref name, ref check)| {
+                let health = panic::catch_unwind(AssertUnwindSafe(|| {
+                    check.check()
+                }));
+                let health = health.unwrap_or_else(|payload| {
+                    let panic = Panic::new(payload);
+                    Health::Unhealthy(format!("panicked: {}", panic.message()))
+                });
+
+                let result = match health {
+                    Health::Healthy => "{\"status\":\"ok\"}".to_string(),
+                    Health::Unhealthy(message) => {
+                        healthy = false;
+                        format!(
+                            "{{\"status\":\"failing\",\"message\":\"{}\"}}",
+                            escape_json(&message)
+                        )
+                    },
+                };
+
+                format!("\"{}\":{}", escape_json(name), result)
+            })
+            .collect();
+
+        let (status, text) = match (shutting_down, healthy) {
+            (true, _) => (StatusCode::ServiceUnavailable, "shutting_down"),
+            (false, false) => (StatusCode::ServiceUnavailable, "failing"),
+            (false, true) => (StatusCode::Ok, "ok"),
+        };
+
+        let body = format!(
+            "{{\"status\":\"{}\",\"checks\":{{{}}}}}",
+            text,
+            checks.join(",")
+        );
+
+        (status, body)
+    }
+}
+
+impl Default for HealthChecks
+{
+    fn default() -> Self
+    {
+        HealthChecks::new()
+    }
+}
+
+impl<F> HealthCheck for F
+    where F: Fn() -> Health + Send + Sync
+{
+    fn check(&self) -> Health
+    {
+        self()
+    }
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+    use std::sync::Arc;
+    use std::sync::atomic::AtomicBool;
+
+    use builder::ServerBuilder;
+
+    /// Tests that readiness reports every check, and fails when any of them
+    /// does.
+    #[test]
+    fn readiness()
+    {
+        let up = Arc::new(AtomicBool::new(true));
+        let db = up.clone();
+
+        let client = ServerBuilder::new()
+            .health(HealthChecks::new()
+                .readiness("/ready")
+                .check("cache", || Health::Healthy)
+                .check("db", move || {
+                    if db.load(Ordering::SeqCst) {
+                        Health::Healthy
+                    }
+                    else {
+                        Health::Unhealthy("connection \"refused\"".to_string())
+                    }
+                }))
+            .test_client();
+
+        client.get("/ready")
+            .send()
+            .assert_status(StatusCode::Ok)
+            .assert_header("Content-Type", "application/json")
+            .assert_body(
+                "{\"status\":\"ok\",\"checks\":{\"cache\":{\"status\":\"ok\"},\
+                 \"db\":{\"status\":\"ok\"}}}"
+            );
+
+        up.store(false, Ordering::SeqCst);
+
+        client.get("/ready")
+            .send()
+            .assert_status(StatusCode::ServiceUnavailable)
+            .assert_body(
+                "{\"status\":\"failing\",\"checks\":{\"cache\":{\"status\":\
+                 \"ok\"},\"db\":{\"status\":\"failing\",\"message\":\
+                 \"connection \\\"refused\\\"\"}}}"
+            );
+
+        client.get("/readyz")
+            .send()
+            .assert_status(StatusCode::NotFound);
+    }
+
+    /// Tests that a check which panics is reported as failing, instead of
+    /// taking the endpoint down with it.
+    #[test]
+    fn panicking_check()
+    {
+        let health = HealthChecks::new()
+            .check("cache", || Health::Healthy)
+            .check("db", || -> Health { panic!("pool poisoned") });
+        let request = Request::new(Method::Get, "/readyz".parse().unwrap());
+
+        assert_eq!(
+            Some((
+                StatusCode::ServiceUnavailable,
+                "{\"status\":\"failing\",\"checks\":{\"cache\":{\"status\":\
+                 \"ok\"},\"db\":{\"status\":\"failing\",\"message\":\
+                 \"panicked: pool poisoned\"}}}".to_string()
+            )),
+            health.respond(&request)
+        );
+    }
+
+    /// Tests that liveness doesn't run the checks, and that readiness fails
+    /// once the server is shutting down.
+    #[test]
+    fn shutting_down()
+    {
+        let health = HealthChecks::new()
+            .check("broken", || Health::Unhealthy("broken".to_string()));
+        let request = |path: &str| {
+            Request::new(Method::Get, path.parse().unwrap())
+        };
+
+        assert_eq!(
+            Some((StatusCode::Ok, "{\"status\":\"ok\"}".to_string())),
+            health.respond(&request("/healthz"))
+        );
+        assert_eq!(None, health.respond(&request("/other")));
+
+        let health = HealthChecks::new();
+        assert_eq!(StatusCode::Ok, health.respond(&request("/readyz")).unwrap().0);
+
+        health.shutting_down();
+        assert_eq!(
+            Some((
+                StatusCode::ServiceUnavailable,
+                "{\"status\":\"shutting_down\",\"checks\":{}}".to_string()
+            )),
+            health.respond(&request("/readyz"))
+        );
+    }
+}
@@ -1,16 +1,37 @@
 pub extern crate hyper;
 pub extern crate mime_guess;
 extern crate futures;
+extern crate futures_cpupool;
 extern crate pulldown_cmark;
+extern crate percent_encoding;
+extern crate base64;
+extern crate bytes;
+extern crate sha1;
+extern crate tokio_codec;
+extern crate tokio_core;
+extern crate tokio_current_thread;
+extern crate tokio_io;
+extern crate openssl;
+#[macro_use]
+extern crate log;
+#[cfg(unix)]
+extern crate tokio_uds;
+#[cfg(unix)]
+extern crate signal_hook;
 
 mod error;
 pub use self::error::*;
 
+mod escape;
+
 mod view;
 pub use self::view::*;
 
+mod stream;
+pub use self::stream::*;
+
 pub mod decorator;
-pub use self::decorator::Decorator;
+pub use self::decorator::{Decorator, TryDecorator};
 
 mod resolution;
 pub use self::resolution::*;
@@ -18,11 +39,47 @@ pub use self::resolution::*;
 mod request_handler;
 pub use self::request_handler::*;
 
+mod websocket;
+pub use self::websocket::{WebSocketHandler, WebSocket, WebSocketSender, Message};
+
 mod routing;
 pub use self::routing::*;
 
+mod range;
+
 mod server;
 pub use self::server::*;
 
+mod reporting;
+pub use self::reporting::{ErrorReporter, ErrorReport, LogReporter, Panic};
+
+mod health;
+pub use self::health::{Health, HealthCheck, HealthChecks};
+
+mod metrics;
+pub use self::metrics::Metrics;
+
+mod access_log;
+pub use self::access_log::{AccessLog, LogFormat, LogSink, StdoutSink, FacadeSink, FileSink, Rotation};
+
+mod tls;
+pub use self::tls::TlsConfig;
+
+#[cfg(unix)]
+mod unix;
+#[cfg(unix)]
+pub use self::unix::UnixSocket;
+
+mod listing;
+pub use self::listing::*;
+
+mod events;
+pub use self::events::*;
+
+mod shutdown;
+pub use self::shutdown::ServerHandle;
+
+pub mod testing;
+
 mod builder;
 pub use self::builder::*;
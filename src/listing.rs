@@ -0,0 +1,685 @@
+use std::cmp::Ordering;
+use std::fs;
+use std::io::{Error as IoError, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use hyper::Request;
+use hyper::header::{Accept, HttpDate};
+use percent_encoding::{percent_decode, utf8_percent_encode};
+use percent_encoding::PATH_SEGMENT_ENCODE_SET;
+
+use error::Result;
+use escape::{escape_html, escape_json};
+use resolution::RouteMap;
+use request_handler::RequestHandler;
+use view::View;
+use decorator::{self, Decorator};
+
+/// The page format the listing is inserted into when no other decorator has
+/// been given.
+const DEFAULT_PAGE: &str =
+"<!DOCTYPE html>
+<html>
+<head><meta charset='utf-8'><title>Directory listing</title></head>
+<body>
+{{middle}}</body>
+</html>
+";
+
+/// The column a [DirectoryListing] is sorted by.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SortKey
+{
+    Name,
+    Size,
+    Modified,
+}
+
+/// The direction a [DirectoryListing] is sorted in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SortOrder
+{
+    Ascending,
+    Descending,
+}
+
+/// A single file or directory shown in a directory listing.
+#[derive(Clone, Debug)]
+pub struct ListingEntry
+{
+    /// The name of the file (not the full path)
+    pub name: String,
+
+    /// If this entry is a directory
+    pub is_dir: bool,
+
+    /// The size of the file in bytes (`0` for directories)
+    pub size: u64,
+
+    /// When the file was last modified, if the platform supports it
+    pub modified: Option<SystemTime>,
+}
+
+/// A request handler which serves the files under a `root` directory, and
+/// generates an index page for any directory that's requested.
+///
+/// The index is HTML by default, or JSON if the client's `Accept` header
+/// prefers `application/json`. It can be sorted by adding
+/// `?sort=name|size|modified` and `&order=asc|desc` to the URL.
+///
+/// The HTML is only the listing itself (a heading and a table), which is then
+/// rendered through a [Decorator], so it can be themed with something like
+/// [decorator::Surround]. JSON listings are never decorated.
+///
+/// ```rust,no_run
+/// use mwf::{ServerBuilder, DirectoryListing};
+/// use mwf::decorator::Surround;
+///
+/// let listing = DirectoryListing::new("public")
+///     .decorator(Surround::from("<html><body>{{middle}}</body></html>"));
+/// # let _ = listing;
+/// ```
+pub struct DirectoryListing
+{
+    root: PathBuf,
+    prefix: String,
+    decorator: Box<Decorator + Send + Sync>,
+}
+
+//
+// Implementation
+//
+
+impl DirectoryListing
+{
+    /// Creates a new directory listing which serves the contents of `root`,
+    /// mounted at the root of the server (see [prefix]).
+    pub fn new<T: Into<PathBuf>>(root: T) -> Self
+    {
+        DirectoryListing {
+            root: root.into(),
+            prefix: "/".into(),
+            decorator: Box::new(decorator::Surround::from(DEFAULT_PAGE)),
+        }
+    }
+
+    /// Changes the URL `prefix` this listing is mounted at. This will be
+    /// stripped from the requested path before looking it up in the root
+    /// directory, and prepended to every link in the listing.
+    pub fn prefix<T: Into<String>>(mut self, prefix: T) -> Self
+    {
+        let prefix = prefix.into();
+        let prefix = prefix.trim_matches('/');
+
+        self.prefix = if prefix.is_empty() {
+            "/".into()
+        }
+        else {
+            format!("/{}/", prefix)
+        };
+        self
+    }
+
+    /// Changes the `decorator` the HTML listing is rendered through.
+    pub fn decorator<D: 'static>(mut self, decorator: D) -> Self
+        where D: Decorator + Send + Sync
+    {
+        self.decorator = Box::new(decorator);
+        self
+    }
+
+    /// Splits the requested `path` into its (decoded) segments relative to
+    /// the listing's prefix, refusing anything that would escape the root.
+    fn segments(&self, path: &str) -> Result<Vec<String>>
+    {
+        let path = format!("{}/", path);
+        if !path.starts_with(&self.prefix) {
+            return Err(IoError::new(ErrorKind::NotFound, path).into());
+        }
+
+        let mut segments = Vec::new();
+        for segment in path[self.prefix.len()..].split("/") {
+            let segment = percent_decode(segment.as_bytes())
+                .decode_utf8_lossy()
+                .into_owned();
+
+            match segment.as_str() {
+                "" | "." => continue,
+                ".." => {
+                    return Err(IoError::new(
+                        ErrorKind::PermissionDenied,
+                        "directory traversal is not allowed"
+                    ).into());
+                },
+                _ => {},
+            }
+
+            if segment.contains("/") || segment.contains("\\") {
+                return Err(IoError::new(
+                    ErrorKind::PermissionDenied,
+                    "directory traversal is not allowed"
+                ).into());
+            }
+
+            segments.push(segment);
+        }
+
+        Ok(segments)
+    }
+
+    /// Builds the view for the directory (or file) at the given `segments`,
+    /// using the request's `query` string and `accept` header to decide how
+    /// to present it.
+    fn view(
+        &self,
+        segments: Vec<String>,
+        query: Option<&str>,
+        accept: Option<&Accept>
+    ) -> Result<View>
+    {
+        let mut path = self.root.clone();
+        for segment in &segments {
+            path.push(segment);
+        }
+
+        // symbolic links are followed, but only while they stay in the root
+        if !path.canonicalize()?.starts_with(self.root.canonicalize()?) {
+            return Err(IoError::new(
+                ErrorKind::PermissionDenied,
+                "the path leads outside of the root"
+            ).into());
+        }
+
+        if path.is_file() {
+            return View::stream_file(path);
+        }
+
+        let (key, order) = parse_query(query);
+        let entries = read_entries(&path, key, order)?;
+
+        // the url of this directory, re-encoded so the links are well formed
+        let mut base = self.prefix.clone();
+        for segment in &segments {
+            base.push_str(&encode(segment));
+            base.push('/');
+        }
+        let parent = if segments.is_empty() {
+            None
+        }
+        else {
+            base[..base.len() - 1].rfind('/').map(|i| &base[..i + 1])
+        };
+
+        let mut view = if prefers_json(accept) {
+            let mut view = View::raw(to_json(&base, parent, &entries));
+            view.mime = "application/json".parse().unwrap();
+            view
+        }
+        else {
+            let mut view =
+                View::raw(to_html(&base, parent, &entries, key, order));
+            view.mime = "text/html".parse().unwrap();
+            self.decorator.decorate(view)
+        };
+
+        // the same url is listed as HTML or JSON, so caches have to keep
+        // both apart
+        view.headers.set_raw("Vary", "Accept");
+        Ok(view)
+    }
+}
+
+impl RequestHandler for DirectoryListing
+{
+    /// Lists the root directory, sorted by name.
+    fn handle(&self, _route_map: RouteMap) -> Result<View>
+    {
+        self.view(Vec::new(), None, None)
+    }
+
+    fn handle_request(&self, request: &Request, _route_map: RouteMap)
+        -> Result<View>
+    {
+        let segments = self.segments(request.path())?;
+        self.view(segments, request.query(), request.headers().get())
+    }
+}
+
+//
+// Helpers
+//
+
+/// Reads the directory at `path` and sorts its entries by `key` in the given
+/// `order`. Directories are always listed before files.
+fn read_entries(path: &Path, key: SortKey, order: SortOrder)
+    -> Result<Vec<ListingEntry>>
+{
+    let mut entries = Vec::new();
+    for entry in path.read_dir()? {
+        let entry = entry?;
+        let metadata = match fs::metadata(entry.path()) {
+            Err(_) => continue,
+            Ok(x) => x,
+        };
+
+        entries.push(ListingEntry {
+            name: entry.file_name().to_string_lossy().into_owned(),
+            is_dir: metadata.is_dir(),
+            size: if metadata.is_dir() { 0 } else { metadata.len() },
+            modified: metadata.modified().ok(),
+        });
+    }
+
+    entries.sort_by(|a, b| {
+        let ordering = match key {
+            SortKey::Name => a.name.cmp(&b.name),
+            SortKey::Size => a.size.cmp(&b.size),
+            SortKey::Modified => a.modified.cmp(&b.modified),
+        };
+
+        // fall back on the name so the order is always stable
+        let ordering = match ordering {
+            Ordering::Equal => a.name.cmp(&b.name),
+            x => x,
+        };
+
+        let ordering = match order {
+            SortOrder::Ascending => ordering,
+            SortOrder::Descending => ordering.reverse(),
+        };
+
+        b.is_dir.cmp(&a.is_dir).then(ordering)
+    });
+
+    Ok(entries)
+}
+
+/// Reads the sort key and order out of the `query` string, defaulting to
+/// sorting by name in ascending order.
+fn parse_query(query: Option<&str>) -> (SortKey, SortOrder)
+{
+    let mut key = SortKey::Name;
+    let mut order = SortOrder::Ascending;
+
+    for pair in query.unwrap_or("").split("&") {
+        let mut pair = pair.splitn(2, "=");
+        match (pair.next(), pair.next()) {
+            (Some("sort"), Some("size")) => key = SortKey::Size,
+            (Some("sort"), Some("modified")) => key = SortKey::Modified,
+            (Some("sort"), Some("name")) => key = SortKey::Name,
+            (Some("order"), Some("desc")) => order = SortOrder::Descending,
+            (Some("order"), Some("asc")) => order = SortOrder::Ascending,
+            _ => {},
+        }
+    }
+
+    (key, order)
+}
+
+/// Checks if the `accept` header prefers JSON over HTML.
+fn prefers_json(accept: Option<&Accept>) -> bool
+{
+    let accept = match accept {
+        None => return false,
+        Some(x) => x,
+    };
+
+    let json = accept.iter()
+        .filter(|it| it.item.type_() == "application")
+        .filter(|it| it.item.subtype() == "json")
+        .map(|it| it.quality)
+        .max();
+
+    let html = accept.iter()
+        .filter(|it| {
+            let type_ = it.item.type_();
+            let subtype = it.item.subtype();
+
+            type_ == "*"
+                || (type_ == "text" && (subtype == "*" || subtype == "html"))
+        })
+        .map(|it| it.quality)
+        .max();
+
+    match (json, html) {
+        (Some(json), Some(html)) => json > html,
+        (Some(_), None) => true,
+        _ => false,
+    }
+}
+
+/// Percent encodes a single path `segment` for use in a link.
+fn encode(segment: &str) -> String
+{
+    utf8_percent_encode(segment, PATH_SEGMENT_ENCODE_SET).to_string()
+}
+
+/// Builds the link to the `entry` inside of the directory at `base`.
+fn link(base: &str, entry: &ListingEntry) -> String
+{
+    let mut link = format!("{}{}", base, encode(&entry.name));
+    if entry.is_dir {
+        link.push('/');
+    }
+    link
+}
+
+/// Renders the `entries` as an HTML heading and table, with a link to the
+/// `parent` (if there is one) and sortable column headers.
+fn to_html(
+    base: &str,
+    parent: Option<&str>,
+    entries: &[ListingEntry],
+    key: SortKey,
+    order: SortOrder
+) -> String
+{
+    // clicking on the current column flips the order, any other column
+    // starts out ascending
+    let header = |column: SortKey, name: &str, title: &str| {
+        let order = match (column == key, order) {
+            (true, SortOrder::Ascending) => "desc",
+            _ => "asc",
+        };
+        format!(
+            "<th><a href=\"?sort={}&amp;order={}\">{}</a></th>",
+            name, order, title
+        )
+    };
+
+    let mut html = String::new();
+    html.push_str(&format!(
+        "<h1>Index of {}</h1>\n",
+        escape_html(&percent_decode(base.as_bytes()).decode_utf8_lossy())
+    ));
+    html.push_str("<table>\n<tr>");
+    html.push_str(&header(SortKey::Name, "name", "Name"));
+    html.push_str(&header(SortKey::Size, "size", "Size"));
+    html.push_str(&header(SortKey::Modified, "modified", "Modified"));
+    html.push_str("</tr>\n");
+
+    if let Some(parent) = parent {
+        html.push_str(&format!(
+            "<tr><td><a href=\"{}\">../</a></td><td></td><td></td></tr>\n",
+            escape_html(parent)
+        ));
+    }
+
+    for entry in entries {
+        let name = if entry.is_dir {
+            format!("{}/", entry.name)
+        }
+        else {
+            entry.name.clone()
+        };
+        let size = if entry.is_dir {
+            "-".into()
+        }
+        else {
+            entry.size.to_string()
+        };
+        let modified = entry.modified
+            .map(|it| HttpDate::from(it).to_string())
+            .unwrap_or_default();
+
+        html.push_str(&format!(
+            "<tr><td><a href=\"{}\">{}</a></td><td>{}</td><td>{}</td></tr>\n",
+            escape_html(&link(base, entry)),
+            escape_html(&name),
+            size,
+            modified
+        ));
+    }
+
+    html.push_str("</table>\n");
+    html
+}
+
+/// Renders the `entries` as a JSON object. Modification times are given in
+/// seconds since the unix epoch.
+fn to_json(base: &str, parent: Option<&str>, entries: &[ListingEntry])
+    -> String
+{
+    let entries: Vec<String> = entries.iter()
+        .map(|entry| {
+            let modified = entry.modified
+                .and_then(|it| it.duration_since(UNIX_EPOCH).ok())
+                .map(|it| it.as_secs().to_string())
+                .unwrap_or("null".into());
+
+            format!(
+                "{{\"name\":\"{}\",\"type\":\"{}\",\"size\":{},\
+                 \"modified\":{},\"href\":\"{}\"}}",
+                escape_json(&entry.name),
+                if entry.is_dir { "directory" } else { "file" },
+                entry.size,
+                modified,
+                escape_json(&link(base, entry))
+            )
+        })
+        .collect();
+
+    let parent = parent
+        .map(|it| format!("\"{}\"", escape_json(it)))
+        .unwrap_or("null".into());
+
+    format!(
+        "{{\"path\":\"{}\",\"parent\":{},\"entries\":[{}]}}",
+        escape_json(base),
+        parent,
+        entries.join(",")
+    )
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+    use error::Error;
+    use hyper::Method;
+    use hyper::header::qitem;
+    use testing::TempDir;
+
+    /// Creates a GET request for the given `uri`.
+    fn request(uri: &str) -> Request
+    {
+        Request::new(Method::Get, uri.parse().unwrap())
+    }
+
+    /// The `Vary` header of the `view`, if it has one.
+    fn vary(view: &View) -> Option<&str>
+    {
+        view.headers.get_raw("Vary")
+            .and_then(|it| it.one())
+            .and_then(|it| ::std::str::from_utf8(it).ok())
+    }
+
+    /// Tests that the listing of the crate root links to its entries and
+    /// lists directories before files.
+    #[test]
+    fn lists_directory()
+    {
+        let listing = DirectoryListing::new(".");
+        let view = listing.handle_request(&request("/"), RouteMap::new())
+            .expect("Could not list the current directory");
+
+        assert_eq!("html", view.mime.subtype());
+        assert!(view.content.contains("<h1>Index of /</h1>"));
+        assert!(view.content.contains("<a href=\"/src/\">src/</a>"));
+        assert!(view.content.contains("<a href=\"/Cargo.toml\">"));
+        assert!(!view.content.contains("../"));
+
+        let src = view.content.find("src/").unwrap();
+        let cargo = view.content.find("Cargo.toml").unwrap();
+        assert!(src < cargo, "directories weren't listed first");
+    }
+
+    /// Tests that subdirectories are resolved under the prefix and link back
+    /// to their parent.
+    #[test]
+    fn lists_subdirectory_with_prefix()
+    {
+        let listing = DirectoryListing::new(".").prefix("/files");
+        let view = listing.handle_request(&request("/files/src"), RouteMap::new())
+            .expect("Could not list src");
+
+        assert!(view.content.contains("<a href=\"/files/\">../</a>"));
+        assert!(view.content.contains("<a href=\"/files/src/view.rs\">"));
+    }
+
+    /// Tests that requesting a file serves the file itself.
+    #[test]
+    fn serves_files()
+    {
+        let listing = DirectoryListing::new(".");
+        let view = listing.handle_request(
+            &request("/src/listing.rs"),
+            RouteMap::new()
+        ).expect("Could not serve src/listing.rs");
+
+        assert!(view.stream.is_some());
+        assert_eq!(Some(PathBuf::from("./src/listing.rs")), view.file);
+    }
+
+    /// Tests that `..` can't be used to escape the root directory, even when
+    /// it's percent encoded.
+    #[test]
+    fn rejects_traversal()
+    {
+        let listing = DirectoryListing::new("src");
+        assert!(listing.handle_request(&request("/../"), RouteMap::new())
+            .is_err());
+        assert!(listing.handle_request(&request("/%2e%2e/"), RouteMap::new())
+            .is_err());
+    }
+
+    /// Tests that symbolic links are only followed while they stay inside
+    /// the root directory.
+    #[cfg(unix)]
+    #[test]
+    fn rejects_escaping_links()
+    {
+        use std::os::unix::fs::symlink;
+
+        let dir = TempDir::new("listing-links");
+        fs::create_dir_all(dir.join("root/inner")).unwrap();
+        fs::write(dir.join("root/inner/in.txt"), "in").unwrap();
+        fs::write(dir.join("secret.txt"), "secret").unwrap();
+        symlink(dir.join("secret.txt"), dir.join("root/secret.txt")).unwrap();
+        symlink(dir.join("root/inner"), dir.join("root/linked")).unwrap();
+
+        let listing = DirectoryListing::new(dir.join("root"));
+        let escaped = listing.handle_request(
+            &request("/secret.txt"),
+            RouteMap::new()
+        );
+        let inside = listing.handle_request(
+            &request("/linked/in.txt"),
+            RouteMap::new()
+        );
+        let missing = listing.handle_request(
+            &request("/missing.txt"),
+            RouteMap::new()
+        );
+
+        match escaped {
+            Err(Error::Io(ref e)) if e.kind() == ErrorKind::PermissionDenied
+                => {},
+            _ => panic!("the link out of the root was followed"),
+        }
+        assert!(inside.is_ok());
+        match missing {
+            Err(Error::Io(ref e)) if e.kind() == ErrorKind::NotFound => {},
+            _ => panic!("a missing file wasn't reported as not found"),
+        }
+    }
+
+    /// Tests the sort key and order are read from the query string.
+    #[test]
+    fn sorting_from_query()
+    {
+        assert_eq!((SortKey::Name, SortOrder::Ascending), parse_query(None));
+        assert_eq!(
+            (SortKey::Size, SortOrder::Descending),
+            parse_query(Some("sort=size&order=desc"))
+        );
+        assert_eq!(
+            (SortKey::Modified, SortOrder::Ascending),
+            parse_query(Some("order=asc&sort=modified&foo=bar"))
+        );
+
+        let dir = TempDir::new("listing-sorting");
+        fs::create_dir_all(dir.join("b")).unwrap();
+        fs::create_dir_all(dir.join("y")).unwrap();
+        for name in &["a.txt", "c.txt", "z.txt"] {
+            fs::write(dir.join(name), name).unwrap();
+        }
+
+        let entries = read_entries(
+            dir.path(),
+            SortKey::Name,
+            SortOrder::Descending
+        ).unwrap();
+        let names: Vec<&str> = entries.iter()
+            .map(|it| it.name.as_str())
+            .collect();
+
+        // directories stay first, whatever the order
+        assert_eq!(vec!["y", "b", "z.txt", "c.txt", "a.txt"], names);
+    }
+
+    /// Tests that file names are percent encoded in links and escaped in the
+    /// page text.
+    #[test]
+    fn encodes_links()
+    {
+        let entry = ListingEntry {
+            name: "a b&c?.txt".into(),
+            is_dir: false,
+            size: 3,
+            modified: None,
+        };
+        let html = to_html("/", None, &[entry], SortKey::Name, SortOrder::Ascending);
+
+        assert!(html.contains("<a href=\"/a%20b&amp;c%3F.txt\">"));
+        assert!(html.contains(">a b&amp;c?.txt</a>"));
+    }
+
+    /// Tests that the listing is generated as JSON when the client asks for
+    /// it and isn't decorated.
+    #[test]
+    fn json_by_accept()
+    {
+        let mut request = request("/src/");
+        request.headers_mut().set(Accept(vec![
+            qitem("application/json".parse().unwrap()),
+        ]));
+
+        let listing = DirectoryListing::new(".");
+        let view = listing.handle_request(&request, RouteMap::new()).unwrap();
+
+        assert_eq!("json", view.mime.subtype());
+        assert!(view.content.starts_with("{\"path\":\"/src/\",\"parent\":\"/\""));
+        assert!(view.content.contains("\"name\":\"view.rs\",\"type\":\"file\""));
+        assert_eq!(Some("Accept"), vary(&view));
+
+        let request = self::request("/src/");
+        let view = listing.handle_request(&request, RouteMap::new()).unwrap();
+        assert_eq!("html", view.mime.subtype());
+        assert_eq!(Some("Accept"), vary(&view));
+    }
+
+    /// Tests that the HTML listing is rendered through the decorator.
+    #[test]
+    fn renders_through_decorator()
+    {
+        let listing = DirectoryListing::new(".")
+            .decorator(decorator::Surround::new("<main>", "</main>"));
+        let view = listing.handle_request(&request("/"), RouteMap::new())
+            .unwrap();
+
+        assert!(view.content.starts_with("<main><h1>"));
+        assert!(view.content.ends_with("</table>\n</main>"));
+    }
+}
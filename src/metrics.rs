@@ -0,0 +1,453 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use hyper::{Method, Request, StatusCode};
+
+/// The route label of requests which didn't match any route.
+const UNMATCHED: &str = "(unmatched)";
+
+/// Records how many requests the server handles, how long they take and
+/// how many are being handled right now, and serves them in the Prometheus
+/// text format.
+///
+/// Requests are labelled by the spec of the route they matched (such as
+/// `/users/:id`, rather than `/users/7`), their method and the status of
+/// their response. Requests which didn't match a route are labelled with
+/// the `(unmatched)` route, and methods other than the standard ones with
+/// `other`, so that clients can't create new series at will.
+///
+/// ```rust,no_run
+/// use mwf::{ServerBuilder, Metrics};
+///
+/// ServerBuilder::new()
+///     .metrics(Metrics::new().path("/internal/metrics"))
+///     .start();
+/// ```
+pub struct Metrics
+{
+    /// The path the metrics are served on.
+    path: String,
+
+    /// The upper bounds of the latency histogram's buckets, in seconds.
+    buckets: Vec<f64>,
+
+    /// The number of requests being handled right now.
+    in_flight: AtomicUsize,
+
+    /// Every series recorded so far, sorted so that they're rendered in the
+    /// same order each time.
+    series: Mutex<BTreeMap<Labels, Series>>,
+}
+
+/// The labels of a single series.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct Labels
+{
+    route: String,
+    method: String,
+    status: u16,
+}
+
+/// What's been recorded for a single set of labels.
+struct Series
+{
+    /// The number of requests.
+    count: u64,
+
+    /// The total time spent on the requests, in seconds.
+    sum: f64,
+
+    /// The number of requests which fell in each bucket, not including the
+    /// requests in the buckets before it.
+    buckets: Vec<u64>,
+}
+
+/// A request which is being handled, counted by the in-flight gauge until
+/// it's finished or dropped, so that requests which panic or fail are still
+/// taken off.
+pub(crate) struct InFlight<'a>
+{
+    metrics: &'a Metrics,
+}
+
+//
+// Implementation
+//
+
+impl Metrics
+{
+    /// Creates the metrics, which are served on `/metrics` with the default
+    /// Prometheus latency buckets.
+    pub fn new() -> Self
+    {
+        Metrics {
+            path: "/metrics".to_string(),
+            buckets: vec![
+                0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+            ],
+            in_flight: AtomicUsize::new(0),
+            series: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Changes the path the metrics are served on. It takes precedence over
+    /// any route bound to the same path.
+    pub fn path<T: Into<String>>(mut self, path: T) -> Self
+    {
+        self.path = path.into();
+        self
+    }
+
+    /// Changes the upper bounds of the latency histogram's buckets, in
+    /// seconds. Bounds which aren't finite are left out, since every
+    /// histogram ends with a `+Inf` bucket anyway.
+    pub fn buckets(mut self, mut buckets: Vec<f64>) -> Self
+    {
+        buckets.retain(|it| it.is_finite());
+        buckets.sort_by(f64::total_cmp);
+        buckets.dedup();
+
+        self.buckets = buckets;
+        self
+    }
+
+    /// If the `request` asks for the metrics.
+    pub(crate) fn is_scrape(&self, request: &Request) -> bool
+    {
+        *request.method() == Method::Get && request.path() == self.path
+    }
+
+    /// Records that a request has started to be handled.
+    pub(crate) fn start(&self) -> InFlight<'_>
+    {
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        InFlight { metrics: self }
+    }
+
+    /// Records that the `request` to the `route` has been handled, which
+    /// took `duration`.
+    pub(crate) fn finish(
+        &self,
+        request: InFlight,
+        route: Option<&str>,
+        method: &Method,
+        status: StatusCode,
+        duration: Duration
+    )
+    {
+        drop(request);
+
+        let method = match *method {
+            Method::Extension(_) => "other".to_string(),
+            ref method => method.to_string(),
+        };
+        let labels = Labels {
+            route: route.unwrap_or(UNMATCHED).to_string(),
+            method,
+            status: status.as_u16(),
+        };
+        let seconds = duration.as_secs() as f64
+            + duration.subsec_nanos() as f64 / 1_000_000_000.0;
+
+        let mut series = self.series.lock().unwrap();
+        let buckets = self.buckets.len();
+        let series = series.entry(labels).or_insert_with(|| Series {
+            count: 0,
+            sum: 0.0,
+            buckets: vec![0; buckets],
+        });
+
+        series.count += 1;
+        series.sum += seconds;
+        if let Some(i) = self.buckets.iter().position(|&it| seconds <= it) {
+            series.buckets[i] += 1;
+        }
+    }
+
+    /// Renders every metric in the Prometheus text format.
+    pub fn render(&self) -> String
+    {
+        let series = self.series.lock().unwrap();
+        let mut out = String::new();
+
+        out.push_str(
+            "# HELP mwf_http_requests_total The number of HTTP requests \
+             handled.\n\
+             # TYPE mwf_http_requests_total counter\n"
+        );
+        for (labels, series) in series.iter() {
+            let _ = writeln!(
+                out,
+                "mwf_http_requests_total{{{}}} {}",
+                labels,
+                series.count
+            );
+        }
+
+        out.push_str(
+            "# HELP mwf_http_request_duration_seconds How long HTTP requests \
+             took to handle.\n\
+             # TYPE mwf_http_request_duration_seconds histogram\n"
+        );
+        for (labels, series) in series.iter() {
+            let mut cumulative = 0;
+            for (bound, count) in self.buckets.iter().zip(&series.buckets) {
+                cumulative += count;
+                let _ = writeln!(
+                    out,
+                    "mwf_http_request_duration_seconds_bucket{{{},le=\"{}\"}} {}",
+                    labels,
+                    bound,
+                    cumulative
+                );
+            }
+
+            let _ = writeln!(
+                out,
+                "mwf_http_request_duration_seconds_bucket{{{},le=\"+Inf\"}} {}\n\
+                 mwf_http_request_duration_seconds_sum{{{}}} {}\n\
+                 mwf_http_request_duration_seconds_count{{{}}} {}",
+                labels,
+                series.count,
+                labels,
+                series.sum,
+                labels,
+                series.count
+            );
+        }
+
+        let _ = writeln!(
+            out,
+            "# HELP mwf_http_requests_in_flight The number of HTTP requests \
+             being handled.\n\
+             # TYPE mwf_http_requests_in_flight gauge\n\
+             mwf_http_requests_in_flight {}",
+            self.in_flight.load(Ordering::SeqCst)
+        );
+
+        out
+    }
+}
+
+impl Default for Metrics
+{
+    fn default() -> Self
+    {
+        Metrics::new()
+    }
+}
+
+impl<'a> Drop for InFlight<'a>
+{
+    fn drop(&mut self)
+    {
+        self.metrics.in_flight.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl ::std::fmt::Display for Labels
+{
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result
+    {
+        write!(
+            f,
+            "route=\"{}\",method=\"{}\",status=\"{}\"",
+            escape_label(&self.route),
+            escape_label(&self.method),
+            self.status
+        )
+    }
+}
+
+/// Escapes a label value for the Prometheus text format.
+fn escape_label(value: &str) -> String
+{
+    value.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+
+    use builder::ServerBuilder;
+    use error::Result;
+    use request_handler::RequestHandler;
+    use resolution::RouteMap;
+    use view::View;
+
+    struct Handler;
+
+    impl RequestHandler for Handler
+    {
+        fn handle(&self, _route_map: RouteMap) -> Result<View>
+        {
+            Ok(View::raw("hi"))
+        }
+    }
+
+    /// Tests that series are labelled and rendered as expected.
+    #[test]
+    fn render()
+    {
+        let metrics = Metrics::new().buckets(vec![1.0, 0.1]);
+
+        metrics.finish(
+            metrics.start(),
+            Some("/say/\"hi\""),
+            &Method::Get,
+            StatusCode::Ok,
+            Duration::from_millis(50)
+        );
+        metrics.finish(
+            metrics.start(),
+            Some("/say/\"hi\""),
+            &Method::Get,
+            StatusCode::Ok,
+            Duration::from_millis(500)
+        );
+        metrics.finish(
+            metrics.start(),
+            None,
+            &Method::Post,
+            StatusCode::NotFound,
+            Duration::from_secs(2)
+        );
+
+        let labels = "route=\"/say/\\\"hi\\\"\",method=\"GET\",status=\"200\"";
+        let unmatched = "route=\"(unmatched)\",method=\"POST\",status=\"404\"";
+
+        assert_eq!(
+            format!(
+                "# HELP mwf_http_requests_total The number of HTTP requests handled.\n\
+                 # TYPE mwf_http_requests_total counter\n\
+                 mwf_http_requests_total{{{1}}} 1\n\
+                 mwf_http_requests_total{{{0}}} 2\n\
+                 # HELP mwf_http_request_duration_seconds How long HTTP requests took to handle.\n\
+                 # TYPE mwf_http_request_duration_seconds histogram\n\
+                 mwf_http_request_duration_seconds_bucket{{{1},le=\"0.1\"}} 0\n\
+                 mwf_http_request_duration_seconds_bucket{{{1},le=\"1\"}} 0\n\
+                 mwf_http_request_duration_seconds_bucket{{{1},le=\"+Inf\"}} 1\n\
+                 mwf_http_request_duration_seconds_sum{{{1}}} 2\n\
+                 mwf_http_request_duration_seconds_count{{{1}}} 1\n\
+                 mwf_http_request_duration_seconds_bucket{{{0},le=\"0.1\"}} 1\n\
+                 mwf_http_request_duration_seconds_bucket{{{0},le=\"1\"}} 2\n\
+                 mwf_http_request_duration_seconds_bucket{{{0},le=\"+Inf\"}} 2\n\
+                 mwf_http_request_duration_seconds_sum{{{0}}} 0.55\n\
+                 mwf_http_request_duration_seconds_count{{{0}}} 2\n\
+                 # HELP mwf_http_requests_in_flight The number of HTTP requests being handled.\n\
+                 # TYPE mwf_http_requests_in_flight gauge\n\
+                 mwf_http_requests_in_flight 0\n",
+                labels,
+                unmatched
+            ),
+            metrics.render()
+        );
+    }
+
+    /// Tests that the server records requests by their route spec, and
+    /// serves the metrics on the configured path.
+    #[test]
+    fn served()
+    {
+        let client = ServerBuilder::new()
+            .bind("/users/:id", Handler)
+            .metrics(Metrics::new().path("/stats"))
+            .test_client();
+
+        client.get("/users/1").send();
+        client.get("/users/2").send();
+        client.get("/missing").send();
+
+        let response = client.get("/stats").send();
+        response
+            .assert_status(StatusCode::Ok)
+            .assert_header("Content-Type", "text/plain; version=0.0.4")
+            .assert_body_contains(
+                "mwf_http_requests_total{route=\"/users/:id\",method=\"GET\",\
+                 status=\"200\"} 2\n"
+            )
+            .assert_body_contains(
+                "mwf_http_requests_total{route=\"(unmatched)\",method=\"GET\",\
+                 status=\"404\"} 1\n"
+            );
+
+        // scrapes aren't recorded themselves
+        assert!(!response.text().contains("/stats"));
+        response.assert_body_contains("mwf_http_requests_in_flight 0\n");
+    }
+
+    /// Tests that the in-flight gauge counts requests until they're
+    /// finished, or dropped when they panic or fail.
+    #[test]
+    fn in_flight()
+    {
+        use std::panic::{self, AssertUnwindSafe};
+
+        let metrics = Metrics::new();
+        let gauge = |count: usize| format!(
+            "# TYPE mwf_http_requests_in_flight gauge\n\
+             mwf_http_requests_in_flight {}\n",
+            count
+        );
+
+        let first = metrics.start();
+        let second = metrics.start();
+        assert!(metrics.render().ends_with(&gauge(2)));
+
+        metrics.finish(
+            first,
+            Some("/"),
+            &Method::Get,
+            StatusCode::Ok,
+            Duration::from_millis(1)
+        );
+        assert!(metrics.render().ends_with(&gauge(1)));
+
+        drop(second);
+        assert!(metrics.render().ends_with(&gauge(0)));
+
+        let panicked = panic::catch_unwind(AssertUnwindSafe(|| {
+            let _request = metrics.start();
+            assert!(metrics.render().ends_with(&gauge(1)));
+            panic!("oh no");
+        }));
+        assert!(panicked.is_err());
+        assert!(metrics.render().ends_with(&gauge(0)));
+    }
+
+    /// Tests that non-standard methods share a single label.
+    #[test]
+    fn other_methods()
+    {
+        let metrics = Metrics::new();
+        for method in &["PURGE", "X-ANYTHING"] {
+            metrics.finish(
+                metrics.start(),
+                None,
+                &method.parse().unwrap(),
+                StatusCode::NotFound,
+                Duration::from_millis(1)
+            );
+        }
+
+        assert!(metrics.render().contains(
+            "mwf_http_requests_total{route=\"(unmatched)\",method=\"other\",\
+             status=\"404\"} 2\n"
+        ));
+    }
+
+    /// Tests that bounds which aren't finite are left out of the buckets.
+    #[test]
+    fn infinite_buckets()
+    {
+        let buckets = vec![1.0, f64::NAN, 0.5, f64::INFINITY];
+        let metrics = Metrics::new().buckets(buckets);
+        assert_eq!(vec![0.5, 1.0], metrics.buckets);
+    }
+}
@@ -0,0 +1,473 @@
+use std::fs::{self, File};
+use std::io::{self, Cursor, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use hyper::server::{Request, Response};
+use hyper::{Body, StatusCode};
+use hyper::header::{AcceptRanges, ContentLength, ContentType};
+use hyper::header::{ContentRange, ContentRangeSpec};
+use hyper::header::{ETag, EntityTag, HttpDate, IfRange, LastModified};
+use hyper::header::{Range, RangeUnit};
+
+use mime_guess::Mime;
+
+use error::Result;
+use stream::BodyStream;
+
+/// The most ranges a single response is split into. Requests for more (once
+/// overlapping ones are merged) get the full representation instead, so a
+/// small request can't ask for a response many times the size of the file.
+const MAX_RANGES: usize = 16;
+
+/// Used to make each multipart boundary unique.
+static BOUNDARY_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// The validators of a file-backed response, used to check `If-Range`
+/// preconditions.
+pub struct Validators
+{
+    pub etag: EntityTag,
+    pub last_modified: Option<HttpDate>,
+}
+
+/// The parts of a representation the client asked for.
+#[derive(Debug, PartialEq)]
+pub enum Ranges
+{
+    /// The entire representation should be sent (no range was requested,
+    /// or the `If-Range` precondition failed).
+    Full,
+
+    /// Only the given inclusive byte ranges should be sent.
+    Partial(Vec<(u64, u64)>),
+
+    /// None of the requested ranges overlap with the representation.
+    Unsatisfiable,
+}
+
+impl Validators
+{
+    /// Creates the validators for a body of `length` bytes read from the file
+    /// at `path`. The entity tag depends on both the length of the body and
+    /// the modification time of the file, so it changes if either the file
+    /// or the way it's decorated changes.
+    pub fn new(path: &Path, length: u64) -> Self
+    {
+        let modified = fs::metadata(path)
+            .and_then(|it| it.modified())
+            .ok();
+
+        let seconds = modified
+            .and_then(|it| it.duration_since(UNIX_EPOCH).ok())
+            .map(|it| it.as_secs())
+            .unwrap_or(0);
+
+        Validators {
+            etag: EntityTag::strong(format!("{:x}-{:x}", length, seconds)),
+            last_modified: modified.map(HttpDate::from),
+        }
+    }
+
+    /// Checks if the `If-Range` precondition (if any) on the `request` is
+    /// met, meaning that a partial response may be sent.
+    fn if_range(&self, request: &Request) -> bool
+    {
+        match request.headers().get::<IfRange>() {
+            None => true,
+            Some(&IfRange::EntityTag(ref tag)) => tag.strong_eq(&self.etag),
+            Some(&IfRange::Date(ref date)) => {
+                self.last_modified.as_ref() == Some(date)
+            },
+        }
+    }
+}
+
+/// Determines which ranges of a representation of `length` bytes the
+/// `request` asked for. Only byte ranges are understood; any other range
+/// unit is ignored and the full representation is sent instead.
+pub fn requested(request: &Request, length: u64, validators: &Validators)
+    -> Ranges
+{
+    let specs = match request.headers().get::<Range>() {
+        Some(&Range::Bytes(ref specs)) => specs,
+        _ => return Ranges::Full,
+    };
+
+    if !validators.if_range(request) {
+        return Ranges::Full;
+    }
+
+    let ranges = merge(specs.iter()
+        .filter_map(|it| it.to_satisfiable_range(length))
+        .collect());
+
+    if ranges.is_empty() {
+        Ranges::Unsatisfiable
+    }
+    else if ranges.len() > MAX_RANGES {
+        Ranges::Full
+    }
+    else {
+        Ranges::Partial(ranges)
+    }
+}
+
+/// Sorts the inclusive `ranges` and merges those which overlap or touch, so
+/// that no byte is sent twice.
+fn merge(mut ranges: Vec<(u64, u64)>) -> Vec<(u64, u64)>
+{
+    ranges.sort();
+
+    let mut merged: Vec<(u64, u64)> = Vec::with_capacity(ranges.len());
+    for (from, to) in ranges {
+        if let Some(last) = merged.last_mut() {
+            if from <= last.1.saturating_add(1) {
+                last.1 = last.1.max(to);
+                continue;
+            }
+        }
+        merged.push((from, to));
+    }
+
+    merged
+}
+
+/// The body of a file-backed response, which the requested ranges are taken
+/// out of.
+pub enum Representation
+{
+    /// The content of the view, which has already been read into memory.
+    Buffered(Vec<u8>),
+
+    /// The file itself, which is streamed from disk.
+    File(File),
+}
+
+/// A piece of a (possibly multipart) response body.
+#[derive(Debug, PartialEq)]
+enum Piece
+{
+    /// Text which isn't part of the representation (i.e. part headers).
+    Text(Vec<u8>),
+
+    /// The inclusive range of bytes from the representation.
+    Bytes(u64, u64),
+}
+
+impl Representation
+{
+    /// The length of the representation, in bytes.
+    fn len(&self) -> u64
+    {
+        match self {
+            &Representation::Buffered(ref content) => content.len() as u64,
+            &Representation::File(ref file) => {
+                file.metadata().map(|it| it.len()).unwrap_or(0)
+            },
+        }
+    }
+
+    /// Converts the given `pieces` of this representation (which was read
+    /// from the file at `path`) into a response body.
+    fn into_body(self, path: &Path, pieces: Vec<Piece>) -> Result<Body>
+    {
+        let content = match self {
+            Representation::Buffered(content) => content,
+
+            // a single piece covering the whole file can just be streamed
+            // straight from the file we already have
+            Representation::File(file) => {
+                let length = file.metadata()?.len();
+                if pieces == vec![Piece::Bytes(0, length - 1)] {
+                    return Ok(BodyStream::file(file).into_body());
+                }
+
+                let mut reader: Box<Read + Send> = Box::new(io::empty());
+                for piece in pieces {
+                    reader = match piece {
+                        Piece::Text(text) => {
+                            Box::new(reader.chain(Cursor::new(text)))
+                        },
+
+                        Piece::Bytes(from, to) => {
+                            let mut file = File::open(path)?;
+                            file.seek(SeekFrom::Start(from))?;
+                            Box::new(reader.chain(file.take(to - from + 1)))
+                        },
+                    };
+                }
+
+                return Ok(BodyStream::reader(reader).into_body());
+            },
+        };
+
+        let mut body = Vec::new();
+        for piece in pieces {
+            match piece {
+                Piece::Text(text) => body.extend_from_slice(&text),
+                Piece::Bytes(from, to) => {
+                    body.extend_from_slice(&content[from as usize..to as usize + 1])
+                },
+            }
+        }
+
+        Ok(body.into())
+    }
+}
+
+/// Creates the response for the `body` of a file-backed view with the given
+/// `mime` type, which was read from the file at `path`. This will be a
+/// `206 Partial Content` or a `416 Range Not Satisfiable` response if the
+/// `request` asked for only parts of it.
+pub fn respond(request: &Request, body: Representation, mime: Mime, path: &Path)
+    -> Result<Response>
+{
+    let length = body.len();
+    let validators = Validators::new(path, length);
+
+    let mut response = Response::new();
+    response.headers_mut().set(AcceptRanges(vec![RangeUnit::Bytes]));
+    response.headers_mut().set(ETag(validators.etag.clone()));
+    if let Some(date) = validators.last_modified {
+        response.headers_mut().set(LastModified(date));
+    }
+
+    let pieces = match requested(request, length, &validators) {
+        Ranges::Full => {
+            response.headers_mut().set(ContentType(mime));
+            if length == 0 {
+                Vec::new()
+            }
+            else {
+                vec![Piece::Bytes(0, length - 1)]
+            }
+        },
+
+        Ranges::Unsatisfiable => {
+            response.set_status(StatusCode::RangeNotSatisfiable);
+            response.headers_mut().set(ContentRange(ContentRangeSpec::Bytes {
+                range: None,
+                instance_length: Some(length),
+            }));
+            Vec::new()
+        },
+
+        Ranges::Partial(ref ranges) if ranges.len() == 1 => {
+            let (from, to) = ranges[0];
+
+            response.set_status(StatusCode::PartialContent);
+            response.headers_mut().set(ContentType(mime));
+            response.headers_mut().set(ContentRange(ContentRangeSpec::Bytes {
+                range: Some((from, to)),
+                instance_length: Some(length),
+            }));
+            vec![Piece::Bytes(from, to)]
+        },
+
+        Ranges::Partial(ranges) => {
+            let boundary = boundary();
+
+            response.set_status(StatusCode::PartialContent);
+            response.headers_mut().set(ContentType(
+                format!("multipart/byteranges; boundary={}", boundary)
+                    .parse()
+                    .unwrap()
+            ));
+            multipart(&ranges, length, &mime, &boundary)
+        },
+    };
+
+    let size = pieces.iter()
+        .map(|it| match it {
+            &Piece::Text(ref text) => text.len() as u64,
+            &Piece::Bytes(from, to) => to - from + 1,
+        })
+        .sum();
+    response.headers_mut().set(ContentLength(size));
+
+    if size > 0 {
+        response.set_body(body.into_body(path, pieces)?);
+    }
+
+    Ok(response)
+}
+
+/// Generates a new boundary for a `multipart/byteranges` body.
+fn boundary() -> String
+{
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|it| it.subsec_nanos())
+        .unwrap_or(0);
+    let count = BOUNDARY_COUNTER.fetch_add(1, Ordering::Relaxed);
+
+    format!("mwf-byteranges-{:08x}{:08x}", nanos, count)
+}
+
+/// Lays out a `multipart/byteranges` body for the given `ranges` of a
+/// representation that's `length` bytes long, with each part separated by
+/// the `boundary`.
+fn multipart(ranges: &[(u64, u64)], length: u64, mime: &Mime, boundary: &str)
+    -> Vec<Piece>
+{
+    let mut pieces = Vec::new();
+
+    for &(from, to) in ranges {
+        pieces.push(Piece::Text(format!(
+            "\r\n--{}\r\nContent-Type: {}\r\nContent-Range: bytes {}-{}/{}\r\n\r\n",
+            boundary,
+            mime,
+            from,
+            to,
+            length
+        ).into_bytes()));
+        pieces.push(Piece::Bytes(from, to));
+    }
+
+    pieces.push(Piece::Text(format!("\r\n--{}--\r\n", boundary).into_bytes()));
+    pieces
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+    use futures::{Future, Stream};
+    use hyper::Method;
+    use hyper::header::ByteRangeSpec;
+
+    /// Creates a GET request with the given `range` header.
+    fn request(range: Range) -> Request
+    {
+        let mut request = Request::new(Method::Get, "/".parse().unwrap());
+        request.headers_mut().set(range);
+        request
+    }
+
+    /// Validators for a ten byte body read from this file.
+    fn validators() -> Validators
+    {
+        Validators::new(Path::new("src/range.rs"), 10)
+    }
+
+    /// Tests that requests without a `Range` header get the full body.
+    #[test]
+    fn no_range_is_full()
+    {
+        let request = Request::new(Method::Get, "/".parse().unwrap());
+        assert_eq!(Ranges::Full, requested(&request, 10, &validators()));
+    }
+
+    /// Tests that open-ended and suffix ranges are resolved against the
+    /// length of the body.
+    #[test]
+    fn resolves_ranges()
+    {
+        let request = request(Range::Bytes(vec![
+            ByteRangeSpec::FromTo(0, 1),
+            ByteRangeSpec::AllFrom(8),
+            ByteRangeSpec::Last(3),
+            ByteRangeSpec::FromTo(5, 100),
+        ]));
+
+        assert_eq!(
+            Ranges::Partial(vec![(0, 1), (5, 9)]),
+            requested(&request, 10, &validators())
+        );
+    }
+
+    /// Tests that overlapping and touching ranges are merged, and that
+    /// asking for too many ranges gets the full body.
+    #[test]
+    fn merges_ranges()
+    {
+        assert_eq!(
+            vec![(0, 4), (6, 9)],
+            merge(vec![(6, 7), (2, 3), (0, 2), (4, 4), (7, 9), (8, 8)])
+        );
+
+        let overlapping = request(Range::Bytes(
+            (0..100).map(|i| ByteRangeSpec::FromTo(0, i)).collect()
+        ));
+        assert_eq!(
+            Ranges::Partial(vec![(0, 9)]),
+            requested(&overlapping, 10, &validators())
+        );
+
+        let scattered = request(Range::Bytes(
+            (0..20).map(|i| ByteRangeSpec::FromTo(i * 2, i * 2)).collect()
+        ));
+        assert_eq!(Ranges::Full, requested(&scattered, 100, &validators()));
+    }
+
+    /// Tests that ranges entirely past the end of the body are
+    /// unsatisfiable.
+    #[test]
+    fn unsatisfiable_ranges()
+    {
+        let request = request(Range::bytes(10, 20));
+        assert_eq!(
+            Ranges::Unsatisfiable,
+            requested(&request, 10, &validators())
+        );
+    }
+
+    /// Tests that a failed `If-Range` precondition results in the full body
+    /// being sent, and a matching one in the range being sent.
+    #[test]
+    fn if_range()
+    {
+        let validators = validators();
+
+        let mut stale = request(Range::bytes(0, 1));
+        stale.headers_mut().set(IfRange::EntityTag(
+            EntityTag::strong("stale".into())
+        ));
+        assert_eq!(Ranges::Full, requested(&stale, 10, &validators));
+
+        let mut fresh = request(Range::bytes(0, 1));
+        fresh.headers_mut().set(IfRange::EntityTag(validators.etag.clone()));
+        assert_eq!(
+            Ranges::Partial(vec![(0, 1)]),
+            requested(&fresh, 10, &validators)
+        );
+    }
+
+    /// Tests the layout of a `multipart/byteranges` body.
+    #[test]
+    fn multipart_body()
+    {
+        let mime: Mime = "text/plain".parse().unwrap();
+        let pieces = multipart(&[(0, 1), (8, 9)], 10, &mime, "XYZ");
+        let body = Representation::Buffered(b"0123456789".to_vec())
+            .into_body(Path::new("src/range.rs"), pieces)
+            .unwrap();
+        let body = body.concat2().wait().unwrap().to_vec();
+
+        assert_eq!(
+            "\r\n--XYZ\r\nContent-Type: text/plain\r\n\
+             Content-Range: bytes 0-1/10\r\n\r\n01\
+             \r\n--XYZ\r\nContent-Type: text/plain\r\n\
+             Content-Range: bytes 8-9/10\r\n\r\n89\
+             \r\n--XYZ--\r\n",
+            String::from_utf8(body).unwrap()
+        );
+    }
+
+    /// Tests that ranges of a streamed file are read from the right place on
+    /// disk.
+    #[test]
+    fn file_ranges()
+    {
+        let path = Path::new("src/range.rs");
+        let file = File::open(path).unwrap();
+        let body = Representation::File(file)
+            .into_body(path, vec![Piece::Bytes(4, 6), Piece::Bytes(0, 2)])
+            .unwrap();
+        let body = body.concat2().wait().unwrap().to_vec();
+
+        assert_eq!("stduse", String::from_utf8(body).unwrap());
+    }
+}
@@ -0,0 +1,423 @@
+use std::any::Any;
+use std::error::Error as StdError;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use hyper::Method;
+use hyper::server::Request;
+
+use error::Error;
+
+/// The header a request ID is read from, and sent back in. Every response
+/// has one: the ID the client (or a proxy in front of the server) sent, or
+/// else one made up for the request.
+pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
+
+/// Used to make the request IDs generated by this process unique.
+static REQUEST_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Receives the errors returned by request handlers, which have already been
+/// turned into `500 Internal Server Error` responses.
+///
+/// Any closure taking an [ErrorReport] is a reporter too.
+///
+/// ```rust,no_run
+/// use mwf::{ServerBuilder, ErrorReport};
+///
+/// ServerBuilder::new()
+///     .error_reporter(|report: &ErrorReport| {
+///         eprintln!("[{}] {}", report.request_id(), report);
+///     })
+///     .start();
+/// ```
+pub trait ErrorReporter
+    where Self: Send + Sync
+{
+    /// Reports a single failed request.
+    fn report(&self, report: &ErrorReport);
+}
+
+/// Reports errors through the `log` crate at the error level, with the
+/// `mwf::error` target. This is the default reporter.
+pub struct LogReporter;
+
+/// The details of a request which failed.
+///
+/// Its `Display` implementation formats every one of them on a single line,
+/// such as:
+///
+/// ```text
+/// GET /users/7 failed (request 5f0e9c1a00000003): database error: caused by: connection refused
+/// ```
+pub struct ErrorReport<'a>
+{
+    error: &'a Error,
+    method: &'a Method,
+    path: &'a str,
+    request_id: &'a str,
+}
+
+/// The error reported when a handler (or resolver) panics. Reporters can
+/// find it by downcasting an [ErrorReport]'s error.
+#[derive(Debug)]
+pub struct Panic
+{
+    message: String,
+}
+
+//
+// Implementation
+//
+
+impl<'a> ErrorReport<'a>
+{
+    pub(crate) fn new(
+        error: &'a Error,
+        method: &'a Method,
+        path: &'a str,
+        request_id: &'a str
+    ) -> Self
+    {
+        ErrorReport {
+            error,
+            method,
+            path,
+            request_id,
+        }
+    }
+
+    /// The error the handler returned.
+    pub fn error(&self) -> &Error
+    {
+        self.error
+    }
+
+    /// The error's causes, starting with the one which caused it directly.
+    pub fn causes(&self) -> Vec<&(StdError + 'static)>
+    {
+        let mut causes = Vec::new();
+
+        let mut cause = self.error.source();
+        while let Some(error) = cause {
+            causes.push(error);
+            cause = error.source();
+        }
+
+        causes
+    }
+
+    /// The method of the failed request.
+    pub fn method(&self) -> &Method
+    {
+        self.method
+    }
+
+    /// The path (and query string) of the failed request.
+    pub fn path(&self) -> &str
+    {
+        self.path
+    }
+
+    /// The ID of the failed request, which is also sent back to the client
+    /// in the `X-Request-Id` header. If the client (or a proxy in front of
+    /// the server) sent a request ID, that one is used.
+    pub fn request_id(&self) -> &str
+    {
+        self.request_id
+    }
+}
+
+impl<'a> ::std::fmt::Display for ErrorReport<'a>
+{
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result
+    {
+        write!(
+            f,
+            "{} {} failed (request {}): {}",
+            self.method,
+            self.path,
+            self.request_id,
+            self.error
+        )?;
+
+        for cause in self.causes() {
+            write!(f, ": caused by: {}", cause)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<F> ErrorReporter for F
+    where F: Fn(&ErrorReport) + Send + Sync
+{
+    fn report(&self, report: &ErrorReport)
+    {
+        self(report)
+    }
+}
+
+impl ErrorReporter for LogReporter
+{
+    fn report(&self, report: &ErrorReport)
+    {
+        error!(target: "mwf::error", "{}", report);
+    }
+}
+
+impl Panic
+{
+    /// Creates the error from the `payload` a panic unwound with.
+    pub(crate) fn new(payload: Box<Any + Send>) -> Self
+    {
+        let message = match payload.downcast::<String>() {
+            Ok(message) => *message,
+            Err(payload) => match payload.downcast_ref::<&str>() {
+                Some(message) => message.to_string(),
+                None => "Box<Any>".to_string(),
+            },
+        };
+
+        Panic {
+            message,
+        }
+    }
+
+    /// The message the handler panicked with.
+    pub fn message(&self) -> &str
+    {
+        &self.message
+    }
+}
+
+impl ::std::fmt::Display for Panic
+{
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result
+    {
+        write!(f, "handler panicked: {}", self.message)
+    }
+}
+
+impl StdError for Panic {}
+
+/// Finds the ID of the `request`, generating a new one if it wasn't given.
+pub(crate) fn request_id(request: &Request) -> String
+{
+    let given = request.headers().get_raw(REQUEST_ID_HEADER)
+        .and_then(|it| it.one())
+        .and_then(|it| ::std::str::from_utf8(it).ok())
+        .filter(|it| !it.is_empty() && it.len() <= 200);
+
+    if let Some(id) = given {
+        return id.to_string();
+    }
+
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|it| it.subsec_nanos())
+        .unwrap_or(0);
+    let count = REQUEST_COUNTER.fetch_add(1, Ordering::Relaxed);
+
+    format!("{:08x}{:08x}", nanos, count)
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+    use std::fmt;
+    use std::io;
+    use std::sync::{Arc, Mutex};
+
+    use builder::ServerBuilder;
+    use error::Result;
+    use hyper::StatusCode;
+    use request_handler::RequestHandler;
+    use resolution::RouteMap;
+    use view::View;
+
+    /// An error which wraps another one.
+    #[derive(Debug)]
+    struct Wrapper(io::Error);
+
+    impl fmt::Display for Wrapper
+    {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+        {
+            f.write_str("query failed")
+        }
+    }
+
+    impl StdError for Wrapper
+    {
+        fn source(&self) -> Option<&(StdError + 'static)>
+        {
+            Some(&self.0)
+        }
+    }
+
+    struct Broken;
+
+    impl RequestHandler for Broken
+    {
+        fn handle(&self, _route_map: RouteMap) -> Result<View>
+        {
+            let cause = io::Error::other("disk on fire");
+            Err(Error::Other(Box::new(Wrapper(cause))))
+        }
+    }
+
+    /// Tests that errors are reported along with the request which caused
+    /// them, and that the request ID is sent back to the client.
+    #[test]
+    fn reports_errors()
+    {
+        let reports = Arc::new(Mutex::new(Vec::new()));
+        let sink = reports.clone();
+
+        let client = ServerBuilder::new()
+            .bind("/broken", Broken)
+            .error_reporter(move |report: &ErrorReport| {
+                assert_eq!(1, report.causes().len());
+                let entry = (report.request_id().to_string(), report.to_string());
+                sink.lock().unwrap().push(entry);
+            })
+            .test_client();
+
+        let response = client.get("/broken?page=2").send();
+        response.assert_status(StatusCode::InternalServerError);
+
+        let id = response.header(REQUEST_ID_HEADER).unwrap().to_string();
+        assert_eq!(16, id.len());
+
+        client.get("/broken")
+            .header(REQUEST_ID_HEADER, "from-proxy")
+            .send()
+            .assert_header(REQUEST_ID_HEADER, "from-proxy");
+
+        let reports = reports.lock().unwrap();
+        assert_eq!(
+            vec![
+                (
+                    id.clone(),
+                    format!(
+                        "GET /broken?page=2 failed (request {}): query failed: \
+                         caused by: disk on fire",
+                        id
+                    ),
+                ),
+                (
+                    "from-proxy".to_string(),
+                    "GET /broken failed (request from-proxy): query failed: \
+                     caused by: disk on fire".to_string(),
+                ),
+            ],
+            *reports
+        );
+    }
+
+    struct Panicking;
+
+    impl RequestHandler for Panicking
+    {
+        fn handle(&self, route_map: RouteMap) -> Result<View>
+        {
+            panic!("no page {}", route_map.get(":page").unwrap())
+        }
+    }
+
+    /// Tests that a panicking handler is reported like any other error, and
+    /// that the server keeps serving requests afterwards.
+    #[test]
+    fn reports_panics()
+    {
+        let reports = Arc::new(Mutex::new(Vec::new()));
+        let sink = reports.clone();
+
+        let client = ServerBuilder::new()
+            .bind("/panic/:page", Panicking)
+            .bind("/broken", Broken)
+            .error_reporter(move |report: &ErrorReport| {
+                let message = match *report.error() {
+                    Error::Other(ref error) => error.downcast_ref::<Panic>()
+                        .map(|it| it.message().to_string()),
+                    _ => None,
+                };
+                sink.lock().unwrap().push((report.to_string(), message));
+            })
+            .test_client();
+
+        client.get("/panic/7")
+            .header(REQUEST_ID_HEADER, "abc")
+            .send()
+            .assert_status(StatusCode::InternalServerError)
+            .assert_header(REQUEST_ID_HEADER, "abc");
+        client.get("/broken")
+            .send()
+            .assert_status(StatusCode::InternalServerError);
+
+        let reports = reports.lock().unwrap();
+        assert_eq!(2, reports.len());
+        assert_eq!(
+            (
+                "GET /panic/7 failed (request abc): handler panicked: no page 7"
+                    .to_string(),
+                Some("no page 7".to_string()),
+            ),
+            reports[0]
+        );
+        assert_eq!(None, reports[1].1);
+    }
+
+    /// Tests that successful responses are given a request ID too, which is
+    /// also written to the access log.
+    #[test]
+    fn ids_for_every_request()
+    {
+        use access_log::{AccessLog, LogFormat};
+
+        struct Fine;
+
+        impl RequestHandler for Fine
+        {
+            fn handle(&self, _route_map: RouteMap) -> Result<View>
+            {
+                Ok(View::raw("fine"))
+            }
+        }
+
+        let lines = Arc::new(Mutex::new(Vec::new()));
+        let sink = lines.clone();
+        let log = AccessLog::new(LogFormat::Json).sink(move |line: &str| {
+            sink.lock().unwrap().push(line.to_string())
+        });
+
+        let client = ServerBuilder::new()
+            .bind("/", Fine)
+            .access_log(log)
+            .test_client();
+
+        let response = client.get("/").send();
+        response.assert_status(StatusCode::Ok);
+        let id = response.header(REQUEST_ID_HEADER).unwrap().to_string();
+        assert_eq!(16, id.len());
+
+        client.get("/missing")
+            .header(REQUEST_ID_HEADER, "from-proxy")
+            .send()
+            .assert_status(StatusCode::NotFound)
+            .assert_header(REQUEST_ID_HEADER, "from-proxy");
+
+        let lines = lines.lock().unwrap();
+        assert!(lines[0].contains(&format!("\"request_id\":\"{}\"", id)));
+        assert!(lines[1].contains("\"request_id\":\"from-proxy\""));
+    }
+
+    /// Tests that generated request IDs are unique.
+    #[test]
+    fn unique_ids()
+    {
+        let request = Request::new(Method::Get, "/".parse().unwrap());
+        assert!(request_id(&request) != request_id(&request));
+    }
+}
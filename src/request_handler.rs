@@ -1,3 +1,5 @@
+use hyper::Request;
+
 use resolution::RouteMap;
 use view::View;
 use error::Result;
@@ -9,4 +11,15 @@ pub trait RequestHandler
 {
     /// Handles the request and returns the view to display.
     fn handle(&self, route_map: RouteMap) -> Result<View>;
+
+    /// Handles the request with access to the full `request` (its headers,
+    /// query string, etc.) as well as the `route_map`.
+    ///
+    /// By default this simply ignores the request and calls [handle], so
+    /// only handlers which need more than the route map have to override it.
+    fn handle_request(&self, _request: &Request, route_map: RouteMap)
+        -> Result<View>
+    {
+        self.handle(route_map)
+    }
 }
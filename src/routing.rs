@@ -1,7 +1,10 @@
+use std::sync::Arc;
+
 use hyper::{Method, Request};
 
 use resolution::*;
 use request_handler::RequestHandler;
+use websocket::WebSocketHandler;
 use view::View;
 use error::Result;
 
@@ -13,10 +16,19 @@ type ResolverConstructor = Fn(Method, Vec<String>) -> Box<Resolver>;
 /// Really, it's nothing more than a named tuple.
 struct ResolverEntry
 {
+    pub spec: String,
     pub resolver: Box<Resolver>,
     pub handler: Box<RequestHandler>,
 }
 
+/// An entry in the [Router]/[RouterBuilder]'s WebSocket vector.
+struct SocketEntry
+{
+    pub spec: String,
+    pub resolver: Box<Resolver>,
+    pub handler: Arc<WebSocketHandler>,
+}
+
 /// Helps construct a thread-safe [Router] by using non-thread-safe operations
 /// (such as creating new [Resolvers](Resolver)), until the server is spawned,
 /// at which case this will be converted into a router.
@@ -24,6 +36,7 @@ pub struct RouterBuilder
 {
     constructor: Box<ResolverConstructor>,
     resolvers: Vec<ResolverEntry>,
+    sockets: Vec<SocketEntry>,
 }
 
 /// A thread-safe list of all [Resolvers](Resolver) and their corresponding
@@ -32,6 +45,7 @@ pub struct RouterBuilder
 pub struct Router
 {
     resolvers: Vec<ResolverEntry>,
+    sockets: Vec<SocketEntry>,
 }
 
 //
@@ -42,25 +56,19 @@ impl Router
 {
     /// Tries to handle the given `request`. If no resolvers accept the route
     /// then it will return `None`, indicating an Http Status 404.
-    pub fn handle(&self, request: Request) -> Option<Result<View>>
+    pub fn handle(&self, request: &Request) -> Option<Result<View>>
     {
-        let method = request.method();
-        let route: Vec<&str> = request.path()
-            .split("/")
-            .filter_map(|it| {
-                if it.is_empty() {
-                    None
-                }
-                else {
-                    Some(it)
-                }
-            })
-            .collect();
+        let (_, handler, data) = self.resolve(request)?;
+        Some(handler.handle_request(request, data))
+    }
 
-        let params = ResolveParams {
-            method: method.clone(),
-            route,
-        };
+    /// Finds the handler for the given `request` without calling it,
+    /// returning it along with the route spec it was bound to, such as
+    /// `/users/:id`.
+    pub(crate) fn resolve(&self, request: &Request)
+        -> Option<(&str, &RequestHandler, RouteMap)>
+    {
+        let params = route_params(request);
 
         for entry in &self.resolvers {
             let data = match entry.resolver.resolve(&params) {
@@ -68,13 +76,53 @@ impl Router
                 Some(x) => x,
             };
 
-            return Some(entry.handler.handle(data));
+            return Some((&entry.spec, &*entry.handler, data));
+        }
+
+        None
+    }
+
+    /// Tries to find the WebSocket handler bound to the route of the given
+    /// upgrade `request`, along with its route spec. If no resolvers accept
+    /// the route, then `None` is returned and the request should be handled
+    /// normally.
+    pub(crate) fn upgrade(&self, request: &Request)
+        -> Option<(&str, Arc<WebSocketHandler>, RouteMap)>
+    {
+        let params = route_params(request);
+
+        for entry in &self.sockets {
+            if let Some(data) = entry.resolver.resolve(&params) {
+                return Some((&entry.spec, entry.handler.clone(), data));
+            }
         }
 
         None
     }
 }
 
+/// Normalizes and splits the path of the `request` into the parameters given
+/// to each resolver.
+fn route_params(request: &Request) -> ResolveParams<'_>
+{
+    let route: Vec<&str> = request.path()
+        .split("/")
+        .filter_map(|it| {
+            if it.is_empty() {
+                None
+            }
+            else {
+                Some(it)
+            }
+        })
+        .collect();
+
+    ResolveParams {
+        method: request.method().clone(),
+        route,
+    }
+}
+
 impl RouterBuilder
 {
     /// Constructs a new router builder which uses the [StandardResolver] by
@@ -84,6 +132,7 @@ impl RouterBuilder
         RouterBuilder {
             constructor: Box::new(StandardResolver::new),
             resolvers: Vec::new(),
+            sockets: Vec::new(),
         }
     }
 
@@ -103,6 +152,29 @@ impl RouterBuilder
         handler: H
     )
         where H: RequestHandler
+    {
+        let (spec, resolver) = self.resolver(method, spec);
+        self.resolvers.push(ResolverEntry::new(spec, resolver, handler));
+    }
+
+    /// Binds a new WebSocket `handler` to the given route `spec`. Upgrade
+    /// requests to this route will be handed to the handler.
+    pub fn websocket<T: Into<String>, H: 'static>(&mut self, spec: T, handler: H)
+        where H: WebSocketHandler
+    {
+        let (spec, resolver) = self.resolver(Method::Get, spec);
+        self.sockets.push(SocketEntry {
+            spec,
+            resolver,
+            handler: Arc::new(handler),
+        });
+    }
+
+    /// Splits the route `spec` and constructs a new resolver for it and the
+    /// given `method`. The spec is returned again with its slashes
+    /// normalized.
+    fn resolver<T: Into<String>>(&self, method: Method, spec: T)
+        -> (String, Box<Resolver>)
     {
         let spec: String = spec.into();
 
@@ -119,13 +191,10 @@ impl RouterBuilder
             .collect();
 
 
+        let normalized = format!("/{}", spec.join("/"));
+
         let constructor = &self.constructor;
-        self.resolvers.push(
-            ResolverEntry::new(
-                constructor(method, spec),
-                handler
-            )
-        );
+        (normalized, constructor(method, spec))
     }
 }
 
@@ -135,16 +204,19 @@ impl Into<Router> for RouterBuilder
     {
         Router {
             resolvers: self.resolvers,
+            sockets: self.sockets,
         }
     }
 }
 
 impl ResolverEntry
 {
-    pub fn new<H: 'static>(resolver: Box<Resolver>, handler: H) -> Self
+    pub fn new<H: 'static>(spec: String, resolver: Box<Resolver>, handler: H)
+        -> Self
         where H: RequestHandler
     {
         ResolverEntry {
+            spec,
             resolver,
             handler: Box::new(handler),
         }
@@ -187,4 +259,24 @@ mod test
         assert_eq!(true, *called);
     }
 
+    /// Tests that the spec of the matching route is found, with its slashes
+    /// normalized.
+    #[test]
+    fn resolves_specs()
+    {
+        let mut builder = RouterBuilder::new();
+        builder.bind(Method::Get, "", Handler);
+        builder.bind(Method::Get, "users//:id/", Handler);
+        let router: Router = builder.into();
+
+        let spec = |path: &str| {
+            let request = Request::new(Method::Get, path.parse().unwrap());
+            router.resolve(&request).map(|(spec, _, _)| spec.to_string())
+        };
+
+        assert_eq!(Some("/".to_string()), spec("/"));
+        assert_eq!(Some("/users/:id".to_string()), spec("/users/7"));
+        assert_eq!(None, spec("/users"));
+    }
+
 }
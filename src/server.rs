@@ -1,20 +1,97 @@
+use std::cell::RefCell;
+use std::io::ErrorKind;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::panic::{self, AssertUnwindSafe};
+use std::process;
+use std::rc::Rc;
 use std::sync::Arc;
+use std::time::Instant;
 
 use futures;
-use futures::Future;
+use futures::{Async, Future};
 
 use hyper;
-use hyper::server::{Request, Response, Service};
-use hyper::StatusCode;
-use hyper::header::ContentType;
+use hyper::server::{Http, Request, Response, Service};
+use hyper::{Body, StatusCode};
+use hyper::header::{
+    ContentLength,
+    ContentType,
+    Host,
+    Location,
+    StrictTransportSecurity,
+};
+use mime_guess::Mime;
 
+use access_log::{AccessLog, Entry};
+use health::HealthChecks;
+use metrics::Metrics;
+use reporting::{
+    self,
+    ErrorReport,
+    ErrorReporter,
+    LogReporter,
+    Panic,
+    REQUEST_ID_HEADER,
+};
 use routing::Router;
+use stream::BodyStream;
+use view::View;
+use error::{Error, Result};
+use range::{self, Representation};
+use shutdown::Drain;
+use websocket::{self, PendingUpgrade};
+use tokio_core::reactor::Handle;
+use tokio_io::{AsyncRead, AsyncWrite};
+
+/// The headers which frame a response's body, and are always worked out
+/// from the body itself rather than taken from the view.
+const FRAMING_HEADERS: [&str; 3] = [
+    "Content-Length",
+    "Content-Range",
+    "Transfer-Encoding",
+];
 
 /// The basic server service which is used to try to resolve paths
 /// and respond with the correct information.
 pub struct Server
 {
     router: Arc<Router>,
+
+    /// The WebSocket upgrade accepted on this connection, if any.
+    upgrade: RefCell<Option<PendingUpgrade>>,
+
+    /// The `Strict-Transport-Security` header added to every response, if
+    /// the connection is secure.
+    hsts: Option<StrictTransportSecurity>,
+
+    /// The address of the client on the other end of the connection, if
+    /// it's known.
+    remote: Option<SocketAddr>,
+
+    /// Where every request is logged, if anywhere.
+    access_log: Option<Arc<AccessLog>>,
+
+    /// Where the errors returned by handlers are reported.
+    reporter: Arc<ErrorReporter>,
+
+    /// If a panicking handler aborts the process, rather than being turned
+    /// into an error.
+    abort_on_panic: bool,
+
+    /// Where every request is counted and timed, if anywhere.
+    metrics: Option<Arc<Metrics>>,
+
+    /// The liveness and readiness endpoints, if they're served.
+    health: Option<Arc<HealthChecks>>,
+}
+
+/// A service which permanently redirects every request to the same path on
+/// the HTTPS server.
+pub(crate) struct Redirect
+{
+    /// The port the HTTPS server listens on.
+    port: u16,
 }
 
 impl Server
@@ -25,8 +102,314 @@ impl Server
     {
         Server {
             router,
+            upgrade: RefCell::new(None),
+            hsts: None,
+            remote: None,
+            access_log: None,
+            reporter: Arc::new(LogReporter),
+            abort_on_panic: false,
+            metrics: None,
+            health: None,
+        }
+    }
+
+    /// Adds the `Strict-Transport-Security` header to every response.
+    pub(crate) fn hsts(mut self, header: Option<StrictTransportSecurity>)
+        -> Self
+    {
+        self.hsts = header;
+        self
+    }
+
+    /// Sets the `address` of the client this service is serving.
+    pub(crate) fn remote(mut self, address: Option<SocketAddr>) -> Self
+    {
+        self.remote = address;
+        self
+    }
+
+    /// Logs every request to the `access_log`.
+    pub(crate) fn access_log(mut self, access_log: Option<Arc<AccessLog>>)
+        -> Self
+    {
+        self.access_log = access_log;
+        self
+    }
+
+    /// Reports the errors returned by handlers to the `reporter`.
+    pub(crate) fn error_reporter(mut self, reporter: Arc<ErrorReporter>)
+        -> Self
+    {
+        self.reporter = reporter;
+        self
+    }
+
+    /// Aborts the process when a handler panics, instead of responding with
+    /// an error.
+    pub(crate) fn abort_on_panic(mut self, abort: bool) -> Self
+    {
+        self.abort_on_panic = abort;
+        self
+    }
+
+    /// Records the requests this server handles in the `metrics`, which are
+    /// also served by it.
+    pub(crate) fn metrics(mut self, metrics: Option<Arc<Metrics>>) -> Self
+    {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Serves the liveness and readiness endpoints of the `health` checks.
+    pub(crate) fn health(mut self, health: Option<Arc<HealthChecks>>) -> Self
+    {
+        self.health = health;
+        self
+    }
+
+    /// Creates the response to the `req` with the given `id`, along with the
+    /// spec of the route it matched, if it matched one.
+    fn response(&self, req: Request, id: &str) -> (Option<&str>, Response)
+    {
+        let mut route = None;
+
+        let result = self.isolate(|| {
+            if websocket::is_upgrade(&req) {
+                if let Some((spec, response)) = self.upgrade(&req) {
+                    route = Some(spec);
+                    return Some(Ok(response));
+                }
+            }
+
+            let (spec, handler, route_map) = self.router.resolve(&req)?;
+            route = Some(spec);
+
+            let result = handler.handle_request(&req, route_map)
+                .and_then(|view| respond(&req, view));
+            Some(result)
+        });
+
+        let response = match result {
+
+            // No response => 404
+            None => text_response(
+                StatusCode::NotFound,
+                "404\nRequested file not found"
+            ),
+
+            // We found something, so use that as our body!
+            Some(Ok(response)) => response,
+
+            // Files which don't exist, or may not be read, are the client's
+            // mistake rather than the server's
+            Some(Err(Error::Io(ref error)))
+                if error.kind() == ErrorKind::NotFound =>
+            {
+                text_response(
+                    StatusCode::NotFound,
+                    "404\nRequested file not found"
+                )
+            },
+            Some(Err(Error::Io(ref error)))
+                if error.kind() == ErrorKind::PermissionDenied =>
+            {
+                text_response(StatusCode::Forbidden, "403\nForbidden")
+            },
+
+            // Report the error; the client is told which request it was
+            // so that they can be matched up
+            Some(Err(error)) => {
+                let path = req.uri().to_string();
+                let report = ErrorReport::new(&error, req.method(), &path, id);
+                self.reporter.report(&report);
+
+                text_response(
+                    StatusCode::InternalServerError,
+                    "Internal Server Error"
+                )
+            },
+        };
+
+        (route, response)
+    }
+
+    /// Runs the `route` function, turning a panic into an error so that it
+    /// doesn't take the connection down with it (unless the server should
+    /// abort instead).
+    fn isolate<F>(&self, route: F) -> Option<Result<Response>>
+        where F: FnOnce() -> Option<Result<Response>>
+    {
+        match panic::catch_unwind(AssertUnwindSafe(route)) {
+            Ok(result) => result,
+            Err(_) if self.abort_on_panic => process::abort(),
+            Err(payload) => {
+                Some(Err(Error::Other(Box::new(Panic::new(payload)))))
+            },
         }
     }
+
+    /// Tries to accept the WebSocket upgrade `request`, returning the
+    /// handshake response if a WebSocket handler is bound to its route,
+    /// along with the route's spec.
+    fn upgrade(&self, request: &Request) -> Option<(&str, Response)>
+    {
+        let (spec, handler, route_map) = self.router.upgrade(request)?;
+
+        let response = websocket::handshake(request);
+        if response.status() == StatusCode::SwitchingProtocols {
+            *self.upgrade.borrow_mut() = Some(PendingUpgrade {
+                handler,
+                route_map,
+            });
+        }
+
+        Some((spec, response))
+    }
+}
+
+/// Serves HTTP over the given `io` with the `service` until the connection is
+/// closed, or taken over by a WebSocket once it's been upgraded. Once the
+/// server starts to `drain`, the connection is closed after the current
+/// request.
+pub(crate) fn serve_connection<I: 'static>(
+    http: &Http,
+    io: I,
+    service: Server,
+    handle: Handle,
+    drain: Rc<Drain>
+) -> Box<Future<Item=(), Error=hyper::Error>>
+    where I: AsyncRead + AsyncWrite
+{
+    let mut connection = Some(http.serve_connection(io, service));
+    let mut closing = false;
+
+    let done = futures::future::poll_fn(move || {
+        if !closing && drain.is_draining() {
+            closing = true;
+            connection.as_mut().unwrap().disable_keep_alive();
+        }
+
+        match connection.as_mut().unwrap().poll_without_shutdown()? {
+            Async::NotReady => Ok(Async::NotReady),
+            Async::Ready(()) => {
+                Ok(Async::Ready(connection.take().unwrap().into_parts()))
+            },
+        }
+    });
+
+    Box::new(done.map(move |parts| {
+        let upgrade = parts.service.upgrade.into_inner();
+        if let Some(upgrade) = upgrade {
+            websocket::serve(parts.io, parts.read_buf, upgrade, &handle);
+        }
+    }))
+}
+
+/// Converts the `view` into the response to the `request`.
+///
+/// The view's headers replace the server's, except for those which frame
+/// the body, and, in responses to range requests, its `Content-Type`, which
+/// describes the parts sent rather than the whole.
+fn respond(request: &Request, view: View) -> Result<Response>
+{
+    let View { content, mime, file, stream, headers, .. } = view;
+
+    let mut response = represent(request, content, mime, file, stream)?;
+    let ranged = matches!(
+        response.status(),
+        StatusCode::PartialContent | StatusCode::RangeNotSatisfiable
+    );
+
+    response.headers_mut().extend(headers.iter().filter(|header| {
+        let name = header.name();
+        let framing = FRAMING_HEADERS.iter()
+            .any(|it| it.eq_ignore_ascii_case(name));
+        let typed = ranged && name.eq_ignore_ascii_case("Content-Type");
+        !framing && !typed
+    }));
+    Ok(response)
+}
+
+/// Creates the response with the body of a view, which is read from its
+/// `file` if it has one, so that it can be requested in parts.
+fn represent(
+    request: &Request,
+    content: String,
+    mime: Mime,
+    file: Option<PathBuf>,
+    stream: Option<BodyStream>
+) -> Result<Response>
+{
+    let path = match file {
+        None => {
+            let response = match stream {
+                None => {
+                    let length = content.len() as u64;
+                    full_response(content.into(), mime)
+                        .with_header(ContentLength(length))
+                },
+                Some(stream) => full_response(stream.into_body(), mime),
+            };
+            return Ok(response);
+        },
+
+        Some(path) => path,
+    };
+
+    // file-backed views may be requested in parts, so streams of files are
+    // left as files to read those parts from
+    let body = match stream {
+        None => Representation::Buffered(content.into_bytes()),
+        Some(stream) => match stream.into_file() {
+            Ok(file) => Representation::File(file),
+            Err(stream) => {
+                return Ok(full_response(stream.into_body(), mime));
+            },
+        },
+    };
+
+    range::respond(request, body, mime, &path)
+}
+
+/// Creates a response with the given `status`, and the `text` as its body.
+fn text_response(status: StatusCode, text: &'static str) -> Response
+{
+    Response::new()
+        .with_status(status)
+        .with_header(ContentLength(text.len() as u64))
+        .with_body(text)
+}
+
+/// Creates a response with the given `status`, and the JSON `body`.
+fn json_response(status: StatusCode, body: String) -> Response
+{
+    Response::new()
+        .with_status(status)
+        .with_header(ContentLength(body.len() as u64))
+        .with_header(ContentType::json())
+        .with_body(body)
+}
+
+/// Creates the response to a scrape of the `metrics`.
+fn scrape(metrics: &Metrics) -> Response
+{
+    let text = metrics.render();
+
+    Response::new()
+        .with_header(ContentLength(text.len() as u64))
+        .with_header(ContentType(
+            "text/plain; version=0.0.4".parse().unwrap()
+        ))
+        .with_body(text)
+}
+
+/// Creates a plain response with the given `body` and `mime` type.
+fn full_response(body: Body, mime: Mime) -> Response
+{
+    let mut response = Response::new();
+    response.set_body(body);
+    response.headers_mut().set(ContentType(mime));
+    response
 }
 
 impl Service for Server
@@ -38,37 +421,327 @@ impl Service for Server
 
     fn call(&self, req: Request) -> Self::Future
     {
-        let response = match self.router.handle(req) {
+        if let Some(ref health) = self.health {
+            if let Some((status, body)) = health.respond(&req) {
+                return Box::new(futures::future::ok(json_response(status, body)));
+            }
+        }
 
-            // No response => 404
+        if let Some(ref metrics) = self.metrics {
+            if metrics.is_scrape(&req) {
+                return Box::new(futures::future::ok(scrape(metrics)));
+            }
+        }
+
+        let started = Instant::now();
+        let id = reporting::request_id(&req);
+        let entry = self.access_log.as_ref()
+            .map(|_| Entry::new(&req, &id, self.remote));
+        let method = req.method().clone();
+        let in_flight = self.metrics.as_ref().map(|it| it.start());
+
+        let (route, mut response) = self.response(req, &id);
+        response.headers_mut().set_raw(REQUEST_ID_HEADER, id);
+
+        if let Some(ref hsts) = self.hsts {
+            response.headers_mut().set(hsts.clone());
+        }
+
+        let elapsed = started.elapsed();
+
+        if let (Some(log), Some(entry)) = (self.access_log.as_ref(), entry) {
+            log.record(&entry.finish(&response, elapsed));
+        }
+
+        if let (Some(metrics), Some(request)) = (&self.metrics, in_flight) {
+            metrics.finish(request, route, &method, response.status(), elapsed);
+        }
+
+        Box::new(futures::future::ok(response))
+    }
+}
+
+impl Redirect
+{
+    /// Creates a service which redirects to the HTTPS server on the `port`.
+    pub(crate) fn new(port: u16) -> Self
+    {
+        Redirect { port }
+    }
+
+    /// The HTTPS URL of the resource the `request` asked for.
+    fn location(&self, request: &Request) -> Option<String>
+    {
+        let host = request.headers().get::<Host>()?.hostname();
+
+        let mut location = match self.port {
+            443 => format!("https://{}", host),
+            port => format!("https://{}:{}", host, port),
+        };
+
+        location.push_str(request.path());
+        if let Some(query) = request.query() {
+            location.push('?');
+            location.push_str(query);
+        }
+
+        Some(location)
+    }
+}
+
+impl Service for Redirect
+{
+    type Request = Request;
+    type Response = Response;
+    type Error = hyper::Error;
+    type Future = futures::future::FutureResult<Response, hyper::Error>;
+
+    fn call(&self, req: Request) -> Self::Future
+    {
+        let mut response = Response::new();
+
+        match self.location(&req) {
+
+            // without a host there's nowhere to redirect to
             None => {
-                let mut response = Response::new();
-                response.set_status(StatusCode::NotFound);
-                response.set_body("404\nRequested file not found");
-                response
+                response.set_status(StatusCode::BadRequest);
+                response.set_body("400\nMissing Host header");
             },
 
-            // We found something, so use that as our body!
-            Some(result) => {
-                let mut response = Response::new();
-
-                match result {
-                    Err(error) => {
-                        response.set_body("Internal Server Error");
-                        response.set_status(StatusCode::InternalServerError);
-                        println!("{}", error);
-                    },
-
-                    Ok(view) => {
-                        response.set_body(view.content);
-                        response.headers_mut().set(ContentType(view.mime));
-                    }
-                }
+            Some(location) => {
+                response.set_status(StatusCode::MovedPermanently);
+                response.headers_mut().set(Location::new(location));
+            },
+        }
+
+        futures::future::ok(response)
+    }
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+    use futures::Stream;
+    use hyper::Method;
 
-                response
+    use request_handler::RequestHandler;
+    use resolution::RouteMap;
+    use routing::RouterBuilder;
+
+    struct Handler;
+    impl RequestHandler for Handler
+    {
+        fn handle(&self, _: RouteMap) -> Result<View>
+        {
+            Ok(View::raw("hi"))
+        }
+    }
+
+    /// Creates a GET request for the `uri` on the `host`.
+    fn get(uri: &str, host: Option<Host>) -> Request
+    {
+        let mut request = Request::new(Method::Get, uri.parse().unwrap());
+        if let Some(host) = host {
+            request.headers_mut().set(host);
+        }
+        request
+    }
+
+    /// Reads the `Location` header of the `response`.
+    fn location(response: &Response) -> String
+    {
+        response.headers().get::<Location>().unwrap().to_string()
+    }
+
+    /// Tests that the path and query are kept when redirecting.
+    #[test]
+    fn redirects_to_https()
+    {
+        let redirect = Redirect::new(443);
+
+        let host = Host::new("example.com", Some(80));
+        let response = redirect.call(get("/foo/bar?baz=1", Some(host)))
+            .wait().unwrap();
+        assert_eq!(StatusCode::MovedPermanently, response.status());
+        assert_eq!("https://example.com/foo/bar?baz=1", location(&response));
+
+        let redirect = Redirect::new(8443);
+        let host = Host::new("example.com", None);
+        let response = redirect.call(get("/", Some(host))).wait().unwrap();
+        assert_eq!("https://example.com:8443/", location(&response));
+
+        let response = redirect.call(get("/", None)).wait().unwrap();
+        assert_eq!(StatusCode::BadRequest, response.status());
+    }
+
+    /// Tests that the HSTS header is only added when it's configured, even
+    /// to error responses.
+    #[test]
+    fn hsts()
+    {
+        let mut router = RouterBuilder::new();
+        router.bind(Method::Get, "/", Handler);
+        let router: Arc<Router> = Arc::new(router.into());
+
+        let server = Server::new(router.clone());
+        let response = server.call(get("/", None)).wait().unwrap();
+        assert!(response.headers().get::<StrictTransportSecurity>().is_none());
+
+        let header = StrictTransportSecurity::including_subdomains(31536000);
+        let server = Server::new(router).hsts(Some(header.clone()));
+
+        for uri in &["/", "/missing"] {
+            let response = server.call(get(uri, None)).wait().unwrap();
+            assert_eq!(
+                Some(&header),
+                response.headers().get::<StrictTransportSecurity>()
+            );
+        }
+
+        let response = server.call(get("/", None)).wait().unwrap();
+        let body = response.body().concat2().wait().unwrap();
+        assert_eq!(b"hi", &body[..]);
+    }
+
+    /// Tests that handlers failing to find or read a file respond with 404
+    /// and 403, rather than an internal server error.
+    #[test]
+    fn io_errors()
+    {
+        use std::io::Error as IoError;
+
+        struct Failing(ErrorKind);
+        impl RequestHandler for Failing
+        {
+            fn handle(&self, _: RouteMap) -> Result<View>
+            {
+                Err(IoError::new(self.0, "failed").into())
             }
+        }
+
+        let mut router = RouterBuilder::new();
+        router.bind(Method::Get, "/missing", Failing(ErrorKind::NotFound));
+        router.bind(
+            Method::Get,
+            "/denied",
+            Failing(ErrorKind::PermissionDenied)
+        );
+        router.bind(Method::Get, "/broken", Failing(ErrorKind::Other));
+        let server = Server::new(Arc::new(router.into()));
+
+        let status = |uri| server.call(get(uri, None)).wait().unwrap().status();
+        assert_eq!(StatusCode::NotFound, status("/missing"));
+        assert_eq!(StatusCode::Forbidden, status("/denied"));
+        assert_eq!(StatusCode::InternalServerError, status("/broken"));
+    }
+
+    /// Tests that the headers of a view are added to its response, replacing
+    /// those the server would have set.
+    #[test]
+    fn view_headers()
+    {
+        use hyper::header::{CacheControl, CacheDirective};
+
+        struct Headed;
+        impl RequestHandler for Headed
+        {
+            fn handle(&self, _: RouteMap) -> Result<View>
+            {
+                Ok(View::raw("{}")
+                    .with_header(CacheControl(vec![CacheDirective::NoCache]))
+                    .with_header(ContentType::json()))
+            }
+        }
+
+        let mut router = RouterBuilder::new();
+        router.bind(Method::Get, "/", Headed);
+        let server = Server::new(Arc::new(router.into()));
+
+        let response = server.call(get("/", None)).wait().unwrap();
+        assert_eq!(
+            Some(&CacheControl(vec![CacheDirective::NoCache])),
+            response.headers().get::<CacheControl>()
+        );
+        assert_eq!(
+            Some(&ContentType::json()),
+            response.headers().get::<ContentType>()
+        );
+        assert_eq!(
+            Some(&ContentLength(2)),
+            response.headers().get::<ContentLength>()
+        );
+    }
+
+    /// Tests that a view's headers can't replace those which describe the
+    /// parts of its file sent in response to a range request.
+    #[test]
+    fn view_headers_with_ranges()
+    {
+        use hyper::header::{ContentRange, Range};
+
+        struct Ranged;
+        impl RequestHandler for Ranged
+        {
+            fn handle(&self, _: RouteMap) -> Result<View>
+            {
+                let mut view = View::file("src/server.rs")?
+                    .with_header(ContentType::json())
+                    .with_header(ContentLength(1));
+                view.headers.set_raw("Content-Range", "bytes 0-0/1");
+                Ok(view)
+            }
+        }
+
+        let mut router = RouterBuilder::new();
+        router.bind(Method::Get, "/", Ranged);
+        let server = Server::new(Arc::new(router.into()));
+        let length = include_str!("server.rs").len() as u64;
+
+        let request = |ranges: &str| {
+            let mut request = get("/", None);
+            request.headers_mut().set(Range::bytes_multi(
+                ranges.split(',')
+                    .map(|it| {
+                        let mut bounds = it.split('-')
+                            .map(|it| it.parse().unwrap());
+                        (bounds.next().unwrap(), bounds.next().unwrap())
+                    })
+                    .collect()
+            ));
+            server.call(request).wait().unwrap()
         };
 
-        Box::new(futures::future::ok(response))
+        let response = request("0-9");
+        assert_eq!(StatusCode::PartialContent, response.status());
+        assert_eq!(
+            Some(&ContentLength(10)),
+            response.headers().get::<ContentLength>()
+        );
+        assert_eq!(
+            "bytes 0-9/".to_string() + &length.to_string(),
+            response.headers().get::<ContentRange>().unwrap().to_string()
+        );
+        assert_eq!(
+            "x-rust",
+            response.headers().get::<ContentType>().unwrap().subtype()
+        );
+
+        let response = request("0-9,20-29");
+        assert_eq!(StatusCode::PartialContent, response.status());
+        let mime = response.headers().get::<ContentType>().unwrap();
+        assert_eq!("multipart/byteranges", mime.essence_str());
+        assert!(response.headers().get::<ContentRange>().is_none());
+
+        // the whole file still takes the view's type, but not its length
+        let response = server.call(get("/", None)).wait().unwrap();
+        assert_eq!(
+            Some(&ContentType::json()),
+            response.headers().get::<ContentType>()
+        );
+        assert_eq!(
+            Some(&ContentLength(length)),
+            response.headers().get::<ContentLength>()
+        );
     }
 }
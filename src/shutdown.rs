@@ -0,0 +1,425 @@
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+use futures::{Async, Future, Poll};
+use futures::future;
+use futures::sync::oneshot;
+use futures::task::{self, Task};
+
+use error::{Error, Result};
+
+/// A handle to a server running on its own thread, which is returned by
+/// [ServerBuilder::spawn].
+///
+/// Dropping the handle leaves the server running in the background.
+///
+/// ```rust,no_run
+/// use mwf::ServerBuilder;
+///
+/// let server = ServerBuilder::new()
+///     .addr("127.0.0.1:0".parse().unwrap())
+///     .spawn()
+///     .unwrap();
+///
+/// println!("Listening on {}", server.addr());
+///
+/// server.shutdown_on_signals().unwrap();
+/// server.wait().unwrap();
+/// ```
+pub struct ServerHandle
+{
+    addrs: Vec<SocketAddr>,
+    trigger: Arc<Mutex<Option<oneshot::Sender<()>>>>,
+    thread: JoinHandle<Result<()>>,
+}
+
+/// Keeps track of the connections a server has open, so that they can be
+/// given the chance to finish when the server shuts down.
+pub(crate) struct Drain
+{
+    /// If the server is shutting down.
+    draining: Cell<bool>,
+
+    /// The id given to the next connection.
+    next_id: Cell<usize>,
+
+    /// The task of each open connection, by id, once it's been polled.
+    connections: RefCell<HashMap<usize, Option<Task>>>,
+
+    /// The task waiting for every connection to close, if any.
+    waiting: RefCell<Option<Task>>,
+}
+
+/// A connection which is tracked by a [Drain] until it's finished.
+pub(crate) struct Tracked<F>
+{
+    id: usize,
+    drain: Rc<Drain>,
+    inner: F,
+}
+
+//
+// Implementation
+//
+
+impl ServerHandle
+{
+    pub(crate) fn new(
+        addrs: Vec<SocketAddr>,
+        trigger: oneshot::Sender<()>,
+        thread: JoinHandle<Result<()>>
+    ) -> Self
+    {
+        ServerHandle {
+            addrs,
+            trigger: Arc::new(Mutex::new(Some(trigger))),
+            thread,
+        }
+    }
+
+    /// The address of the first listener the server bound, which has its
+    /// actual port filled in if it was bound to port `0`.
+    ///
+    /// This panics if the server is only listening on Unix domain sockets.
+    pub fn addr(&self) -> SocketAddr
+    {
+        *self.addrs.first().expect("The server has no TCP listeners")
+    }
+
+    /// The addresses of all of the TCP listeners the server bound, in the
+    /// order they were given to the builder.
+    pub fn addrs(&self) -> &[SocketAddr]
+    {
+        &self.addrs
+    }
+
+    /// Tells the server to shut down. It stops accepting connections once
+    /// the builder's grace period is over (straight away by default), and
+    /// then waits for the requests in flight to finish, up to the builder's
+    /// shutdown timeout.
+    ///
+    /// This returns immediately; use [wait] to wait for the server to stop.
+    pub fn shutdown(&self)
+    {
+        trigger(&self.trigger);
+    }
+
+    /// Shuts the server down gracefully when the process receives `SIGINT`
+    /// or `SIGTERM`. If a second signal arrives while it's still shutting
+    /// down, the process exits immediately.
+    #[cfg(unix)]
+    pub fn shutdown_on_signals(&self) -> Result<()>
+    {
+        use signal_hook::consts::{SIGINT, SIGTERM};
+        use signal_hook::iterator::Signals;
+
+        let mut signals = Signals::new([SIGINT, SIGTERM])?;
+        let shutdown = self.trigger.clone();
+
+        thread::spawn(move || {
+            let mut signals = signals.forever();
+
+            if signals.next().is_some() {
+                trigger(&shutdown);
+            }
+
+            if let Some(signal) = signals.next() {
+                ::std::process::exit(128 + signal);
+            }
+        });
+
+        Ok(())
+    }
+
+    /// Blocks until the server has shut down, returning the error which
+    /// stopped it, if any.
+    pub fn wait(self) -> Result<()>
+    {
+        match self.thread.join() {
+            Ok(result) => result,
+            Err(_) => Err(other("The server thread panicked")),
+        }
+    }
+}
+
+/// Sends the shutdown signal, unless it's already been sent.
+fn trigger(trigger: &Mutex<Option<oneshot::Sender<()>>>)
+{
+    if let Some(trigger) = trigger.lock().unwrap().take() {
+        let _ = trigger.send(());
+    }
+}
+
+/// Creates a future which resolves once the `trigger` is used. If the
+/// trigger is dropped instead, it never resolves.
+pub(crate) fn signal(trigger: oneshot::Receiver<()>)
+    -> Box<Future<Item=(), Error=io::Error>>
+{
+    Box::new(trigger.then(|result| -> Box<Future<Item=(), Error=io::Error>> {
+        match result {
+            Ok(()) => Box::new(future::ok(())),
+            Err(_) => Box::new(future::empty()),
+        }
+    }))
+}
+
+/// Creates an error with the given `message`.
+pub(crate) fn other(message: &str) -> Error
+{
+    Error::Other(Box::new(io::Error::other(message)))
+}
+
+impl Drain
+{
+    pub fn new() -> Rc<Self>
+    {
+        Rc::new(Drain {
+            draining: Cell::new(false),
+            next_id: Cell::new(0),
+            connections: RefCell::new(HashMap::new()),
+            waiting: RefCell::new(None),
+        })
+    }
+
+    /// If the server is shutting down, in which case connections shouldn't
+    /// be kept alive after their current request.
+    pub fn is_draining(&self) -> bool
+    {
+        self.draining.get()
+    }
+
+    /// The number of connections which are still open.
+    pub fn open(&self) -> usize
+    {
+        self.connections.borrow().len()
+    }
+
+    /// Tracks the `connection` until it's finished or dropped.
+    pub fn track<F: Future>(self: &Rc<Self>, connection: F) -> Tracked<F>
+    {
+        let id = self.next_id.get();
+        self.next_id.set(id + 1);
+        self.connections.borrow_mut().insert(id, None);
+
+        Tracked {
+            id,
+            drain: self.clone(),
+            inner: connection,
+        }
+    }
+
+    /// Starts shutting down, waking up every connection so that the idle
+    /// ones notice and close.
+    pub fn start(&self)
+    {
+        self.draining.set(true);
+
+        for task in self.connections.borrow().values() {
+            if let Some(ref task) = *task {
+                task.notify();
+            }
+        }
+    }
+
+    /// Creates a future which resolves once every connection is closed.
+    pub fn finished(self: &Rc<Self>) -> Box<Future<Item=(), Error=()>>
+    {
+        let drain = self.clone();
+
+        Box::new(future::poll_fn(move || {
+            if drain.open() == 0 {
+                return Ok(Async::Ready(()));
+            }
+
+            *drain.waiting.borrow_mut() = Some(task::current());
+            Ok(Async::NotReady)
+        }))
+    }
+}
+
+impl<F: Future> Future for Tracked<F>
+{
+    type Item = F::Item;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error>
+    {
+        self.drain.connections.borrow_mut()
+            .insert(self.id, Some(task::current()));
+
+        self.inner.poll()
+    }
+}
+
+impl<F> Drop for Tracked<F>
+{
+    fn drop(&mut self)
+    {
+        self.drain.connections.borrow_mut().remove(&self.id);
+
+        if self.drain.open() == 0 {
+            if let Some(task) = self.drain.waiting.borrow_mut().take() {
+                task.notify();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+    use std::time::Duration;
+
+    /// Polls the `future` once, outside of any reactor.
+    fn poll<F: Future>(future: &mut F) -> Poll<F::Item, F::Error>
+    {
+        future::lazy(|| Ok::<_, ()>(future.poll())).wait().unwrap()
+    }
+
+    /// Creates a handle to a "server" which stops as soon as it's told to,
+    /// with the result from `stopped`.
+    fn handle<F: 'static>(stopped: F) -> ServerHandle
+        where F: FnOnce() -> Result<()> + Send
+    {
+        let (trigger, shutdown) = oneshot::channel();
+        let thread = thread::spawn(move || {
+            let _ = shutdown.wait();
+            stopped()
+        });
+
+        let addrs = vec![
+            "127.0.0.1:8080".parse().unwrap(),
+            "[::1]:8081".parse().unwrap(),
+        ];
+        ServerHandle::new(addrs, trigger, thread)
+    }
+
+    /// Tests that the handle reports the server's addresses, and that it can
+    /// be shut down more than once.
+    #[test]
+    fn handle_shutdown()
+    {
+        let server = handle(|| Ok(()));
+        let first: SocketAddr = "127.0.0.1:8080".parse().unwrap();
+        assert_eq!(first, server.addr());
+        assert_eq!(2, server.addrs().len());
+
+        server.shutdown();
+        server.shutdown();
+        assert!(server.wait().is_ok());
+    }
+
+    /// Tests that waiting on the handle reports why the server stopped.
+    #[test]
+    fn handle_errors()
+    {
+        let server = handle(|| Err(other("oh no")));
+        server.shutdown();
+        assert_eq!("oh no", server.wait().unwrap_err().to_string());
+
+        let server = handle(|| panic!("oh no"));
+        server.shutdown();
+        assert!(server.wait().is_err());
+    }
+
+    /// Tests that a signal shuts the server down gracefully.
+    #[cfg(unix)]
+    #[test]
+    fn shutdown_on_signals()
+    {
+        use signal_hook::consts::SIGTERM;
+        use signal_hook::low_level::raise;
+
+        let (stopped, finished) = ::std::sync::mpsc::channel();
+        let server = handle(move || {
+            let _ = stopped.send(());
+            Ok(())
+        });
+        server.shutdown_on_signals().unwrap();
+
+        raise(SIGTERM).unwrap();
+        assert!(finished.recv_timeout(Duration::from_secs(5)).is_ok());
+        assert!(server.wait().is_ok());
+    }
+
+    /// Tests that the shutdown signal only resolves once it's triggered.
+    #[test]
+    fn signals()
+    {
+        let (trigger, shutdown) = oneshot::channel();
+        let mut shutdown = signal(shutdown);
+        assert!(!poll(&mut shutdown).unwrap().is_ready());
+
+        trigger.send(()).unwrap();
+        assert!(poll(&mut shutdown).unwrap().is_ready());
+
+        let (trigger, shutdown) = oneshot::channel::<()>();
+        let mut shutdown = signal(shutdown);
+        drop(trigger);
+        assert!(!poll(&mut shutdown).unwrap().is_ready());
+    }
+
+    /// Tests that connections are counted while they're open, and that they
+    /// pass their inner future's result through.
+    #[test]
+    fn tracked()
+    {
+        let drain = Drain::new();
+
+        let mut first = drain.track(future::ok::<_, ()>(1));
+        let second = drain.track(future::empty::<(), ()>());
+        assert_eq!(2, drain.open());
+
+        assert_eq!(Async::Ready(1), poll(&mut first).unwrap());
+        drop(first);
+        assert_eq!(1, drain.open());
+
+        drop(second);
+        assert_eq!(0, drain.open());
+    }
+
+    /// Tests that draining wakes up open connections, and finishes once
+    /// they've all closed.
+    #[test]
+    fn drain()
+    {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use futures::executor::{self, Notify};
+
+        /// Counts how many times a task is woken up.
+        struct Woken(AtomicUsize);
+
+        impl Notify for Woken
+        {
+            fn notify(&self, _id: usize)
+            {
+                self.0.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+
+        let drain = Drain::new();
+        let mut finished = drain.finished();
+        assert!(poll(&mut finished).unwrap().is_ready());
+
+        let woken = Arc::new(Woken(AtomicUsize::new(0)));
+        let mut connection = executor::spawn(
+            drain.track(future::empty::<(), ()>())
+        );
+        assert!(!connection.poll_future_notify(&woken, 0).unwrap().is_ready());
+        assert!(!drain.is_draining());
+        assert!(!poll(&mut finished).unwrap().is_ready());
+
+        drain.start();
+        assert!(drain.is_draining());
+        assert_eq!(1, woken.0.load(Ordering::SeqCst));
+
+        drop(connection);
+        assert!(poll(&mut finished).unwrap().is_ready());
+    }
+}
@@ -0,0 +1,394 @@
+use std::fs::File;
+use std::io::{self, Read};
+use std::sync::OnceLock;
+use std::thread;
+
+use futures::{Future, Sink, Stream};
+use futures::future::Executor;
+use futures::stream;
+use futures_cpupool::{Builder, CpuPool};
+use hyper;
+use hyper::{Body, Chunk};
+use tokio_current_thread::TaskExecutor;
+
+use error::{Error, Result};
+
+/// How many bytes are read from a file or reader at a time.
+const CHUNK_SIZE: usize = 8 * 1024;
+
+/// How many threads read from the bodies whose sources may block, unless
+/// the server is given another number.
+const BLOCKING_THREADS: usize = 8;
+
+/// The threads bodies with blocking sources are read on, shared by all of
+/// them, along with how many there are.
+static BLOCKING_POOL: OnceLock<(usize, CpuPool)> = OnceLock::new();
+
+/// A response body which is sent to the client piece by piece as it's
+/// produced, instead of being buffered into a [View]'s content first.
+///
+/// Streamed bodies are sent with chunked transfer encoding. Futures streams
+/// are driven by the server's reactor, while files, readers and iterators,
+/// which may block, are read on a small pool of threads shared by every
+/// body in the process. Each of those holds a thread until it's been sent
+/// in full, so once every thread is taken by slow clients or endless
+/// sources, the other streamed bodies wait. There are 8 threads unless
+/// [ServerBuilder::blocking_threads](crate::ServerBuilder::blocking_threads)
+/// says otherwise; use [BodyStream::stream] for sources which never end.
+///
+/// ```rust
+/// use mwf::{View, BodyStream};
+///
+/// let rows = vec!["id,name\n", "1,foo\n", "2,bar\n"];
+/// let mut view = View::stream(BodyStream::chunks(rows));
+/// view.mime = "text/csv".parse().unwrap();
+/// ```
+pub struct BodyStream
+{
+    source: Source,
+}
+
+/// Where a [BodyStream] gets its data from.
+enum Source
+{
+    /// A file on disk, which is kept separate from other readers so that
+    /// it can be seeked when only parts of it are requested.
+    File(File),
+
+    /// Any other reader.
+    Reader(Box<Read + Send>),
+
+    /// A series of chunks which are already split up.
+    Chunks(Box<Iterator<Item=Result<Vec<u8>>> + Send>),
+
+    /// A futures stream, which never blocks.
+    Stream(Box<Stream<Item=Vec<u8>, Error=Error> + Send>),
+}
+
+/// An iterator over the chunks read from a reader.
+struct ReadChunks<R: Read>
+{
+    reader: R,
+    done: bool,
+}
+
+//
+// Implementation
+//
+
+impl BodyStream
+{
+    /// Creates a stream which sends everything read from the `reader`.
+    pub fn reader<R: 'static>(reader: R) -> Self
+        where R: Read + Send
+    {
+        BodyStream {
+            source: Source::Reader(Box::new(reader)),
+        }
+    }
+
+    /// Creates a stream which sends the contents of the `file`.
+    pub fn file(file: File) -> Self
+    {
+        BodyStream {
+            source: Source::File(file),
+        }
+    }
+
+    /// Creates a stream which sends each of the `chunks` as it's produced
+    /// by the iterator. The iterator is run on the blocking pool, holding
+    /// one of its threads until it ends.
+    pub fn chunks<I, T>(chunks: I) -> Self
+        where I: IntoIterator<Item=T>,
+              I::IntoIter: Send + 'static,
+              T: Into<Vec<u8>>
+    {
+        let chunks = chunks.into_iter().map(|it| Ok(it.into()));
+
+        BodyStream {
+            source: Source::Chunks(Box::new(chunks)),
+        }
+    }
+
+    /// Creates a body which sends each item of the futures `stream` as it
+    /// becomes ready. If the stream fails, the response is cut off.
+    pub fn stream<S: 'static>(stream: S) -> Self
+        where S: Stream + Send,
+              S::Item: Into<Vec<u8>>,
+              S::Error: Into<Error>
+    {
+        let chunks = stream.map(Into::into).map_err(Into::into);
+
+        BodyStream {
+            source: Source::Stream(Box::new(chunks)),
+        }
+    }
+
+    /// Takes the file out of this stream, if it's streaming a file.
+    pub(crate) fn into_file(self) -> ::std::result::Result<File, Self>
+    {
+        match self.source {
+            Source::File(file) => Ok(file),
+            source => Err(BodyStream { source }),
+        }
+    }
+
+    /// Converts this stream into a hyper body, which is fed from the
+    /// stream's source on the reactor, or on the blocking pool if the source
+    /// may block.
+    pub(crate) fn into_body(self) -> Body
+    {
+        let chunks: Box<Iterator<Item=Result<Vec<u8>>> + Send> =
+            match self.source {
+                Source::File(file) => Box::new(ReadChunks::new(file)),
+                Source::Reader(reader) => Box::new(ReadChunks::new(reader)),
+                Source::Chunks(chunks) => chunks,
+                Source::Stream(stream) => {
+                    let (body, feed) = feed(stream);
+                    spawn(feed);
+                    return body;
+                },
+            };
+
+        let (body, feed) = feed(stream::iter_result(chunks));
+        blocking_pool().spawn(feed).forget();
+        body
+    }
+}
+
+/// Creates a body, along with the future which feeds it the `chunks`. The
+/// body is cut off after the first chunk which fails, and the future stops
+/// early if the client hangs up.
+fn feed<S: 'static>(chunks: S) -> (Body, Box<Future<Item=(), Error=()> + Send>)
+    where S: Stream<Item=Vec<u8>, Error=Error> + Send
+{
+    let mut failed = false;
+    let chunks = chunks
+        .then(|chunk| Ok(chunk.map(Chunk::from).map_err(|it| {
+            hyper::Error::Io(io::Error::other(it.to_string()))
+        })))
+        .take_while(move |chunk| {
+            let send = !failed;
+            failed = chunk.is_err();
+            Ok(send)
+        });
+
+    let (sender, body) = Body::pair();
+    let feed = sender.send_all(chunks).map(|_| ()).map_err(|_| ());
+    (body, Box::new(feed))
+}
+
+/// Runs the `future` on the reactor the server's running on. Without one
+/// (as when a response is made outside of a server) it's run on its own
+/// thread instead.
+fn spawn(future: Box<Future<Item=(), Error=()> + Send>)
+{
+    if let Err(error) = TaskExecutor::current().execute(future) {
+        let future = error.into_future();
+        thread::spawn(move || future.wait());
+    }
+}
+
+/// Creates the pool of threads which bodies with blocking sources are read
+/// on, with the given number of `threads`. The pool is shared by the whole
+/// process, so this has no effect once it's been created.
+pub(crate) fn blocking_threads(threads: usize)
+{
+    let &(size, _) = BLOCKING_POOL.get_or_init(|| pool(threads));
+    if size != threads {
+        warn!(
+            "Streamed bodies are already read on {} threads, not {}",
+            size,
+            threads
+        );
+    }
+}
+
+/// The pool of threads which bodies with blocking sources are read on.
+fn blocking_pool() -> &'static CpuPool
+{
+    &BLOCKING_POOL.get_or_init(|| pool(BLOCKING_THREADS)).1
+}
+
+/// Creates a pool of at least one thread.
+fn pool(threads: usize) -> (usize, CpuPool)
+{
+    let threads = threads.max(1);
+    let pool = Builder::new()
+        .pool_size(threads)
+        .name_prefix("mwf-body-")
+        .create();
+
+    (threads, pool)
+}
+
+impl<R: Read> ReadChunks<R>
+{
+    fn new(reader: R) -> Self
+    {
+        ReadChunks {
+            reader,
+            done: false,
+        }
+    }
+}
+
+impl<R: Read> Iterator for ReadChunks<R>
+{
+    type Item = Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item>
+    {
+        if self.done {
+            return None;
+        }
+
+        let mut buffer = vec![0; CHUNK_SIZE];
+        loop {
+            match self.reader.read(&mut buffer) {
+                Ok(0) => {
+                    self.done = true;
+                    return None;
+                },
+
+                Ok(count) => {
+                    buffer.truncate(count);
+                    return Some(Ok(buffer));
+                },
+
+                Err(ref error) if error.kind() == io::ErrorKind::Interrupted => {
+                    continue;
+                },
+
+                Err(error) => {
+                    self.done = true;
+                    return Some(Err(error.into()));
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+
+    /// Collects the entire `body` into a string.
+    fn collect(body: Body) -> String
+    {
+        let chunks = body.concat2().wait().expect("The body failed");
+        String::from_utf8(chunks.to_vec()).unwrap()
+    }
+
+    /// Tests that a reader is sent in chunks of at most [CHUNK_SIZE] bytes.
+    #[test]
+    fn reader_chunks()
+    {
+        let data = vec![b'a'; CHUNK_SIZE + 10];
+        let chunks: Vec<usize> = ReadChunks::new(&data[..])
+            .map(|it| it.unwrap().len())
+            .collect();
+
+        assert_eq!(vec![CHUNK_SIZE, 10], chunks);
+    }
+
+    /// Tests that readers, iterators and futures streams are all sent in
+    /// full.
+    #[test]
+    fn bodies()
+    {
+        let body = BodyStream::reader(&b"foobar"[..]).into_body();
+        assert_eq!("foobar", collect(body));
+
+        let body = BodyStream::chunks(vec!["foo", "bar"]).into_body();
+        assert_eq!("foobar", collect(body));
+
+        let chunks: Vec<::std::result::Result<&str, io::Error>> =
+            vec![Ok("foo"), Ok("bar")];
+        let body = BodyStream::stream(stream::iter_result(chunks)).into_body();
+        assert_eq!("foobar", collect(body));
+
+        let file = File::open("src/stream.rs").unwrap();
+        let body = BodyStream::file(file).into_body();
+        assert_eq!(include_str!("stream.rs"), collect(body));
+    }
+
+    /// Tests that futures streams are fed from the reactor they're sent on,
+    /// rather than from a thread of their own.
+    #[test]
+    fn driven_by_reactor()
+    {
+        use std::sync::{Arc, Mutex};
+        use futures::future;
+        use tokio_core::reactor::Core;
+
+        let threads = Arc::new(Mutex::new(Vec::new()));
+        let seen = threads.clone();
+        let chunks = stream::iter_ok::<_, io::Error>(vec!["foo", "bar"])
+            .map(move |it| {
+                seen.lock().unwrap().push(thread::current().id());
+                it
+            });
+
+        let mut core = Core::new().unwrap();
+        let body = core.run(future::lazy(|| {
+            BodyStream::stream(chunks).into_body().concat2()
+        })).unwrap();
+
+        assert_eq!(b"foobar", &body[..]);
+        let threads = threads.lock().unwrap();
+        assert_eq!(2, threads.len());
+        assert!(threads.iter().all(|it| *it == thread::current().id()));
+    }
+
+    /// Tests that a source which blocks only holds up its own body, and not
+    /// the files streamed while it's blocked.
+    #[test]
+    fn slow_source()
+    {
+        use std::sync::mpsc;
+        use std::time::Duration;
+
+        /// Blocks until it's told to end.
+        struct Slow(mpsc::Receiver<()>);
+
+        impl Read for Slow
+        {
+            fn read(&mut self, _buffer: &mut [u8]) -> io::Result<usize>
+            {
+                let _ = self.0.recv();
+                Ok(0)
+            }
+        }
+
+        let (release, blocked) = mpsc::channel();
+        let slow = BodyStream::reader(Slow(blocked)).into_body();
+
+        let (sent, received) = mpsc::channel();
+        thread::spawn(move || {
+            let file = File::open("src/stream.rs").unwrap();
+            let _ = sent.send(collect(BodyStream::file(file).into_body()));
+        });
+
+        let file = received.recv_timeout(Duration::from_secs(5))
+            .expect("The file waited for the slow source");
+        assert_eq!(include_str!("stream.rs"), file);
+
+        release.send(()).unwrap();
+        assert_eq!("", collect(slow));
+    }
+
+    /// Tests that a failing stream ends the body with an error.
+    #[test]
+    fn failing_stream()
+    {
+        let chunks: Vec<::std::result::Result<&str, io::Error>> = vec![
+            Ok("foo"),
+            Err(io::Error::other("oh no")),
+            Ok("bar"),
+        ];
+        let body = BodyStream::stream(stream::iter_result(chunks)).into_body();
+        assert!(body.concat2().wait().is_err());
+    }
+}
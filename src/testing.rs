@@ -0,0 +1,489 @@
+//! Helpers for testing a server's routes without opening any sockets.
+//!
+//! A [TestClient] sends requests straight to the same service a running
+//! server uses, so routing, handlers, range requests and error responses
+//! are all exercised together.
+//!
+//! ```rust
+//! use mwf::{ServerBuilder, RequestHandler, RouteMap, View, Result};
+//! use mwf::hyper::StatusCode;
+//!
+//! struct Greeter;
+//!
+//! impl RequestHandler for Greeter
+//! {
+//!     fn handle(&self, route_map: RouteMap) -> Result<View>
+//!     {
+//!         Ok(View::raw(format!("Hello, {}!", route_map.get(":name").unwrap())))
+//!     }
+//! }
+//!
+//! let client = ServerBuilder::new()
+//!     .bind("/hello/:name", Greeter)
+//!     .test_client();
+//!
+//! client.get("/hello/world")
+//!     .send()
+//!     .assert_status(StatusCode::Ok)
+//!     .assert_body("Hello, world!");
+//!
+//! client.get("/goodbye")
+//!     .send()
+//!     .assert_status(StatusCode::NotFound);
+//! ```
+
+use std::str;
+use std::sync::Arc;
+#[cfg(test)]
+use std::path::{Path, PathBuf};
+
+use futures::{Future, Stream};
+use hyper::{Headers, Method, Request, StatusCode};
+use hyper::header::ContentLength;
+use hyper::server::Service;
+
+use routing::Router;
+use server::Server;
+
+/// Sends requests to a server in-process.
+///
+/// Responses are read in full before they're returned, so requests to
+/// handlers which stream forever (such as an [EventSource]) never return.
+pub struct TestClient
+{
+    server: Server,
+}
+
+/// A request which is being built up to be sent by a [TestClient].
+pub struct TestRequest<'a>
+{
+    client: &'a TestClient,
+    method: Method,
+    uri: String,
+    headers: Headers,
+    body: Option<Vec<u8>>,
+}
+
+/// The response to a [TestRequest], with its body read in full.
+#[derive(Debug)]
+pub struct TestResponse
+{
+    status: StatusCode,
+    headers: Headers,
+    body: Vec<u8>,
+}
+
+/// A directory in the system's temporary directory for the files of one of
+/// the crate's own tests, which is deleted along with them when dropped.
+#[cfg(test)]
+pub(crate) struct TempDir
+{
+    path: PathBuf,
+}
+
+//
+// Implementation
+//
+
+impl TestClient
+{
+    /// Creates a client which sends requests to the given `router`.
+    pub fn new(router: Router) -> Self
+    {
+        TestClient::from_server(Server::new(Arc::new(router)))
+    }
+
+    /// Creates a client which sends requests to the given `server`.
+    pub(crate) fn from_server(server: Server) -> Self
+    {
+        TestClient {
+            server,
+        }
+    }
+
+    /// Starts building a GET request for the `uri`.
+    pub fn get<T: Into<String>>(&self, uri: T) -> TestRequest<'_>
+    {
+        self.request(Method::Get, uri)
+    }
+
+    /// Starts building a POST request for the `uri`.
+    pub fn post<T: Into<String>>(&self, uri: T) -> TestRequest<'_>
+    {
+        self.request(Method::Post, uri)
+    }
+
+    /// Starts building a request with any `method` for the `uri`, which is
+    /// the path and query string, such as `/search?q=foo`.
+    pub fn request<T: Into<String>>(&self, method: Method, uri: T)
+        -> TestRequest<'_>
+    {
+        TestRequest {
+            client: self,
+            method,
+            uri: uri.into(),
+            headers: Headers::new(),
+            body: None,
+        }
+    }
+}
+
+impl<'a> TestRequest<'a>
+{
+    /// Adds a header to the request. This may be called several times with
+    /// the same `name` to send the header more than once.
+    pub fn header<N, V>(mut self, name: N, value: V) -> Self
+        where N: Into<String>,
+              V: Into<Vec<u8>>
+    {
+        self.headers.append_raw(name.into(), value.into());
+        self
+    }
+
+    /// Sets the body of the request, along with its `Content-Length`.
+    /// Handlers can see it through
+    /// [handle_request](crate::RequestHandler::handle_request).
+    pub fn body<B: Into<Vec<u8>>>(mut self, body: B) -> Self
+    {
+        self.body = Some(body.into());
+        self
+    }
+
+    /// Sends the request and waits for the whole response.
+    ///
+    /// This panics if the uri is invalid, or if the response body fails.
+    pub fn send(self) -> TestResponse
+    {
+        let client = self.client;
+        let response = client.server.call(self.into_request()).wait()
+            .expect("The server failed to respond");
+
+        let status = response.status();
+        let headers = response.headers().clone();
+        let body = response.body().concat2().wait()
+            .expect("The response body failed");
+
+        TestResponse {
+            status,
+            headers,
+            body: body.to_vec(),
+        }
+    }
+}
+
+impl<'a> TestRequest<'a>
+{
+    /// Builds the request which is sent to the server.
+    fn into_request(self) -> Request
+    {
+        let uri = self.uri.parse()
+            .unwrap_or_else(|_| panic!("Invalid request uri {:?}", self.uri));
+
+        let mut request = Request::new(self.method, uri);
+        *request.headers_mut() = self.headers;
+
+        if let Some(body) = self.body {
+            request.headers_mut().set(ContentLength(body.len() as u64));
+            request.set_body(body);
+        }
+
+        request
+    }
+}
+
+impl TestResponse
+{
+    /// The status code of the response.
+    pub fn status(&self) -> StatusCode
+    {
+        self.status
+    }
+
+    /// All of the response's headers.
+    pub fn headers(&self) -> &Headers
+    {
+        &self.headers
+    }
+
+    /// The value of the header with the given `name`, if it was sent and is
+    /// valid UTF-8. If it was sent more than once, this is the first value.
+    pub fn header(&self, name: &str) -> Option<&str>
+    {
+        self.headers.get_raw(name)
+            .and_then(|it| it.iter().next())
+            .and_then(|it| str::from_utf8(it).ok())
+    }
+
+    /// The raw body of the response.
+    pub fn body(&self) -> &[u8]
+    {
+        &self.body
+    }
+
+    /// The body of the response as text.
+    ///
+    /// This panics if the body isn't valid UTF-8.
+    pub fn text(&self) -> &str
+    {
+        str::from_utf8(&self.body).expect("The response body isn't UTF-8")
+    }
+
+    /// Asserts that the response has the given `status`.
+    pub fn assert_status(&self, status: StatusCode) -> &Self
+    {
+        assert!(
+            self.status == status,
+            "Expected status {} but got {}, with the body:\n{}",
+            status,
+            self.status,
+            String::from_utf8_lossy(&self.body)
+        );
+        self
+    }
+
+    /// Asserts that the header with the given `name` has the `value`.
+    pub fn assert_header(&self, name: &str, value: &str) -> &Self
+    {
+        assert_eq!(
+            Some(value),
+            self.header(name),
+            "Unexpected value for the {} header",
+            name
+        );
+        self
+    }
+
+    /// Asserts that the response doesn't have a header with the `name`.
+    pub fn assert_no_header(&self, name: &str) -> &Self
+    {
+        assert!(
+            self.headers.get_raw(name).is_none(),
+            "Expected no {} header, but got {:?}",
+            name,
+            self.header(name)
+        );
+        self
+    }
+
+    /// Asserts that the body is exactly the given `text`.
+    pub fn assert_body(&self, text: &str) -> &Self
+    {
+        assert_eq!(text, String::from_utf8_lossy(&self.body));
+        self
+    }
+
+    /// Asserts that the body contains the given `text`.
+    pub fn assert_body_contains(&self, text: &str) -> &Self
+    {
+        let body = String::from_utf8_lossy(&self.body);
+        assert!(
+            body.contains(text),
+            "Expected the body to contain {:?}, but it was:\n{}",
+            text,
+            body
+        );
+        self
+    }
+}
+
+#[cfg(test)]
+impl TempDir
+{
+    /// Creates an empty directory for the test `name`, which is unique to
+    /// the test and the process running it.
+    pub fn new(name: &str) -> Self
+    {
+        let path = ::std::env::temp_dir()
+            .join(format!("mwf-{}-{}", name, ::std::process::id()));
+        let _ = ::std::fs::remove_dir_all(&path);
+        ::std::fs::create_dir_all(&path).unwrap();
+
+        TempDir {
+            path,
+        }
+    }
+
+    /// The path of the directory.
+    pub fn path(&self) -> &Path
+    {
+        &self.path
+    }
+
+    /// The path of the file at `path` inside of the directory.
+    pub fn join<P: AsRef<Path>>(&self, path: P) -> PathBuf
+    {
+        self.path.join(path)
+    }
+}
+
+#[cfg(test)]
+impl Drop for TempDir
+{
+    fn drop(&mut self)
+    {
+        let _ = ::std::fs::remove_dir_all(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+    use std::io;
+
+    use error::{Error, Result};
+    use request_handler::RequestHandler;
+    use resolution::RouteMap;
+    use routing::RouterBuilder;
+    use view::View;
+
+    /// Echoes back the method and a header of the request.
+    struct Echo;
+
+    impl RequestHandler for Echo
+    {
+        fn handle(&self, _route_map: RouteMap) -> Result<View>
+        {
+            unreachable!()
+        }
+
+        fn handle_request(&self, request: &Request, route_map: RouteMap)
+            -> Result<View>
+        {
+            let name = request.headers().get_raw("X-Name")
+                .and_then(|it| it.one())
+                .map(|it| String::from_utf8_lossy(it).into_owned())
+                .unwrap_or_default();
+
+            Ok(View::raw(format!(
+                "{} {} {}",
+                request.method(),
+                route_map.get(":id").unwrap(),
+                name
+            )))
+        }
+    }
+
+    /// Echoes back the length of the request's body.
+    struct Length;
+
+    impl RequestHandler for Length
+    {
+        fn handle(&self, _route_map: RouteMap) -> Result<View>
+        {
+            unreachable!()
+        }
+
+        fn handle_request(&self, request: &Request, _route_map: RouteMap)
+            -> Result<View>
+        {
+            let length = request.headers().get::<ContentLength>()
+                .map(|it| it.0.to_string())
+                .unwrap_or_default();
+
+            Ok(View::raw(format!(
+                "{} {}",
+                length,
+                request.body_ref().is_some()
+            )))
+        }
+    }
+
+    /// Always fails.
+    struct Broken;
+
+    impl RequestHandler for Broken
+    {
+        fn handle(&self, _route_map: RouteMap) -> Result<View>
+        {
+            Err(Error::Io(io::Error::other("oh no")))
+        }
+    }
+
+    fn client() -> TestClient
+    {
+        let mut router = RouterBuilder::new();
+        router.bind(Method::Get, "/echo/:id", Echo);
+        router.bind(Method::Post, "/echo/:id", Echo);
+        router.bind(Method::Post, "/length", Length);
+        router.bind(Method::Get, "/broken", Broken);
+        TestClient::new(router.into())
+    }
+
+    /// Tests that requests are routed to their handlers along with their
+    /// headers.
+    #[test]
+    fn routes_requests()
+    {
+        let client = client();
+
+        client.get("/echo/7")
+            .header("X-Name", "foo")
+            .send()
+            .assert_status(StatusCode::Ok)
+            .assert_header("Content-Type", "text/plain")
+            .assert_body("GET 7 foo");
+
+        let response = client.post("/echo/8?query=1").send();
+        assert_eq!(StatusCode::Ok, response.status());
+        assert_eq!("POST 8 ", response.text());
+    }
+
+    /// Tests that request bodies are sent with their length.
+    #[test]
+    fn request_bodies()
+    {
+        let client = client();
+
+        client.post("/length")
+            .body("name=mwf")
+            .send()
+            .assert_status(StatusCode::Ok)
+            .assert_body("8 true");
+
+        client.post("/length")
+            .send()
+            .assert_body(" false");
+
+        let request = client.post("/length")
+            .body(vec![1, 2, 3])
+            .into_request();
+        assert_eq!(
+            Some(&ContentLength(3)),
+            request.headers().get::<ContentLength>()
+        );
+
+        let body = request.body().concat2().wait().unwrap();
+        assert_eq!(&[1, 2, 3][..], &body[..]);
+    }
+
+    /// Tests that missing routes and failing handlers are mapped to the
+    /// right responses.
+    #[test]
+    fn error_responses()
+    {
+        let client = client();
+
+        client.get("/missing")
+            .send()
+            .assert_status(StatusCode::NotFound)
+            .assert_no_header("Content-Type")
+            .assert_body_contains("404");
+
+        client.get("/broken")
+            .send()
+            .assert_status(StatusCode::InternalServerError);
+
+        client.request(Method::Delete, "/echo/1")
+            .send()
+            .assert_status(StatusCode::NotFound);
+    }
+
+    /// Tests that a failing assertion panics.
+    #[test]
+    #[should_panic(expected = "Expected status 200 OK but got 404 Not Found")]
+    fn failed_assertion()
+    {
+        client().get("/missing").send().assert_status(StatusCode::Ok);
+    }
+}
@@ -0,0 +1,454 @@
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+
+use futures::{Async, Future, Poll};
+use openssl::error::ErrorStack;
+use openssl::ssl::{
+    HandshakeError,
+    MidHandshakeSslStream,
+    NameType,
+    ShutdownResult,
+    SniError,
+    SslAcceptor,
+    SslAcceptorBuilder,
+    SslFiletype,
+    SslMethod,
+    SslStream,
+};
+use tokio_io::{AsyncRead, AsyncWrite};
+
+use error::{Error, Result};
+
+/// The certificates used to serve HTTPS.
+///
+/// Every certificate and private key is read from a PEM file. The certificate
+/// file may contain the whole chain, starting with the server's certificate.
+///
+/// Clients which ask for a host (using SNI) that one of the certificates was
+/// added for with [host] are given that certificate, and all other clients
+/// are given the default one.
+///
+/// ```rust,no_run
+/// use mwf::{ServerBuilder, Protocol, TlsConfig};
+///
+/// let tls = TlsConfig::new("certs/default.pem", "certs/default.key")
+///     .host("example.com", "certs/example.pem", "certs/example.key");
+///
+/// ServerBuilder::new()
+///     .proto(Protocol::Https(tls))
+///     .start();
+/// ```
+#[derive(Clone, Debug)]
+pub struct TlsConfig
+{
+    default: Certificate,
+    hosts: Vec<(String, Certificate)>,
+}
+
+/// The paths to a certificate chain and its private key.
+#[derive(Clone, Debug)]
+struct Certificate
+{
+    chain: PathBuf,
+    key: PathBuf,
+}
+
+/// A connection which is encrypted with TLS.
+pub(crate) struct TlsStream<S>
+{
+    stream: SslStream<S>,
+}
+
+/// A future which completes once the server side of a TLS handshake is done.
+pub(crate) struct TlsAccept<S>
+{
+    state: Option<Handshake<S>>,
+}
+
+/// How far along a TLS handshake is.
+enum Handshake<S>
+{
+    Start(SslAcceptor, S),
+    Waiting(MidHandshakeSslStream<S>),
+}
+
+//
+// Implementation
+//
+
+impl TlsConfig
+{
+    /// Creates a configuration which gives every client the certificate
+    /// `chain` in the given PEM file, with its private `key`.
+    pub fn new<C, K>(chain: C, key: K) -> Self
+        where C: Into<PathBuf>,
+              K: Into<PathBuf>
+    {
+        TlsConfig {
+            default: Certificate::new(chain, key),
+            hosts: Vec::new(),
+        }
+    }
+
+    /// Adds a certificate `chain` and private `key` which are used for
+    /// clients asking for the given `hostname`.
+    pub fn host<H, C, K>(mut self, hostname: H, chain: C, key: K) -> Self
+        where H: Into<String>,
+              C: Into<PathBuf>,
+              K: Into<PathBuf>
+    {
+        let hostname = hostname.into().to_lowercase();
+        self.hosts.push((hostname, Certificate::new(chain, key)));
+        self
+    }
+
+    /// Loads the certificates and creates the acceptor which performs the
+    /// server side of TLS handshakes with them.
+    pub(crate) fn acceptor(&self) -> Result<SslAcceptor>
+    {
+        let mut contexts = HashMap::new();
+        for &(ref hostname, ref certificate) in &self.hosts {
+            let context = certificate.builder()?.build().into_context();
+            contexts.insert(hostname.clone(), context);
+        }
+
+        let mut builder = self.default.builder()?;
+        builder.set_servername_callback(move |ssl, _alert| {
+            let context = ssl.servername(NameType::HOST_NAME)
+                .and_then(|it| contexts.get(&it.to_lowercase()));
+
+            match context {
+                None => Ok(()),
+                Some(context) => ssl.set_ssl_context(context)
+                    .map_err(|_| SniError::ALERT_FATAL),
+            }
+        });
+
+        Ok(builder.build())
+    }
+}
+
+impl Certificate
+{
+    fn new<C, K>(chain: C, key: K) -> Self
+        where C: Into<PathBuf>,
+              K: Into<PathBuf>
+    {
+        Certificate {
+            chain: chain.into(),
+            key: key.into(),
+        }
+    }
+
+    /// Creates an acceptor builder which uses this certificate, with
+    /// Mozilla's recommended settings for most servers.
+    fn builder(&self) -> Result<SslAcceptorBuilder>
+    {
+        let setup = || -> ::std::result::Result<_, ErrorStack> {
+            let mut builder =
+                SslAcceptor::mozilla_intermediate_v5(SslMethod::tls())?;
+            builder.set_certificate_chain_file(&self.chain)?;
+            builder.set_private_key_file(&self.key, SslFiletype::PEM)?;
+            builder.check_private_key()?;
+            Ok(builder)
+        };
+
+        setup().map_err(|error| {
+            let message = format!(
+                "couldn't load the certificate {} with the key {}: {}",
+                self.chain.display(),
+                self.key.display(),
+                error
+            );
+            Error::Other(Box::new(io::Error::other(message)))
+        })
+    }
+}
+
+/// Starts the server side of a TLS handshake over the `stream`.
+pub(crate) fn accept<S>(acceptor: &SslAcceptor, stream: S) -> TlsAccept<S>
+    where S: Read + Write
+{
+    TlsAccept {
+        state: Some(Handshake::Start(acceptor.clone(), stream)),
+    }
+}
+
+impl<S: Read + Write> Future for TlsAccept<S>
+{
+    type Item = TlsStream<S>;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error>
+    {
+        let result = match self.state.take().expect("polled after completion") {
+            Handshake::Start(acceptor, stream) => acceptor.accept(stream),
+            Handshake::Waiting(stream) => stream.handshake(),
+        };
+
+        match result {
+            Ok(stream) => Ok(Async::Ready(TlsStream { stream })),
+
+            // the underlying stream will wake us up once it can go on
+            Err(HandshakeError::WouldBlock(stream)) => {
+                self.state = Some(Handshake::Waiting(stream));
+                Ok(Async::NotReady)
+            },
+
+            Err(HandshakeError::SetupFailure(error)) => {
+                Err(io::Error::other(error))
+            },
+
+            Err(HandshakeError::Failure(stream)) => {
+                let message = stream.error().to_string();
+                Err(io::Error::other(message))
+            },
+        }
+    }
+}
+
+impl<S: Read + Write> Read for TlsStream<S>
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize>
+    {
+        self.stream.read(buf)
+    }
+}
+
+impl<S: Read + Write> Write for TlsStream<S>
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize>
+    {
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()>
+    {
+        self.stream.flush()
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite> AsyncRead for TlsStream<S> {}
+
+impl<S: AsyncRead + AsyncWrite> AsyncWrite for TlsStream<S>
+{
+    fn shutdown(&mut self) -> Poll<(), io::Error>
+    {
+        match self.stream.shutdown() {
+            Ok(ShutdownResult::Sent) | Ok(ShutdownResult::Received) => {},
+
+            Err(error) => match error.into_io_error() {
+                Ok(ref error) if error.kind() == io::ErrorKind::WouldBlock => {
+                    return Ok(Async::NotReady);
+                },
+
+                // the client may well have hung up already, which is fine
+                // since we're done with the connection anyway
+                _ => {},
+            },
+        }
+
+        self.stream.get_mut().shutdown()
+    }
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+    use std::fs;
+    use std::net::{SocketAddr, TcpStream};
+
+    use openssl::asn1::Asn1Time;
+    use openssl::bn::BigNum;
+    use openssl::hash::MessageDigest;
+    use openssl::nid::Nid;
+    use openssl::pkey::PKey;
+    use openssl::rsa::Rsa;
+    use openssl::ssl::{SslConnector, SslVerifyMode};
+    use openssl::x509::{X509, X509NameBuilder};
+    use openssl::x509::extension::SubjectAlternativeName;
+
+    use builder::{Protocol, ServerBuilder};
+    use shutdown::ServerHandle;
+    use request_handler::RequestHandler;
+    use resolution::RouteMap;
+    use testing::TempDir;
+    use view::View;
+
+    struct Hello;
+
+    impl RequestHandler for Hello
+    {
+        fn handle(&self, _route_map: RouteMap) -> Result<View>
+        {
+            Ok(View::raw("Hello, world!"))
+        }
+    }
+
+    /// Generates a self-signed certificate for the `hostname` in the `dir`,
+    /// returning the paths to its PEM and private key files.
+    fn self_signed(dir: &TempDir, hostname: &str) -> (PathBuf, PathBuf)
+    {
+        let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_nid(Nid::COMMONNAME, hostname).unwrap();
+        let name = name.build();
+
+        let mut builder = X509::builder().unwrap();
+        builder.set_version(2).unwrap();
+        let serial = BigNum::from_u32(1).unwrap().to_asn1_integer().unwrap();
+        builder.set_serial_number(&serial).unwrap();
+        builder.set_subject_name(&name).unwrap();
+        builder.set_issuer_name(&name).unwrap();
+        builder.set_pubkey(&key).unwrap();
+        builder.set_not_before(&Asn1Time::days_from_now(0).unwrap()).unwrap();
+        builder.set_not_after(&Asn1Time::days_from_now(1).unwrap()).unwrap();
+        let names = SubjectAlternativeName::new()
+            .dns(hostname)
+            .build(&builder.x509v3_context(None, None))
+            .unwrap();
+        builder.append_extension(names).unwrap();
+        builder.sign(&key, MessageDigest::sha256()).unwrap();
+        let certificate = builder.build();
+
+        let chain = dir.join(format!("{}.pem", hostname));
+        let private = dir.join(format!("{}.key", hostname));
+        fs::write(&chain, certificate.to_pem().unwrap()).unwrap();
+        fs::write(&private, key.private_key_to_pem_pkcs8().unwrap()).unwrap();
+
+        (chain, private)
+    }
+
+    /// Starts an HTTPS server with the given `config` on a free port.
+    fn start(config: TlsConfig) -> ServerHandle
+    {
+        ServerBuilder::new()
+            .bind("/", Hello)
+            .addr("127.0.0.1:0".parse().unwrap())
+            .proto(Protocol::Https(config))
+            .spawn()
+            .unwrap()
+    }
+
+    /// Requests `/` from the server at `addr` over TLS, asking for the
+    /// `hostname`. Returns the common name of the certificate the server
+    /// presented along with the raw response.
+    fn get(addr: SocketAddr, hostname: &str) -> (String, String)
+    {
+        let mut connector = SslConnector::builder(SslMethod::tls()).unwrap();
+        connector.set_verify(SslVerifyMode::NONE);
+        let connector = connector.build();
+
+        let stream = TcpStream::connect(addr).unwrap();
+        let mut stream = connector.connect(hostname, stream).unwrap();
+
+        let certificate = stream.ssl().peer_certificate().unwrap();
+        let name = certificate.subject_name()
+            .entries_by_nid(Nid::COMMONNAME)
+            .next().unwrap()
+            .data().as_slice()
+            .to_vec();
+        let name = String::from_utf8(name).unwrap();
+
+        let request = format!(
+            "GET / HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n",
+            hostname
+        );
+        stream.write_all(request.as_bytes()).unwrap();
+
+        let mut response = String::new();
+        let _ = stream.read_to_string(&mut response);
+        (name, response)
+    }
+
+    /// Tests that requests are served over TLS, with the certificate chosen
+    /// by the host the client asks for.
+    #[test]
+    fn serves_by_hostname()
+    {
+        let dir = TempDir::new("tls-hostnames");
+        let (chain, key) = self_signed(&dir, "localhost");
+        let (example_chain, example_key) = self_signed(&dir, "example.test");
+
+        let server = start(
+            TlsConfig::new(chain, key)
+                .host("Example.Test", example_chain, example_key)
+        );
+        let addr = server.addr();
+
+        let (name, response) = get(addr, "localhost");
+        assert_eq!("localhost", name);
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("Hello, world!"));
+
+        let (name, response) = get(addr, "example.test");
+        assert_eq!("example.test", name);
+        assert!(response.contains("Hello, world!"));
+
+        // unknown hosts get the default certificate
+        let (name, _) = get(addr, "unknown.test");
+        assert_eq!("localhost", name);
+
+        server.shutdown();
+        server.wait().unwrap();
+    }
+
+    /// Tests that plain HTTP is redirected to the port HTTPS is served on.
+    #[test]
+    fn redirects_to_tls_port()
+    {
+        let dir = TempDir::new("tls-redirect");
+        let (chain, key) = self_signed(&dir, "localhost");
+
+        // the redirect listener's address isn't reported, so find a port
+        // which is free first
+        let redirect = ::std::net::TcpListener::bind("127.0.0.1:0").unwrap()
+            .local_addr()
+            .unwrap();
+
+        let server = ServerBuilder::new()
+            .bind("/", Hello)
+            .addr("127.0.0.1:0".parse().unwrap())
+            .proto(Protocol::Https(TlsConfig::new(chain, key)))
+            .redirect_http(redirect)
+            .spawn()
+            .unwrap();
+
+        let mut stream = TcpStream::connect(redirect).unwrap();
+        stream.write_all(
+            b"GET /a?b HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n"
+        ).unwrap();
+        let mut response = String::new();
+        let _ = stream.read_to_string(&mut response);
+
+        assert!(response.starts_with("HTTP/1.1 301 Moved Permanently\r\n"));
+        assert!(response.contains(&format!(
+            "Location: https://localhost:{}/a?b\r\n",
+            server.addr().port()
+        )));
+
+        server.shutdown();
+        server.wait().unwrap();
+    }
+
+    /// Tests that missing or mismatched certificates are reported.
+    #[test]
+    fn invalid_certificates()
+    {
+        let dir = TempDir::new("tls-invalid");
+        let (chain, _) = self_signed(&dir, "first.test");
+        let (_, key) = self_signed(&dir, "second.test");
+
+        assert!(TlsConfig::new(&chain, "missing.key").acceptor().is_err());
+        assert!(TlsConfig::new(&chain, &key).acceptor().is_err());
+        assert!(
+            TlsConfig::new(&chain, &chain)
+                .host("second.test", "missing.pem", &key)
+                .acceptor()
+                .is_err()
+        );
+    }
+}
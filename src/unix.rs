@@ -0,0 +1,236 @@
+use std::fs::{self, DirBuilder, Permissions};
+use std::io;
+use std::os::unix::fs::{chown, DirBuilderExt, FileTypeExt, PermissionsExt};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use tokio_core::reactor::Handle;
+use tokio_uds::UnixListener;
+
+/// A Unix domain socket for the server to listen on, such as one a local
+/// reverse proxy forwards requests to.
+///
+/// ```rust,no_run
+/// use mwf::{ServerBuilder, UnixSocket};
+///
+/// ServerBuilder::new()
+///     .unix(UnixSocket::new("/run/mwf/site.sock").mode(0o660).group(33))
+///     .start();
+/// ```
+#[derive(Clone, Debug)]
+pub struct UnixSocket
+{
+    path: PathBuf,
+    mode: Option<u32>,
+    owner: Option<u32>,
+    group: Option<u32>,
+}
+
+//
+// Implementation
+//
+
+impl UnixSocket
+{
+    /// Creates a socket at the given `path`. If a socket file is left over
+    /// there from a previous run, it's replaced, but a socket which another
+    /// server is still listening on is left alone.
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self
+    {
+        UnixSocket {
+            path: path.into(),
+            mode: None,
+            owner: None,
+            group: None,
+        }
+    }
+
+    /// Sets the permissions of the socket file, such as `0o660`. Clients
+    /// need write permission on the socket to connect to it.
+    pub fn mode(mut self, mode: u32) -> Self
+    {
+        self.mode = Some(mode);
+        self
+    }
+
+    /// Changes the user id which owns the socket file.
+    pub fn owner(mut self, uid: u32) -> Self
+    {
+        self.owner = Some(uid);
+        self
+    }
+
+    /// Changes the group id which owns the socket file.
+    pub fn group(mut self, gid: u32) -> Self
+    {
+        self.group = Some(gid);
+        self
+    }
+
+    /// The path of the socket file.
+    pub fn path(&self) -> &Path
+    {
+        &self.path
+    }
+
+    /// Creates the socket file and starts listening on it.
+    ///
+    /// The socket is created in a directory only we can access and moved
+    /// into place once its mode and owner are set, so that nobody can
+    /// connect to it in the meantime.
+    pub(crate) fn bind(&self, handle: &Handle) -> io::Result<UnixListener>
+    {
+        self.remove_stale()?;
+
+        let name = self.path.file_name().ok_or_else(|| io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "The socket path has no file name"
+        ))?;
+        let mut private = self.path.with_file_name(".mwf-");
+        private.as_mut_os_string().push(name);
+        private.as_mut_os_string().push(format!(".{}", process::id()));
+
+        DirBuilder::new().mode(0o700).create(&private)?;
+        let result = self.bind_in(&private.join(name), handle);
+        let _ = fs::remove_dir_all(&private);
+        result
+    }
+
+    /// Removes the socket file left over from a previous run, if there is
+    /// one. Anything else at the path, including a socket which is still
+    /// being listened on, is an error.
+    fn remove_stale(&self) -> io::Result<()>
+    {
+        let metadata = match fs::symlink_metadata(&self.path) {
+            Ok(metadata) => metadata,
+            Err(ref error) if error.kind() == io::ErrorKind::NotFound => {
+                return Ok(());
+            },
+            Err(error) => return Err(error),
+        };
+
+        // only sockets are removed, so a typo can't delete anything else
+        if !metadata.file_type().is_socket() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                "Something other than a socket is in the way"
+            ));
+        }
+
+        if UnixStream::connect(&self.path).is_ok() {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                "Another server is listening on the socket"
+            ));
+        }
+
+        fs::remove_file(&self.path)
+    }
+
+    /// Binds the socket at the `temporary` path, sets it up, and moves it to
+    /// where it belongs.
+    fn bind_in(&self, temporary: &Path, handle: &Handle)
+        -> io::Result<UnixListener>
+    {
+        let listener = UnixListener::bind(temporary, handle)?;
+
+        if let Some(mode) = self.mode {
+            fs::set_permissions(temporary, Permissions::from_mode(mode))?;
+        }
+
+        if self.owner.is_some() || self.group.is_some() {
+            chown(temporary, self.owner, self.group)?;
+        }
+
+        fs::rename(temporary, &self.path)?;
+        Ok(listener)
+    }
+}
+
+impl<'a> From<&'a str> for UnixSocket
+{
+    fn from(path: &'a str) -> Self
+    {
+        UnixSocket::new(path)
+    }
+}
+
+impl<'a> From<&'a Path> for UnixSocket
+{
+    fn from(path: &'a Path) -> Self
+    {
+        UnixSocket::new(path)
+    }
+}
+
+impl From<PathBuf> for UnixSocket
+{
+    fn from(path: PathBuf) -> Self
+    {
+        UnixSocket::new(path)
+    }
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+    use std::os::unix::fs::MetadataExt;
+    use tokio_core::reactor::Core;
+
+    use testing::TempDir;
+
+    /// Tests that the mode and ownership of the socket file are set.
+    #[test]
+    fn permissions()
+    {
+        let core = Core::new().unwrap();
+        let dir = TempDir::new("unix-permissions");
+        let path = dir.join("site.sock");
+
+        // we can only portably give the file to the ids it already has
+        let created = UnixSocket::new(&path).bind(&core.handle()).unwrap();
+        let ids = fs::metadata(&path).unwrap();
+        drop(created);
+
+        let socket = UnixSocket::new(&path)
+            .mode(0o600)
+            .owner(ids.uid())
+            .group(ids.gid());
+        let _listener = socket.bind(&core.handle()).unwrap();
+
+        let metadata = fs::metadata(&path).unwrap();
+        assert!(metadata.file_type().is_socket());
+        assert_eq!(0o600, metadata.mode() & 0o777);
+        assert_eq!(ids.uid(), metadata.uid());
+        assert_eq!(ids.gid(), metadata.gid());
+
+        // the directory it was set up in is gone
+        assert_eq!(1, fs::read_dir(dir.path()).unwrap().count());
+    }
+
+    /// Tests that stale sockets are replaced, but other files and sockets
+    /// which are still listened on are left alone.
+    #[test]
+    fn replaces_stale_sockets()
+    {
+        let core = Core::new().unwrap();
+        let dir = TempDir::new("unix-stale");
+        let path = dir.join("site.sock");
+
+        let socket = UnixSocket::from(path.as_path());
+        drop(socket.bind(&core.handle()).unwrap());
+        let listener = socket.bind(&core.handle()).unwrap();
+
+        let error = socket.bind(&core.handle()).unwrap_err();
+        assert_eq!(io::ErrorKind::AddrInUse, error.kind());
+        assert!(UnixStream::connect(&path).is_ok());
+        drop(listener);
+
+        fs::remove_file(&path).unwrap();
+        fs::write(&path, "not a socket").unwrap();
+        assert!(socket.bind(&core.handle()).is_err());
+        assert_eq!("not a socket", fs::read_to_string(&path).unwrap());
+    }
+}
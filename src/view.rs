@@ -1,14 +1,29 @@
+use std::collections::BTreeMap;
 use std::path::PathBuf;
 use std::fs::File;
-use std::io::Read;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::str;
 
+use hyper::header::{Header, Headers};
 use mime_guess;
 use mime_guess::Mime;
 
 use error::Result;
-use decorator::Decorator;
+use decorator::{Decorator, TryDecorator, RequestContext};
+use stream::BodyStream;
+
+/// The largest file which is read into a view's content so that it can be
+/// decorated, rather than streamed.
+const MAX_DECORATED_FILE: u64 = 16 * 1024 * 1024;
+
+/// How much of a file is read to tell if it's text.
+const SNIFF_LENGTH: u64 = 8 * 1024;
 
 /// A view on the server.
+///
+/// Fields are added to views as the server learns to do more with them, so
+/// create them with [View::new] or the other constructors rather than with a
+/// struct literal, which would stop compiling when that happens.
 pub struct View
 {
     /// The content to display
@@ -16,6 +31,24 @@ pub struct View
 
     /// The contents mime type
     pub mime: Mime,
+
+    /// The file this view was read from, if any. File-backed views advertise
+    /// `Accept-Ranges`, and clients may request only parts of them.
+    pub file: Option<PathBuf>,
+
+    /// If set, the response body is streamed from here instead of being
+    /// sent from `content` (which is ignored).
+    pub stream: Option<BodyStream>,
+
+    /// Information about the view, such as its `title` or `description`,
+    /// which decorators can read and add to.
+    pub meta: BTreeMap<String, String>,
+
+    /// Headers which are added to the response, replacing any the server
+    /// would have sent with the same names. Those which frame the body
+    /// (`Content-Length`, `Content-Range` and `Transfer-Encoding`) are
+    /// ignored, as is `Content-Type` when only parts of the body are sent.
+    pub headers: Headers,
 }
 
 //
@@ -24,6 +57,15 @@ pub struct View
 
 impl View
 {
+    /// Constructs a view from the `content` with the given `mime` type.
+    pub fn new<T: Into<String>>(content: T, mime: Mime) -> Self
+    {
+        View {
+            mime,
+            ..View::raw(content)
+        }
+    }
+
     /// Constructs a view from the raw text in `content`.
     /// This will have the `text/plain` mime type.
     pub fn raw<T: Into<String>>(content: T) -> Self
@@ -31,35 +73,218 @@ impl View
         View {
             content: content.into(),
             mime: "text/plain".parse().unwrap(),
+            file: None,
+            stream: None,
+            meta: BTreeMap::new(),
+            headers: Headers::new(),
+        }
+    }
+
+    /// Constructs a view whose response body will be streamed from the given
+    /// `body`, instead of being buffered in memory first.
+    /// This will have the `application/octet-stream` mime type.
+    ///
+    /// Files, readers and iterators are read on a small pool of threads
+    /// shared by every streamed body, each holding a thread until it's sent,
+    /// as described on [BodyStream].
+    pub fn stream(body: BodyStream) -> Self
+    {
+        View {
+            content: String::new(),
+            mime: "application/octet-stream".parse().unwrap(),
+            file: None,
+            stream: Some(body),
+            meta: BTreeMap::new(),
+            headers: Headers::new(),
         }
     }
 
     /// Constructs a view from the text in the given `file`.
-    /// This will have the `text/plain` mime type.
+    /// The mime type is guessed from the file's extension, or is
+    /// `text/plain` if it can't be.
+    ///
+    /// Files which aren't valid UTF-8, such as images, can't be held as
+    /// text, so they're streamed from disk as with [View::stream_file]. So
+    /// are text files larger than 16 MiB. Only the start of a file is read
+    /// to decide, so large files are never read whole.
     pub fn file<T: Into<PathBuf>>(file: T) -> Result<Self>
     {
         let path: PathBuf = file.into();
         let mut file = File::open(&path)?;
-        let mut content = String::new();
-        file.read_to_string(&mut content)?;
 
-        let mime: Mime = mime_guess::guess_mime_type_opt(path)
+        let length = file.metadata()?.len();
+        if length > MAX_DECORATED_FILE || !is_text(&mut file)? {
+            return View::stream_file(path);
+        }
+
+        let mut bytes = Vec::with_capacity(length as usize);
+        file.read_to_end(&mut bytes)?;
+
+        let content = match String::from_utf8(bytes) {
+            Ok(content) => content,
+            Err(_) => return View::stream_file(path),
+        };
+
+        let mime: Mime = mime_guess::guess_mime_type_opt(&path)
             .unwrap_or_else(|| "text/plain".parse().unwrap());
 
         Ok(View {
             content,
             mime,
+            file: Some(path),
+            stream: None,
+            meta: BTreeMap::new(),
+            headers: Headers::new(),
+        })
+    }
+
+    /// Constructs a view which streams the contents of the given `file` from
+    /// disk as they're sent, rather than reading it whole. Unlike
+    /// [View::file], this works for binary files too.
+    /// The mime type is guessed from the file's extension.
+    pub fn stream_file<T: Into<PathBuf>>(file: T) -> Result<Self>
+    {
+        let path: PathBuf = file.into();
+        let file = File::open(&path)?;
+
+        let mime: Mime = mime_guess::guess_mime_type_opt(&path)
+            .unwrap_or_else(|| "application/octet-stream".parse().unwrap());
+
+        Ok(View {
+            content: String::new(),
+            mime,
+            file: Some(path),
+            stream: Some(BodyStream::file(file)),
+            meta: BTreeMap::new(),
+            headers: Headers::new(),
         })
     }
 
+    /// The value of the metadata `key`, if it's set.
+    pub fn meta(&self, key: &str) -> Option<&str>
+    {
+        self.meta.get(key).map(String::as_str)
+    }
+
+    /// Sets the metadata `key` to the `value`.
+    pub fn with_meta<K, V>(mut self, key: K, value: V) -> Self
+        where K: Into<String>,
+              V: Into<String>
+    {
+        self.meta.insert(key.into(), value.into());
+        self
+    }
+
+    /// Fills in the block `name` of the [Layout] this view is rendered into
+    /// with the given `html`. This is the `block.<name>` metadata.
+    pub fn with_block<N, V>(self, name: N, html: V) -> Self
+        where N: AsRef<str>,
+              V: Into<String>
+    {
+        self.with_meta(format!("block.{}", name.as_ref()), html)
+    }
+
+    /// The title of the view, if it has one. This is the `title` metadata.
+    pub fn title(&self) -> Option<&str>
+    {
+        self.meta("title")
+    }
+
+    /// Adds the `header` to the response.
+    pub fn with_header<H: Header>(mut self, header: H) -> Self
+    {
+        self.headers.set(header);
+        self
+    }
+
     /// Applies the given `decorator` to this view, consuming it and
     /// creating another one.
-    pub fn apply<T: Decorator>(self, decorator: &T) -> Self
+    ///
+    /// Decorators only see the view's `content`, so a streamed text file
+    /// (of up to 16 MiB) is read into it first. Any other
+    /// stream is only kept if the decorator leaves the content empty and the
+    /// mime type alone; otherwise the stream is dropped in favour of what
+    /// the decorator made, so it's never sent under the decorator's type.
+    pub fn apply<T: Decorator + ?Sized>(self, decorator: &T) -> Self
+    {
+        let (view, stream) = self.unstream();
+        let mime = view.mime.clone();
+        decorator.decorate(view).restream(stream, &mime)
+    }
+
+    /// Applies the given fallible `decorator` to this view, for the request
+    /// described by the `context`. Chained with `?`, this stops at the first
+    /// decorator which fails. Streams are treated as they are by
+    /// [View::apply].
+    pub fn try_apply<T: TryDecorator + ?Sized>(
+        self,
+        decorator: &T,
+        context: &RequestContext
+    ) -> Result<Self>
+    {
+        let (view, stream) = self.unstream();
+        let mime = view.mime.clone();
+        Ok(decorator.try_decorate(view, context)?.restream(stream, &mime))
+    }
+
+    /// Takes the stream out of this view before it's decorated, reading it
+    /// into the content instead if it's a small enough text file.
+    fn unstream(mut self) -> (Self, Option<BodyStream>)
+    {
+        let mut file = match self.stream.take().map(BodyStream::into_file) {
+            None => return (self, None),
+            Some(Err(stream)) => return (self, Some(stream)),
+            Some(Ok(file)) => file,
+        };
+
+        let length = file.metadata().map(|it| it.len()).unwrap_or(u64::MAX);
+        let mut bytes = Vec::new();
+        if length <= MAX_DECORATED_FILE && file.read_to_end(&mut bytes).is_ok()
+        {
+            if let Ok(content) = String::from_utf8(bytes) {
+                self.content = content;
+                return (self, None);
+            }
+        }
+
+        match file.seek(SeekFrom::Start(0)) {
+            Ok(_) => (self, Some(BodyStream::file(file))),
+            Err(_) => (self, None),
+        }
+    }
+
+    /// Puts the `stream` taken out by [View::unstream] back, unless the
+    /// decorator replaced the content or changed the `mime` type, in which
+    /// case the decorated content is sent instead.
+    fn restream(mut self, stream: Option<BodyStream>, mime: &Mime) -> Self
     {
-        decorator.decorate(self)
+        if let Some(stream) = stream {
+            if self.content.is_empty() && self.mime == *mime {
+                self.stream = Some(stream);
+            }
+            else {
+                self.file = None;
+            }
+        }
+        self
     }
 }
 
+/// Checks if the start of the `file` looks like UTF-8 text, leaving it at
+/// the start again.
+fn is_text(file: &mut File) -> io::Result<bool>
+{
+    let mut prefix = Vec::new();
+    file.by_ref().take(SNIFF_LENGTH).read_to_end(&mut prefix)?;
+    file.seek(SeekFrom::Start(0))?;
+
+    // the prefix may end part of the way through a character
+    Ok(match str::from_utf8(&prefix) {
+        Ok(_) => true,
+        Err(error) => error.error_len().is_none(),
+    })
+}
+
 #[cfg(test)]
 mod test
 {
@@ -76,6 +301,26 @@ mod test
         assert_eq!("plain", view.mime.subtype());
     }
 
+    /// Tests that metadata and headers survive being decorated.
+    #[test]
+    fn metadata()
+    {
+        use decorator::Surround;
+        use hyper::header::CacheControl;
+
+        let view = View::raw("foo")
+            .with_meta("title", "Foo")
+            .with_meta("author", "Sam")
+            .with_header(CacheControl(vec![]))
+            .apply(&Surround::new("<", ">"));
+
+        assert_eq!("<foo>", view.content);
+        assert_eq!(Some("Foo"), view.title());
+        assert_eq!(Some("Sam"), view.meta("author"));
+        assert_eq!(None, view.meta("description"));
+        assert!(view.headers.has::<CacheControl>());
+    }
+
     /// Tests the [View::raw] API's ability to take a `String`.
     #[test]
     fn from_string()
@@ -97,6 +342,45 @@ mod test
             .expect("Could not find or open src/view.rs for read");
 
         assert_eq!(contents, view.content);
+        assert_eq!(Some(PathBuf::from("src/view.rs")), view.file);
+    }
+
+    /// Tests that text files too large to decorate are streamed from disk
+    /// instead.
+    #[test]
+    fn from_large_file()
+    {
+        use testing::TempDir;
+
+        let dir = TempDir::new("view-large");
+        let path = dir.join("large.txt");
+        let content = vec![b'a'; MAX_DECORATED_FILE as usize + 1];
+        ::std::fs::write(&path, content).unwrap();
+
+        let view = View::file(&path).expect("Could not read a large file");
+
+        assert!(view.stream.is_some());
+        assert!(view.content.is_empty());
+        assert_eq!("plain", view.mime.subtype());
+        assert_eq!(Some(path), view.file);
+    }
+
+    /// Tests that a character split by the end of the sniffed prefix isn't
+    /// mistaken for binary.
+    #[test]
+    fn from_file_split_character()
+    {
+        use testing::TempDir;
+
+        let dir = TempDir::new("view-split");
+        let path = dir.join("split.txt");
+        let mut content = "a".repeat(SNIFF_LENGTH as usize - 1);
+        content.push('é');
+        ::std::fs::write(&path, &content).unwrap();
+
+        let view = View::file(&path).unwrap();
+        assert!(view.stream.is_none());
+        assert_eq!(content, view.content);
     }
 
     /// Tests the [View::file] API's handling of IO errors while reading a
@@ -118,6 +402,93 @@ mod test
         assert_eq!("x-rust", view.mime.subtype());
     }
 
-    // apply has been tested in the decorators files
-    // no need to test it here too
+    /// Tests that binary files, which aren't text, are streamed from disk
+    /// instead.
+    #[test]
+    fn from_binary_file()
+    {
+        use testing::TempDir;
+
+        let dir = TempDir::new("view-binary");
+        let path = dir.join("image.png");
+        ::std::fs::write(&path, b"\x89PNG\r\n\x1a\n\xff\xfe").unwrap();
+
+        let view = View::file(&path).expect("Could not read a binary file");
+
+        assert!(view.stream.is_some());
+        assert!(view.content.is_empty());
+        assert_eq!("png", view.mime.subtype());
+        assert_eq!(Some(path), view.file);
+    }
+
+    /// Tests the [View::new] API sets both the content and mime type.
+    #[test]
+    fn from_content_and_mime()
+    {
+        let view = View::new("<p>hi</p>", "text/html".parse().unwrap());
+        assert_eq!("<p>hi</p>", view.content);
+        assert_eq!("html", view.mime.subtype());
+        assert!(view.file.is_none() && view.stream.is_none());
+    }
+
+    /// Tests the [View::stream_file] API's handling of missing files, and
+    /// detection of mime types.
+    #[test]
+    fn stream_file()
+    {
+        assert!(View::stream_file("src/rs.view").is_err());
+
+        let view = View::stream_file("src/view.rs")
+            .expect("Could not find or open src/view.rs for read");
+
+        assert!(view.stream.is_some());
+        assert!(view.content.is_empty());
+        assert_eq!("x-rust", view.mime.subtype());
+        assert_eq!(Some(PathBuf::from("src/view.rs")), view.file);
+    }
+
+    /// Tests that streamed text files are read into the content so they can
+    /// be decorated, and aren't streamed anymore.
+    #[test]
+    fn apply_to_streamed_file()
+    {
+        use decorator::Surround;
+
+        let view = View::stream_file("src/view.rs")
+            .unwrap()
+            .apply(&Surround::new("<pre>", "</pre>"));
+
+        assert!(view.stream.is_none());
+        assert_eq!(
+            format!("<pre>{}</pre>", include_str!("view.rs")),
+            view.content
+        );
+    }
+
+    /// Tests that other streams are dropped when a decorator replaces the
+    /// content or the mime type, and kept when it doesn't.
+    #[test]
+    fn apply_to_stream()
+    {
+        use decorator::Surround;
+        use hyper::header::CacheControl;
+
+        let stream = || View::stream(BodyStream::chunks(vec!["<script>"]));
+
+        let view = stream().apply(&Surround::new("<pre>", "</pre>"));
+        assert!(view.stream.is_none());
+        assert_eq!("<pre></pre>", view.content);
+
+        let view = stream().apply(&|mut view: View| {
+            view.mime = "text/html".parse().unwrap();
+            view
+        });
+        assert!(view.stream.is_none());
+
+        let view = stream().apply(&|view: View| {
+            view.with_header(CacheControl(vec![]))
+        });
+        assert!(view.stream.is_some());
+        assert!(view.headers.has::<CacheControl>());
+    }
 }
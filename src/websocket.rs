@@ -0,0 +1,982 @@
+use std::io::{self, Read, Write};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+
+use base64;
+use bytes::{BufMut, Bytes, BytesMut};
+use futures::{Future, Poll, Stream};
+use futures::stream::Wait;
+use futures::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use hyper::server::{Request, Response};
+use hyper::StatusCode;
+use hyper::header::{Connection, ConnectionOption, Upgrade};
+use hyper::header::{Protocol, ProtocolName};
+use sha1::Sha1;
+use tokio_codec::{Decoder, Encoder};
+use tokio_core::reactor::Handle;
+use tokio_io::{AsyncRead, AsyncWrite};
+
+use error::Result;
+use resolution::RouteMap;
+
+/// The GUID which is appended to the client's key during the handshake, as
+/// specified by RFC 6455.
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// The largest frame payload which will be accepted from a client.
+const MAX_PAYLOAD: u64 = 16 * 1024 * 1024;
+
+/// The handler for a WebSocket connection. This is only called once the
+/// server has accepted the upgrade of a request on a route it was bound to.
+///
+/// Each connection is handled on its own thread, so it's fine to block while
+/// waiting for messages.
+///
+/// ```rust,no_run
+/// use mwf::{ServerBuilder, RouteMap, WebSocketHandler, WebSocket, Message};
+///
+/// /// Sends every text message back to the client
+/// struct Echo;
+/// impl WebSocketHandler for Echo
+/// {
+///     fn connect(&self, mut socket: WebSocket, _route_map: RouteMap)
+///     {
+///         while let Some(message) = socket.recv() {
+///             if let Message::Text(text) = message {
+///                 socket.send(Message::Text(text)).unwrap();
+///             }
+///         }
+///     }
+/// }
+///
+/// ServerBuilder::new()
+///     .websocket("/echo", Echo)
+///     .start();
+/// ```
+pub trait WebSocketHandler
+    where Self: Send + Sync
+{
+    /// Handles the connection until it's closed. If the `socket` hasn't been
+    /// closed once this returns, it will be closed normally.
+    fn connect(&self, socket: WebSocket, route_map: RouteMap);
+}
+
+/// A message sent over a WebSocket connection.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Message
+{
+    /// A UTF-8 text message.
+    Text(String),
+
+    /// A binary message.
+    Binary(Vec<u8>),
+
+    /// A ping. The server replies to pings from the client on its own, so
+    /// these are only informational.
+    Ping(Vec<u8>),
+
+    /// A reply to a ping.
+    Pong(Vec<u8>),
+
+    /// The connection is being closed, with an optional status code and
+    /// reason.
+    Close(Option<(u16, String)>),
+}
+
+/// An open WebSocket connection, as given to a [WebSocketHandler].
+pub struct WebSocket
+{
+    incoming: Wait<UnboundedReceiver<Message>>,
+    sender: WebSocketSender,
+}
+
+/// Sends messages over a [WebSocket]. Senders may be cloned and moved to
+/// other threads, so that messages can be pushed to the client while the
+/// handler is waiting for messages.
+#[derive(Clone)]
+pub struct WebSocketSender
+{
+    outgoing: UnboundedSender<Frame>,
+    closed: Arc<AtomicBool>,
+}
+
+/// An accepted WebSocket upgrade, which is waiting for its HTTP connection
+/// to finish so it can take over.
+pub(crate) struct PendingUpgrade
+{
+    pub handler: Arc<WebSocketHandler>,
+    pub route_map: RouteMap,
+}
+
+/// A single WebSocket frame.
+#[derive(Clone, Debug, PartialEq)]
+struct Frame
+{
+    fin: bool,
+    opcode: u8,
+    payload: Vec<u8>,
+}
+
+/// Encodes frames sent by the server and decodes those sent by the client.
+struct Codec;
+
+/// Puts fragmented messages back together, and checks that the frames from
+/// the client make sense.
+struct Assembler
+{
+    /// The opcode and payload of the fragmented message, if one is in the
+    /// middle of being received.
+    partial: Option<(u8, Vec<u8>)>,
+}
+
+/// An IO object whose first reads come from bytes which had already been
+/// read from it.
+struct Rewind<T>
+{
+    prefix: Bytes,
+    inner: T,
+}
+
+//
+// Frame opcodes
+//
+
+const CONTINUATION: u8 = 0x0;
+const TEXT: u8 = 0x1;
+const BINARY: u8 = 0x2;
+const CLOSE: u8 = 0x8;
+const PING: u8 = 0x9;
+const PONG: u8 = 0xA;
+
+//
+// Close codes
+//
+
+const NORMAL: u16 = 1000;
+const PROTOCOL_ERROR: u16 = 1002;
+const INVALID_DATA: u16 = 1007;
+
+//
+// Handshake
+//
+
+/// Checks if the `request` is asking to be upgraded to a WebSocket.
+pub(crate) fn is_upgrade(request: &Request) -> bool
+{
+    header_contains(request, "Upgrade", "websocket")
+}
+
+/// Computes the `Sec-WebSocket-Accept` value for the client's `key`.
+fn accept_key(key: &str) -> String
+{
+    let digest = Sha1::from(format!("{}{}", key, GUID)).digest().bytes();
+    base64::encode(&digest)
+}
+
+/// Checks if the raw `header` on the `request` contains the `token` in its
+/// comma-separated list of values, ignoring case.
+fn header_contains(request: &Request, header: &str, token: &str) -> bool
+{
+    let raw = match request.headers().get_raw(header) {
+        None => return false,
+        Some(x) => x,
+    };
+
+    raw.iter()
+        .filter_map(|it| ::std::str::from_utf8(it).ok())
+        .flat_map(|it| it.split(","))
+        .any(|it| it.trim().eq_ignore_ascii_case(token))
+}
+
+/// Performs the server's half of the opening handshake for the upgrade
+/// `request`. This will be a `101 Switching Protocols` response if the
+/// request was valid.
+pub(crate) fn handshake(request: &Request) -> Response
+{
+    let mut response = Response::new();
+
+    let key = request.headers()
+        .get_raw("Sec-WebSocket-Key")
+        .and_then(|it| it.one())
+        .and_then(|it| ::std::str::from_utf8(it).ok());
+
+    let version = request.headers()
+        .get_raw("Sec-WebSocket-Version")
+        .and_then(|it| it.one());
+
+    if version != Some(b"13") {
+        response.set_status(StatusCode::UpgradeRequired);
+        response.headers_mut().set_raw("Sec-WebSocket-Version", "13");
+        return response;
+    }
+
+    let key = match key {
+        Some(key) if header_contains(request, "Connection", "upgrade") => key,
+        _ => {
+            response.set_status(StatusCode::BadRequest);
+            return response;
+        }
+    };
+
+    response.set_status(StatusCode::SwitchingProtocols);
+    response.headers_mut().set(Upgrade(vec![
+        Protocol::new(ProtocolName::WebSocket, None),
+    ]));
+    response.headers_mut().set(Connection(vec![
+        ConnectionOption::ConnectionHeader("upgrade".parse().unwrap()),
+    ]));
+    response.headers_mut().set_raw(
+        "Sec-WebSocket-Accept",
+        accept_key(key.trim())
+    );
+    response
+}
+
+/// Takes over the `io` of a connection whose upgrade was accepted, where
+/// `read` is anything which was already read from the connection. The
+/// frames are read and written on the reactor behind the `handle`, while the
+/// upgrade's handler is run on its own thread.
+pub(crate) fn serve<I: 'static>(
+    io: I,
+    read: Bytes,
+    upgrade: PendingUpgrade,
+    handle: &Handle
+)
+    where I: AsyncRead + AsyncWrite
+{
+    let io = Rewind {
+        prefix: read,
+        inner: io,
+    };
+    let (sink, stream) = Codec.framed(io).split();
+
+    let (outgoing, outgoing_receiver) = mpsc::unbounded::<Frame>();
+    let (incoming_sender, incoming) = mpsc::unbounded::<Message>();
+    let sender = WebSocketSender {
+        outgoing,
+        closed: Arc::new(AtomicBool::new(false)),
+    };
+
+    // everything sent by the handler (or the reader) is written out until
+    // every sender has been dropped
+    let writer = outgoing_receiver
+        .map_err(|_| io::Error::other("unreachable"))
+        .forward(sink)
+        .then(|_| Ok(()));
+    handle.spawn(writer);
+
+    // everything read is passed along to the handler, except that pings are
+    // answered and the connection stops being read once it's closed
+    let reader_sender = sender.clone();
+    let mut assembler = Assembler::new();
+    let reader = stream
+        .for_each(move |frame| {
+            let message = match assembler.push(frame) {
+                Ok(None) => return Ok(()),
+                Ok(Some(message)) => message,
+                Err(code) => {
+                    let _ = reader_sender.close(code, "");
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "protocol error"
+                    ));
+                },
+            };
+
+            let closing = match message {
+                Message::Ping(ref data) => {
+                    let _ = reader_sender.send(Message::Pong(data.clone()));
+                    false
+                },
+
+                Message::Close(ref reason) => {
+                    let code = reason.as_ref().map(|it| it.0).unwrap_or(NORMAL);
+                    let _ = reader_sender.close(code, "");
+                    true
+                },
+
+                _ => false,
+            };
+
+            // the handler might not care about messages anymore, which is
+            // fine
+            let _ = incoming_sender.unbounded_send(message);
+
+            if closing {
+                Err(io::Error::other("closed"))
+            }
+            else {
+                Ok(())
+            }
+        })
+        .then(|_| Ok(()));
+    handle.spawn(reader);
+
+    let socket = WebSocket {
+        incoming: incoming.wait(),
+        sender,
+    };
+
+    let PendingUpgrade { handler, route_map } = upgrade;
+    thread::spawn(move || handler.connect(socket, route_map));
+}
+
+//
+// Implementation
+//
+
+impl WebSocket
+{
+    /// Waits for the next message from the client. This returns `None` once
+    /// the connection has been closed.
+    pub fn recv(&mut self) -> Option<Message>
+    {
+        self.incoming.next().and_then(|it| it.ok())
+    }
+
+    /// Sends the `message` to the client.
+    pub fn send(&self, message: Message) -> Result<()>
+    {
+        self.sender.send(message)
+    }
+
+    /// Starts closing the connection with the given status `code` and
+    /// `reason`. The client's reply will still be received by [recv].
+    pub fn close<T: AsRef<str>>(&self, code: u16, reason: T) -> Result<()>
+    {
+        self.sender.close(code, reason)
+    }
+
+    /// Creates a new sender for this connection, which can be used from other
+    /// threads.
+    pub fn sender(&self) -> WebSocketSender
+    {
+        self.sender.clone()
+    }
+}
+
+impl Drop for WebSocket
+{
+    fn drop(&mut self)
+    {
+        let _ = self.sender.close(NORMAL, "");
+    }
+}
+
+impl WebSocketSender
+{
+    /// Sends the `message` to the client. This will fail if the connection
+    /// has already been closed.
+    pub fn send(&self, message: Message) -> Result<()>
+    {
+        let frame = match message {
+            Message::Close(reason) => {
+                return match reason {
+                    None => self.close(NORMAL, ""),
+                    Some((code, reason)) => self.close(code, reason),
+                };
+            },
+
+            Message::Text(text) => Frame::new(TEXT, text.into_bytes()),
+            Message::Binary(data) => Frame::new(BINARY, data),
+            Message::Ping(data) => Frame::new(PING, data),
+            Message::Pong(data) => Frame::new(PONG, data),
+        };
+
+        if self.closed.load(Ordering::SeqCst) {
+            return Err(closed());
+        }
+
+        self.outgoing.unbounded_send(frame).map_err(|_| closed())
+    }
+
+    /// Starts closing the connection with the given status `code` and
+    /// `reason`. Closing an already closed connection does nothing.
+    pub fn close<T: AsRef<str>>(&self, code: u16, reason: T) -> Result<()>
+    {
+        if self.closed.swap(true, Ordering::SeqCst) {
+            return Ok(());
+        }
+
+        let mut payload = Vec::new();
+        payload.push((code >> 8) as u8);
+        payload.push(code as u8);
+        payload.extend_from_slice(reason.as_ref().as_bytes());
+
+        self.outgoing.unbounded_send(Frame::new(CLOSE, payload))
+            .map_err(|_| closed())
+    }
+}
+
+/// The error returned when sending on a closed connection.
+fn closed() -> ::error::Error
+{
+    io::Error::new(io::ErrorKind::NotConnected, "WebSocket is closed").into()
+}
+
+impl Frame
+{
+    /// Creates a new final frame.
+    fn new(opcode: u8, payload: Vec<u8>) -> Self
+    {
+        Frame {
+            fin: true,
+            opcode,
+            payload,
+        }
+    }
+}
+
+impl Decoder for Codec
+{
+    type Item = Frame;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> io::Result<Option<Frame>>
+    {
+        if src.len() < 2 {
+            return Ok(None);
+        }
+
+        let first = src[0];
+        let second = src[1];
+
+        if first & 0x70 != 0 {
+            return Err(invalid("reserved bits are set"));
+        }
+
+        if second & 0x80 == 0 {
+            return Err(invalid("frames from the client must be masked"));
+        }
+
+        let (length, offset) = match second & 0x7F {
+            126 => {
+                if src.len() < 4 {
+                    return Ok(None);
+                }
+                (((src[2] as u64) << 8) | src[3] as u64, 4)
+            },
+
+            127 => {
+                if src.len() < 10 {
+                    return Ok(None);
+                }
+                let length = src[2..10].iter()
+                    .fold(0u64, |length, &byte| (length << 8) | byte as u64);
+                (length, 10)
+            },
+
+            length => (length as u64, 2),
+        };
+
+        if length > MAX_PAYLOAD {
+            return Err(invalid("frame is too large"));
+        }
+
+        let total = offset + 4 + length as usize;
+        if src.len() < total {
+            src.reserve(total - src.len());
+            return Ok(None);
+        }
+
+        let frame = src.split_to(total);
+        let mask = &frame[offset..offset + 4];
+        let payload = frame[offset + 4..].iter()
+            .enumerate()
+            .map(|(i, byte)| byte ^ mask[i % 4])
+            .collect();
+
+        Ok(Some(Frame {
+            fin: first & 0x80 != 0,
+            opcode: first & 0x0F,
+            payload,
+        }))
+    }
+}
+
+impl Encoder for Codec
+{
+    type Item = Frame;
+    type Error = io::Error;
+
+    fn encode(&mut self, frame: Frame, dst: &mut BytesMut) -> io::Result<()>
+    {
+        let length = frame.payload.len();
+        dst.reserve(length + 10);
+
+        let fin = if frame.fin { 0x80 } else { 0x00 };
+        dst.put_u8(fin | frame.opcode);
+
+        if length < 126 {
+            dst.put_u8(length as u8);
+        }
+        else if length <= 0xFFFF {
+            dst.put_u8(126);
+            dst.put_u16_be(length as u16);
+        }
+        else {
+            dst.put_u8(127);
+            dst.put_u64_be(length as u64);
+        }
+
+        dst.put_slice(&frame.payload);
+        Ok(())
+    }
+}
+
+/// Creates an error for invalid data from the client.
+fn invalid(reason: &str) -> io::Error
+{
+    io::Error::new(io::ErrorKind::InvalidData, reason.to_string())
+}
+
+impl Assembler
+{
+    fn new() -> Self
+    {
+        Assembler {
+            partial: None,
+        }
+    }
+
+    /// Adds the next `frame` from the client, returning the message if it's
+    /// complete. If the client broke the protocol, then the status code to
+    /// close the connection with is returned instead.
+    fn push(&mut self, frame: Frame) -> ::std::result::Result<Option<Message>, u16>
+    {
+        let Frame { fin, opcode, payload } = frame;
+
+        match opcode {
+            CLOSE | PING | PONG => {
+                if !fin || payload.len() > 125 {
+                    return Err(PROTOCOL_ERROR);
+                }
+
+                Ok(Some(match opcode {
+                    PING => Message::Ping(payload),
+                    PONG => Message::Pong(payload),
+                    _ => close_message(payload)?,
+                }))
+            },
+
+            TEXT | BINARY => {
+                if self.partial.is_some() {
+                    return Err(PROTOCOL_ERROR);
+                }
+
+                if !fin {
+                    self.partial = Some((opcode, payload));
+                    return Ok(None);
+                }
+
+                data_message(opcode, payload).map(Some)
+            },
+
+            CONTINUATION => {
+                let (opcode, mut data) = match self.partial.take() {
+                    None => return Err(PROTOCOL_ERROR),
+                    Some(x) => x,
+                };
+
+                data.extend_from_slice(&payload);
+                if data.len() as u64 > MAX_PAYLOAD {
+                    return Err(PROTOCOL_ERROR);
+                }
+
+                if !fin {
+                    self.partial = Some((opcode, data));
+                    return Ok(None);
+                }
+
+                data_message(opcode, data).map(Some)
+            },
+
+            _ => Err(PROTOCOL_ERROR),
+        }
+    }
+}
+
+/// Creates the text or binary message out of a complete `payload`.
+fn data_message(opcode: u8, payload: Vec<u8>)
+    -> ::std::result::Result<Message, u16>
+{
+    if opcode == BINARY {
+        return Ok(Message::Binary(payload));
+    }
+
+    String::from_utf8(payload)
+        .map(Message::Text)
+        .map_err(|_| INVALID_DATA)
+}
+
+/// Reads the status code and reason out of a close frame's `payload`. Codes
+/// which may not be sent over the wire are a protocol error.
+fn close_message(payload: Vec<u8>) -> ::std::result::Result<Message, u16>
+{
+    match payload.len() {
+        0 => Ok(Message::Close(None)),
+        1 => Err(PROTOCOL_ERROR),
+        _ => {
+            let code = ((payload[0] as u16) << 8) | payload[1] as u16;
+            if !valid_close_code(code) {
+                return Err(PROTOCOL_ERROR);
+            }
+
+            let reason = String::from_utf8(payload[2..].to_vec())
+                .map_err(|_| INVALID_DATA)?;
+
+            Ok(Message::Close(Some((code, reason))))
+        },
+    }
+}
+
+/// Checks if the close `code` may be sent in a close frame: those defined by
+/// RFC 6455 (except the ones reserved for reporting, such as 1005 and 1006),
+/// the later registered ones, and those set aside for libraries and
+/// applications.
+fn valid_close_code(code: u16) -> bool
+{
+    matches!(code, 1000..=1003 | 1007..=1014 | 3000..=4999)
+}
+
+impl<T: Read> Read for Rewind<T>
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize>
+    {
+        if self.prefix.is_empty() {
+            return self.inner.read(buf);
+        }
+
+        let count = ::std::cmp::min(buf.len(), self.prefix.len());
+        buf[..count].copy_from_slice(&self.prefix[..count]);
+        self.prefix.advance(count);
+        Ok(count)
+    }
+}
+
+impl<T: Write> Write for Rewind<T>
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize>
+    {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()>
+    {
+        self.inner.flush()
+    }
+}
+
+impl<T: AsyncRead> AsyncRead for Rewind<T> {}
+
+impl<T: AsyncWrite> AsyncWrite for Rewind<T>
+{
+    fn shutdown(&mut self) -> Poll<(), io::Error>
+    {
+        self.inner.shutdown()
+    }
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+    use hyper::Method;
+
+    /// Masks the `payload` of a frame with the given `opcode`, the way a
+    /// client would send it.
+    fn client_frame(fin: bool, opcode: u8, payload: &[u8]) -> BytesMut
+    {
+        let mask = [1u8, 2, 3, 4];
+        let mut bytes = BytesMut::with_capacity(payload.len() + 8);
+        bytes.put_u8(if fin { 0x80 } else { 0 } | opcode);
+
+        if payload.len() < 126 {
+            bytes.put_u8(0x80 | payload.len() as u8);
+        }
+        else {
+            bytes.put_u8(0x80 | 126);
+            bytes.put_u16_be(payload.len() as u16);
+        }
+
+        bytes.put_slice(&mask);
+        for (i, byte) in payload.iter().enumerate() {
+            bytes.put_u8(byte ^ mask[i % 4]);
+        }
+        bytes
+    }
+
+    /// Creates an upgrade request with the given `key` and `version`.
+    fn upgrade(key: &str, version: &str) -> Request
+    {
+        let mut request = Request::new(Method::Get, "/".parse().unwrap());
+        request.headers_mut().set_raw("Upgrade", "websocket");
+        request.headers_mut().set_raw("Connection", "keep-alive, Upgrade");
+        request.headers_mut().set_raw("Sec-WebSocket-Key", key.to_string());
+        request.headers_mut().set_raw(
+            "Sec-WebSocket-Version",
+            version.to_string()
+        );
+        request
+    }
+
+    /// Tests the accept key against the example in RFC 6455.
+    #[test]
+    fn accept_key_example()
+    {
+        assert_eq!(
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=",
+            accept_key("dGhlIHNhbXBsZSBub25jZQ==")
+        );
+    }
+
+    /// Tests that valid upgrade requests are accepted, and invalid ones are
+    /// rejected.
+    #[test]
+    fn handshakes()
+    {
+        let request = upgrade("dGhlIHNhbXBsZSBub25jZQ==", "13");
+        assert!(is_upgrade(&request));
+
+        let response = handshake(&request);
+        assert_eq!(StatusCode::SwitchingProtocols, response.status());
+        assert_eq!(
+            Some(&b"s3pPLMBiTxaQ9kYGzzhZRbK+xOo="[..]),
+            response.headers().get_raw("Sec-WebSocket-Accept")
+                .and_then(|it| it.one())
+        );
+
+        let response = handshake(&upgrade("dGhlIHNhbXBsZSBub25jZQ==", "8"));
+        assert_eq!(StatusCode::UpgradeRequired, response.status());
+
+        let mut request = upgrade("dGhlIHNhbXBsZSBub25jZQ==", "13");
+        request.headers_mut().remove_raw("Connection");
+        assert_eq!(StatusCode::BadRequest, handshake(&request).status());
+    }
+
+    /// Tests that masked frames are decoded, even when they arrive in pieces.
+    #[test]
+    fn decodes_frames()
+    {
+        let bytes = client_frame(true, TEXT, b"Hello");
+        let mut src = BytesMut::with_capacity(64);
+        src.put_slice(&bytes[..3]);
+        assert_eq!(None, Codec.decode(&mut src).unwrap());
+
+        src.put_slice(&bytes[3..]);
+        assert_eq!(
+            Some(Frame::new(TEXT, b"Hello".to_vec())),
+            Codec.decode(&mut src).unwrap()
+        );
+        assert!(src.is_empty());
+
+        let payload = vec![7u8; 300];
+        let mut src = client_frame(true, BINARY, &payload);
+        assert_eq!(
+            Some(Frame::new(BINARY, payload)),
+            Codec.decode(&mut src).unwrap()
+        );
+    }
+
+    /// Tests that unmasked frames from the client are rejected.
+    #[test]
+    fn rejects_unmasked_frames()
+    {
+        let mut src = BytesMut::from(&[0x81u8, 0x01, b'a'][..]);
+        assert!(Codec.decode(&mut src).is_err());
+    }
+
+    /// Tests that frames are encoded without a mask, with the right length
+    /// encoding.
+    #[test]
+    fn encodes_frames()
+    {
+        let mut dst = BytesMut::new();
+        Codec.encode(Frame::new(TEXT, b"Hi".to_vec()), &mut dst).unwrap();
+        assert_eq!(&[0x81, 0x02, b'H', b'i'], &dst[..]);
+
+        let mut dst = BytesMut::new();
+        Codec.encode(Frame::new(BINARY, vec![0; 200]), &mut dst).unwrap();
+        assert_eq!(&[0x82, 126, 0, 200], &dst[..4]);
+        assert_eq!(204, dst.len());
+    }
+
+    /// Tests that fragmented messages are put back together, with control
+    /// frames allowed in between the fragments.
+    #[test]
+    fn assembles_fragments()
+    {
+        let mut assembler = Assembler::new();
+        let frame = |fin, opcode, data: &str| Frame {
+            fin,
+            opcode,
+            payload: data.as_bytes().to_vec(),
+        };
+
+        assert_eq!(Ok(None), assembler.push(frame(false, TEXT, "foo")));
+        assert_eq!(
+            Ok(Some(Message::Ping(b"?".to_vec()))),
+            assembler.push(frame(true, PING, "?"))
+        );
+        assert_eq!(Ok(None), assembler.push(frame(false, CONTINUATION, "bar")));
+        assert_eq!(
+            Ok(Some(Message::Text("foobarbaz".into()))),
+            assembler.push(frame(true, CONTINUATION, "baz"))
+        );
+
+        // continuing without starting a message is an error
+        assert_eq!(
+            Err(PROTOCOL_ERROR),
+            assembler.push(frame(true, CONTINUATION, "qux"))
+        );
+    }
+
+    /// Tests that close frames are parsed, and invalid text is rejected.
+    #[test]
+    fn close_and_invalid_text()
+    {
+        let mut assembler = Assembler::new();
+
+        let mut payload = vec![0x03, 0xE8];
+        payload.extend_from_slice(b"bye");
+        assert_eq!(
+            Ok(Some(Message::Close(Some((1000, "bye".into()))))),
+            assembler.push(Frame::new(CLOSE, payload))
+        );
+
+        assert_eq!(
+            Err(INVALID_DATA),
+            assembler.push(Frame::new(TEXT, vec![0xFF, 0xFE]))
+        );
+    }
+
+    /// Tests that close codes which may not be sent are rejected, rather
+    /// than echoed back to the client.
+    #[test]
+    fn close_codes()
+    {
+        for &code in &[1000, 1003, 1007, 1011, 1014, 3000, 4999] {
+            let payload = vec![(code >> 8) as u8, code as u8];
+            assert_eq!(
+                Ok(Some(Message::Close(Some((code, String::new()))))),
+                Assembler::new().push(Frame::new(CLOSE, payload))
+            );
+        }
+
+        for &code in &[0, 999, 1004, 1005, 1006, 1015, 2000, 2999, 5000] {
+            let payload = vec![(code >> 8) as u8, code as u8];
+            assert_eq!(
+                Err(PROTOCOL_ERROR),
+                Assembler::new().push(Frame::new(CLOSE, payload)),
+                "close code {} was accepted",
+                code
+            );
+        }
+    }
+
+    /// Tests a whole WebSocket connection served by the server: the upgrade
+    /// handshake, a message echoed by the handler, and the closing
+    /// handshake.
+    #[test]
+    fn upgrade_end_to_end()
+    {
+        use std::io::{Read as _, Write as _};
+        use std::net::TcpStream;
+        use std::thread;
+        use futures::sync::oneshot;
+        use hyper::server::Http;
+        use tokio_core::net::TcpListener;
+        use tokio_core::reactor::Core;
+
+        use routing::{Router, RouterBuilder};
+        use server::{serve_connection, Server};
+        use shutdown::Drain;
+
+        struct Echo;
+        impl WebSocketHandler for Echo
+        {
+            fn connect(&self, mut socket: WebSocket, route_map: RouteMap)
+            {
+                while let Some(message) = socket.recv() {
+                    if let Message::Text(text) = message {
+                        let name = route_map.get(":name").unwrap();
+                        let reply = format!("{}: {}", name, text);
+                        socket.send(Message::Text(reply)).unwrap();
+                    }
+                }
+            }
+        }
+
+        let mut router = RouterBuilder::new();
+        router.websocket("/echo/:name", Echo);
+        let router: Arc<Router> = Arc::new(router.into());
+
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let listener = TcpListener::bind(
+            &"127.0.0.1:0".parse().unwrap(),
+            &handle
+        ).unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let (done, finished) = oneshot::channel();
+        let client = thread::spawn(move || {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            stream.write_all(
+                b"GET /echo/sam HTTP/1.1\r\n\
+                  Host: localhost\r\n\
+                  Upgrade: websocket\r\n\
+                  Connection: Upgrade\r\n\
+                  Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+                  Sec-WebSocket-Version: 13\r\n\r\n"
+            ).unwrap();
+
+            // the handshake response ends with an empty line
+            let mut response = Vec::new();
+            let mut byte = [0];
+            while !response.ends_with(b"\r\n\r\n") {
+                stream.read_exact(&mut byte).unwrap();
+                response.push(byte[0]);
+            }
+
+            stream.write_all(&client_frame(true, TEXT, b"hi")).unwrap();
+            let mut echo = [0; 9];
+            stream.read_exact(&mut echo).unwrap();
+
+            stream.write_all(&client_frame(true, CLOSE, &[0x03, 0xE8]))
+                .unwrap();
+            let mut close = [0; 4];
+            stream.read_exact(&mut close).unwrap();
+
+            let _ = done.send(());
+            (String::from_utf8(response).unwrap(), echo, close)
+        });
+
+        let http = Http::new();
+        let drain = Drain::new();
+        let connection = listener.incoming()
+            .into_future()
+            .map_err(|(error, _)| error)
+            .and_then(move |(accepted, _)| {
+                let (socket, _) = accepted.unwrap();
+                serve_connection(
+                    &http,
+                    socket,
+                    Server::new(router),
+                    handle,
+                    drain
+                ).map_err(|it| io::Error::other(it.to_string()))
+            });
+        core.run(connection).unwrap();
+        core.run(finished).unwrap();
+
+        let (response, echo, close) = client.join().unwrap();
+        assert!(response.starts_with("HTTP/1.1 101 Switching Protocols\r\n"));
+        assert!(response.contains(
+            "Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"
+        ));
+        assert_eq!(b"\x81\x07sam: hi", &echo);
+        assert_eq!([0x88, 0x02, 0x03, 0xE8], close);
+    }
+}